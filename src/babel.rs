@@ -0,0 +1,566 @@
+use crate::org::{Element, ElementKind, Headline, OrgDocument};
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HeaderArguments {
+    arguments: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceBlock {
+    pub name: Option<String>,
+    pub language: String,
+    pub arguments: HeaderArguments,
+    pub body: String,
+    // Title of the enclosing headline.
+    pub headline: Option<String>,
+    // Lines from `#+BEGIN_SRC` to `#+END_SRC`, end exclusive.
+    pub range: Range<usize>,
+}
+
+impl HeaderArguments {
+    // Parses `:key value :other value ...`. Values may contain spaces.
+    pub fn parse(text: &str) -> Self {
+        let mut arguments: Vec<(String, String)> = Vec::new();
+        for word in text.split_whitespace() {
+            if let Some(key) = word.strip_prefix(':') {
+                arguments.push((key.to_lowercase(), String::new()));
+            } else if let Some((_, value)) = arguments.last_mut() {
+                if !value.is_empty() {
+                    value.push(' ');
+                }
+                value.push_str(word);
+            }
+        }
+        Self { arguments }
+    }
+    // Adds the arguments of a `header-args` or `header-args:LANGUAGE`
+    // property, later ones taking precedence.
+    fn add(&mut self, property: &str, arguments: &str) {
+        let property = property.to_lowercase();
+        let (name, language) = property.split_once(':').unwrap_or((&property, ""));
+        if name != "header-args" {
+            return;
+        }
+        let prefix = if language.is_empty() {
+            String::new()
+        } else {
+            format!("{}:", language)
+        };
+        for (key, value) in Self::parse(arguments).arguments {
+            self.arguments.push((format!("{}{}", prefix, key), value));
+        }
+    }
+    // The arguments that apply to a block in `language`, followed by the
+    // block's own arguments.
+    fn for_block(&self, language: &str, own: Self) -> Self {
+        let prefix = format!("{}:", language);
+        let mut arguments: Vec<(String, String)> = self
+            .arguments
+            .iter()
+            .filter_map(|(key, value)| match key.split_once(':') {
+                Some(_) => key
+                    .strip_prefix(&prefix)
+                    .map(|key| (key.to_string(), value.clone())),
+                None => Some((key.clone(), value.clone())),
+            })
+            .collect();
+        arguments.extend(own.arguments);
+        Self { arguments }
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.arguments
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+    // `:var` may be given several times, each with one or more
+    // comma-separated assignments.
+    pub fn variables(&self) -> Vec<(String, String)> {
+        self.arguments
+            .iter()
+            .filter(|(key, _)| key == "var")
+            .flat_map(|(_, value)| value.split(','))
+            .filter_map(|assignment| {
+                let (name, value) = assignment.split_once('=')?;
+                Some((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect()
+    }
+    pub fn has_flag(&self, key: &str, flag: &str) -> bool {
+        self.get(key)
+            .is_some_and(|value| value.split_whitespace().any(|word| word == flag))
+    }
+}
+
+impl SourceBlock {
+    // All source blocks of the document, with header arguments inherited
+    // from `#+PROPERTY: header-args` keywords and `header-args` properties
+    // of enclosing headlines.
+    pub fn all(document: &OrgDocument) -> Vec<Self> {
+        let mut defaults = HeaderArguments::default();
+        for (key, value) in &document.keywords {
+            if key == "PROPERTY" {
+                let (name, arguments) =
+                    value.split_once(char::is_whitespace).unwrap_or((value, ""));
+                defaults.add(name, arguments);
+            }
+        }
+        let mut blocks = Vec::new();
+        collect_blocks(&document.section, &defaults, None, &mut blocks);
+        for headline in &document.headlines {
+            collect_headline_blocks(headline, &defaults, &mut blocks);
+        }
+        blocks
+    }
+    // The source block whose lines include `line`.
+    pub fn at(document: &OrgDocument, line: usize) -> Option<Self> {
+        Self::all(document)
+            .into_iter()
+            .find(|block| block.range.contains(&line))
+    }
+    pub fn is_silent(&self) -> bool {
+        self.arguments.has_flag("results", "silent")
+    }
+    // Like Org, blocks return their value unless `:results output` is given.
+    fn wants_value(&self) -> bool {
+        !self.arguments.has_flag("results", "output")
+    }
+}
+
+fn collect_headline_blocks(
+    headline: &Headline,
+    defaults: &HeaderArguments,
+    blocks: &mut Vec<SourceBlock>,
+) {
+    let mut defaults = defaults.clone();
+    for (key, value) in &headline.properties {
+        defaults.add(key, value);
+    }
+    collect_blocks(&headline.section, &defaults, Some(&headline.title), blocks);
+    for child in &headline.children {
+        collect_headline_blocks(child, &defaults, blocks);
+    }
+}
+
+fn collect_blocks(
+    elements: &[Element],
+    defaults: &HeaderArguments,
+    headline: Option<&str>,
+    blocks: &mut Vec<SourceBlock>,
+) {
+    let mut name = None;
+    for element in elements {
+        match &element.kind {
+            ElementKind::Keyword(keyword) if keyword.key == "NAME" => {
+                name = Some((keyword.value.clone(), element.range.end));
+                continue;
+            }
+            ElementKind::Block(block) if block.kind == "src" => {
+                let (language, arguments) = block
+                    .parameters
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((&block.parameters, ""));
+                let body: Vec<String> = block.contents.iter().map(|line| unescape(line)).collect();
+                blocks.push(SourceBlock {
+                    name: name
+                        .take()
+                        .filter(|(_, end)| *end == element.range.start)
+                        .map(|(name, _)| name),
+                    language: language.to_string(),
+                    arguments: defaults.for_block(language, HeaderArguments::parse(arguments)),
+                    body: body.join("\n"),
+                    headline: headline.map(ToString::to_string),
+                    range: element.range.clone(),
+                });
+            }
+            _ => (),
+        }
+        name = None;
+    }
+}
+
+// Removes the comma Org puts in front of lines that would otherwise be read
+// as headlines or keywords inside a block.
+pub fn unescape(line: &str) -> String {
+    let content = line.trim_start();
+    let indent = line
+        .get(..line.len().saturating_sub(content.len()))
+        .unwrap_or("");
+    let unescaped = content.trim_start_matches(',');
+    match content.strip_prefix(',') {
+        Some(rest) if unescaped.starts_with('*') || unescaped.starts_with("#+") => {
+            format!("{}{}", indent, rest)
+        }
+        _ => line.to_string(),
+    }
+}
+
+// Adds the comma Org requires in front of block lines that would
+// otherwise be read as headlines or keywords.
+pub fn escape(line: &str) -> String {
+    let content = line.trim_start();
+    let indent = line
+        .get(..line.len().saturating_sub(content.len()))
+        .unwrap_or("");
+    let unescaped = content.trim_start_matches(',');
+    if unescaped.starts_with('*') || unescaped.starts_with("#+") {
+        format!("{},{}", indent, content)
+    } else {
+        line.to_string()
+    }
+}
+
+// Unescapes block contents and removes the indentation common to all
+// non-blank lines, which is returned so it can be restored by `reindent`.
+pub fn dedent(lines: &[String]) -> (Vec<String>, String) {
+    let indentation = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let content = line.trim_start();
+            line.get(..line.len().saturating_sub(content.len()))
+                .unwrap_or("")
+        })
+        .reduce(|common, indent| {
+            let length = common
+                .chars()
+                .zip(indent.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum();
+            common.get(..length).unwrap_or("")
+        })
+        .unwrap_or("")
+        .to_string();
+    let lines = lines
+        .iter()
+        .map(|line| unescape(line.strip_prefix(&indentation).unwrap_or(line.trim_start())))
+        .collect();
+    (lines, indentation)
+}
+
+pub fn reindent(lines: &[String], indentation: &str) -> Vec<String> {
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indentation, escape(line))
+            }
+        })
+        .collect()
+}
+
+fn interpreter(language: &str) -> Option<&'static [&'static str]> {
+    match language {
+        "sh" | "shell" => Some(&["sh"]),
+        "bash" => Some(&["bash"]),
+        "zsh" => Some(&["zsh"]),
+        "python" | "python3" => Some(&["python3", "-"]),
+        "ruby" => Some(&["ruby", "-"]),
+        "perl" => Some(&["perl", "-"]),
+        "js" | "javascript" | "node" => Some(&["node", "-"]),
+        _ => None,
+    }
+}
+
+// Numbers are passed through, anything else becomes a string.
+fn literal(value: &str) -> (String, bool) {
+    if let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        return (inner.to_string(), false);
+    }
+    (value.to_string(), value.parse::<f64>().is_ok())
+}
+
+fn string_literal(language: &str, value: &str) -> String {
+    match language {
+        // Single quotes avoid interpolation of `$`, `@` and `#{}`.
+        "perl" | "ruby" => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+        _ => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+fn assignment(language: &str, name: &str, value: &str) -> String {
+    let (value, is_number) = literal(value);
+    let literal = if is_number {
+        value.clone()
+    } else {
+        string_literal(language, &value)
+    };
+    match language {
+        "python" | "python3" | "ruby" => format!("{} = {}", name, literal),
+        "perl" => format!("my ${} = {};", name, literal),
+        "js" | "javascript" | "node" => format!("const {} = {};", name, literal),
+        _ => format!("{}='{}'", name, value.replace('\'', "'\\''")),
+    }
+}
+
+fn indent(body: &str, prefix: &str) -> String {
+    body.lines()
+        .map(|line| format!("{}{}", prefix, line))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// The program handed to the interpreter: variable assignments followed by
+// the body, wrapped so that `:results value` prints the returned value.
+fn script(block: &SourceBlock) -> String {
+    let mut script: Vec<String> = block
+        .arguments
+        .variables()
+        .iter()
+        .map(|(name, value)| assignment(&block.language, name, value))
+        .collect();
+    let body = if block.wants_value() {
+        match block.language.as_str() {
+            "python" | "python3" => {
+                format!(
+                    "def main():\n{}\n\nprint(main())",
+                    indent(&block.body, "    ")
+                )
+            }
+            "ruby" => format!("puts((lambda do\n{}\nend).call)", block.body),
+            "perl" => format!(
+                "my $result = do {{\n{}\n}};\nprint \"$result\\n\";",
+                block.body
+            ),
+            "js" | "javascript" | "node" => {
+                format!("console.log((function() {{\n{}\n}})());", block.body)
+            }
+            // Shells have no return value, so their output is the value.
+            _ => block.body.clone(),
+        }
+    } else {
+        block.body.clone()
+    };
+    script.push(body);
+    let mut script = script.join("\n");
+    script.push('\n');
+    script
+}
+
+// Expands a leading `~/` to `$HOME`, as header arguments like `:dir` and
+// `:tangle` allow.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => std::env::var("HOME")
+            .map(|home| Path::new(&home).join(rest))
+            .unwrap_or_else(|_| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+// Runs the block and returns its standard output. Relative `:dir`s are
+// resolved against `directory`, which is also the default working
+// directory.
+pub fn execute(block: &SourceBlock, directory: Option<&Path>) -> Result<String, String> {
+    let custom;
+    let command: Vec<&str> = if let Some(cmd) = block.arguments.get("cmd") {
+        custom = cmd.to_string();
+        custom.split_whitespace().collect()
+    } else {
+        interpreter(&block.language)
+            .ok_or_else(|| format!("No interpreter configured for {}", block.language))?
+            .to_vec()
+    };
+    let (program, arguments) = command
+        .split_first()
+        .ok_or_else(|| "Empty :cmd".to_string())?;
+    let mut working_directory = directory.map(Path::to_path_buf);
+    if let Some(dir) = block.arguments.get("dir") {
+        let dir = expand_home(dir);
+        working_directory = Some(match &working_directory {
+            Some(base) => base.join(dir),
+            None => dir,
+        });
+    }
+    let mut command = Command::new(program);
+    command
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(dir) = working_directory.filter(|dir| !dir.as_os_str().is_empty()) {
+        command.current_dir(dir);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|error| format!("Could not run {}: {}", program, error))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(script(block).as_bytes())
+            .map_err(|error| error.to_string())?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|error| error.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("");
+        return Err(format!(
+            "{} exited with {}: {}",
+            program, output.status, reason
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The `#+RESULTS:` keyword and its contents following the block that ends
+// at `block_end`, if there is one.
+pub fn results_range(lines: &[&str], block_end: usize) -> Option<Range<usize>> {
+    let start = (block_end..lines.len()).find(|index| {
+        lines
+            .get(*index)
+            .is_some_and(|line| !line.trim().is_empty())
+    })?;
+    let keyword = lines.get(start)?.trim_start();
+    if !keyword
+        .get(..9)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("#+RESULTS"))
+    {
+        return None;
+    }
+    let mut end = start.saturating_add(1);
+    if let Some(begin) = lines.get(end).and_then(|line| {
+        let line = line.trim_start();
+        line.get(..8)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("#+begin_"))
+            .map(|_| line.to_lowercase().replacen("#+begin_", "#+end_", 1))
+    }) {
+        let marker = begin.split_whitespace().next().unwrap_or("");
+        let close = (end..lines.len()).find(|index| {
+            lines
+                .get(*index)
+                .is_some_and(|line| line.trim().to_lowercase() == marker)
+        })?;
+        return Some(start..close.saturating_add(1));
+    }
+    while lines.get(end).is_some_and(|line| {
+        let line = line.trim_start();
+        line == ":" || line.starts_with(": ") || line.starts_with('|')
+    }) {
+        end = end.saturating_add(1);
+    }
+    Some(start..end)
+}
+
+// Output formatted as fixed-width lines under a `#+RESULTS:` keyword.
+pub fn format_results(name: Option<&str>, output: &str) -> Vec<String> {
+    let mut lines = vec![match name {
+        Some(name) => format!("#+RESULTS: {}", name),
+        None => "#+RESULTS:".to_string(),
+    }];
+    lines.extend(output.lines().map(|line| {
+        if line.is_empty() {
+            ":".to_string()
+        } else {
+            format!(": {}", line)
+        }
+    }));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_arguments() {
+        let arguments =
+            HeaderArguments::parse(":var x=1, y=\"a b\" :results output silent :dir /tmp");
+        assert_eq!(arguments.get("dir"), Some("/tmp"));
+        assert!(arguments.has_flag("results", "silent"));
+        assert_eq!(
+            arguments.variables(),
+            vec![
+                ("x".to_string(), "1".to_string()),
+                ("y".to_string(), "\"a b\"".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_source_block_at() {
+        let document = OrgDocument::parse(
+            "* Code\n#+NAME: greet\n#+BEGIN_SRC sh :var who=world\necho hello $who\n,* escaped\n#+END_SRC\n",
+        );
+        let block = SourceBlock::at(&document, 3).unwrap();
+        assert_eq!(block.name.as_deref(), Some("greet"));
+        assert_eq!(block.language, "sh");
+        assert_eq!(block.body, "echo hello $who\n* escaped");
+        assert_eq!(block.range, 2..6);
+        assert!(SourceBlock::at(&document, 0).is_none());
+    }
+
+    #[test]
+    fn test_execute() {
+        let document = OrgDocument::parse(
+            "#+BEGIN_SRC sh :var who=\"it's me\" :var n=2\necho \"$who $n\"\n#+END_SRC",
+        );
+        let block = SourceBlock::at(&document, 0).unwrap();
+        assert_eq!(execute(&block, None).unwrap(), "it's me 2\n");
+        let document = OrgDocument::parse("#+BEGIN_SRC sh\nexit 3\n#+END_SRC");
+        let block = SourceBlock::at(&document, 0).unwrap();
+        assert!(execute(&block, None).is_err());
+    }
+
+    #[test]
+    fn test_dedent_and_reindent() {
+        let lines: Vec<String> = [
+            "  fn main() {",
+            "  ,* not a headline",
+            "",
+            "      body();",
+            "  }",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        let (dedented, indentation) = dedent(&lines);
+        assert_eq!(indentation, "  ");
+        assert_eq!(
+            dedented,
+            vec!["fn main() {", "* not a headline", "", "    body();", "}"]
+        );
+        assert_eq!(reindent(&dedented, &indentation), lines);
+        assert_eq!(escape(",#+TITLE"), ",,#+TITLE");
+        assert_eq!(unescape(",,#+TITLE"), ",#+TITLE");
+    }
+
+    #[test]
+    fn test_results_range() {
+        let lines = vec![
+            "#+END_SRC",
+            "",
+            "#+RESULTS:",
+            ": one",
+            ":",
+            ": two",
+            "after",
+        ];
+        assert_eq!(results_range(&lines, 1), Some(2..6));
+        let lines = vec![
+            "#+END_SRC",
+            "#+RESULTS:",
+            "#+begin_example",
+            "x",
+            "#+end_example",
+            "y",
+        ];
+        assert_eq!(results_range(&lines, 1), Some(1..5));
+        assert_eq!(results_range(&["#+END_SRC", "text"], 1), None);
+        assert_eq!(
+            format_results(None, "a\n\nb\n"),
+            vec!["#+RESULTS:", ": a", ":", ": b"]
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use crate::clipboard::{self, Selection};
+use crate::screen::{Rgb, Style};
+use std::any::Any;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use termion::event::Key;
+
+// Keys use termion's vocabulary whichever backend reads them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Key(Key),
+    Resize(u16, u16),
+    // Text pasted into the terminal, with newlines as `\n`.
+    Paste(String),
+    // Sent through a `Waker`.
+    Wake,
+}
+
+// Ends a backend's wait for an event with `Event::Wake`, from any thread.
+#[derive(Clone)]
+pub struct Waker(Sender<io::Result<Event>>);
+
+impl Waker {
+    pub fn wake(&self) {
+        let _ = self.0.send(Ok(Event::Wake));
+    }
+}
+
+// The channel the input threads send events into. It is also how a
+// `Waker` interrupts a wait.
+struct Events {
+    sender: Sender<io::Result<Event>>,
+    receiver: Receiver<io::Result<Event>>,
+    started: bool,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            started: false,
+        }
+    }
+}
+
+impl Events {
+    // The input threads are started by `start` on the first wait.
+    fn poll(
+        &mut self,
+        timeout: Option<Duration>,
+        start: fn(Sender<io::Result<Event>>),
+    ) -> io::Result<Option<Event>> {
+        if !self.started {
+            self.started = true;
+            start(self.sender.clone());
+        }
+        match timeout {
+            Some(timeout) => match self.receiver.recv_timeout(timeout) {
+                Ok(event) => event.map(Some),
+                Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+                Err(mpsc::RecvTimeoutError::Disconnected) => Err(closed()),
+            },
+            None => self.receiver.recv().map_err(|_| closed())?.map(Some),
+        }
+    }
+    fn waker(&self) -> Waker {
+        Waker(self.sender.clone())
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "standard input was closed")
+}
+
+// Everything the editor needs from a terminal. Output calls are queued and
+// only sent on `flush`, so a frame reaches the terminal in one write.
+pub trait Backend: Any {
+    fn size(&self) -> io::Result<(u16, u16)>;
+    fn enable_raw_mode(&mut self) -> io::Result<()>;
+    fn disable_raw_mode(&mut self) -> io::Result<()>;
+    // Waits up to `timeout` for an event, or indefinitely without one.
+    // `None` means the time ran out.
+    fn poll_event(&mut self, timeout: Option<Duration>) -> io::Result<Option<Event>>;
+    fn waker(&self) -> Waker;
+    fn hide_cursor(&mut self) -> io::Result<()>;
+    fn show_cursor(&mut self) -> io::Result<()>;
+    // Zero-based.
+    fn move_cursor(&mut self, x: u16, y: u16) -> io::Result<()>;
+    fn set_style(&mut self, style: Style) -> io::Result<()>;
+    fn clear_all(&mut self) -> io::Result<()>;
+    fn clear_until_newline(&mut self) -> io::Result<()>;
+    fn print(&mut self, text: &str) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    // Asks the terminal to set the system clipboard.
+    fn copy_to_clipboard(&mut self, selection: Selection, text: &str) -> io::Result<()>;
+}
+
+// The backend named by `$ORGONAUT_BACKEND`; crossterm unless it says
+// `termion`.
+pub fn from_env() -> Box<dyn Backend> {
+    match std::env::var("ORGONAUT_BACKEND").as_deref() {
+        Ok("termion") => Box::new(TermionBackend::default()),
+        _ => Box::new(CrosstermBackend::default()),
+    }
+}
+
+#[derive(Default)]
+pub struct TermionBackend {
+    raw: Option<termion::raw::RawTerminal<io::Stdout>>,
+    output: String,
+    events: Events,
+}
+
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+
+// The character a key stands for inside a paste.
+fn pasted_char(key: &Key) -> Option<char> {
+    match key {
+        Key::Char(c) => Some(*c),
+        Key::Ctrl(c) if c.is_ascii_lowercase() => char::from_u32(
+            u32::from(*c)
+                .saturating_sub(u32::from('a'))
+                .saturating_add(1),
+        ),
+        _ => None,
+    }
+}
+
+// termion has no resize events; the window size only changes with a
+// SIGWINCH. Keys and signals are read on their own threads and merged into
+// one channel, so a resize wakes up a read that is waiting for a key.
+// termion does not know bracketed paste either: the keys between the
+// markers it reports as unsupported are collected into one paste.
+fn termion_events(sender: Sender<io::Result<Event>>) {
+    use signal_hook::consts::SIGWINCH;
+    use signal_hook::iterator::Signals;
+    use termion::input::TermRead;
+    let keys = sender.clone();
+    thread::spawn(move || {
+        let mut paste: Option<String> = None;
+        for event in io::stdin().events() {
+            let event = match event {
+                Ok(termion::event::Event::Unsupported(bytes)) if bytes == b"\x1b[200~" => {
+                    paste = Some(String::new());
+                    continue;
+                }
+                Ok(termion::event::Event::Unsupported(bytes)) if bytes == b"\x1b[201~" => {
+                    match paste.take() {
+                        Some(text) => Ok(Event::Paste(text)),
+                        None => continue,
+                    }
+                }
+                Ok(termion::event::Event::Key(key)) => match paste.as_mut() {
+                    Some(text) => {
+                        text.extend(pasted_char(&key));
+                        continue;
+                    }
+                    None => Ok(Event::Key(key)),
+                },
+                Ok(_) => continue,
+                Err(error) => Err(error),
+            };
+            let failed = event.is_err();
+            if keys.send(event).is_err() || failed {
+                return;
+            }
+        }
+        let _ = keys.send(Err(closed()));
+    });
+    if let Ok(mut signals) = Signals::new([SIGWINCH]) {
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                let size = termion::terminal_size();
+                if sender
+                    .send(size.map(|(width, height)| Event::Resize(width, height)))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl Backend for TermionBackend {
+    fn size(&self) -> io::Result<(u16, u16)> {
+        termion::terminal_size()
+    }
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+        use termion::raw::IntoRawMode;
+        let mut raw = io::stdout().into_raw_mode()?;
+        raw.write_all(BRACKETED_PASTE_ON.as_bytes())?;
+        raw.flush()?;
+        self.raw = Some(raw);
+        Ok(())
+    }
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+        // Dropping the raw terminal restores the previous mode.
+        if let Some(mut raw) = self.raw.take() {
+            raw.write_all(BRACKETED_PASTE_OFF.as_bytes())?;
+            raw.flush()?;
+        }
+        Ok(())
+    }
+    fn poll_event(&mut self, timeout: Option<Duration>) -> io::Result<Option<Event>> {
+        self.events.poll(timeout, termion_events)
+    }
+    fn waker(&self) -> Waker {
+        self.events.waker()
+    }
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        self.output.push_str(&termion::cursor::Hide.to_string());
+        Ok(())
+    }
+    fn show_cursor(&mut self) -> io::Result<()> {
+        self.output.push_str(&termion::cursor::Show.to_string());
+        Ok(())
+    }
+    fn move_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+        let goto = termion::cursor::Goto(x.saturating_add(1), y.saturating_add(1));
+        self.output.push_str(&goto.to_string());
+        Ok(())
+    }
+    fn set_style(&mut self, style: Style) -> io::Result<()> {
+        use termion::color;
+        let fg = match style.fg {
+            Some(Rgb(r, g, b)) => color::Fg(color::Rgb(r, g, b)).to_string(),
+            None => color::Fg(color::Reset).to_string(),
+        };
+        let bg = match style.bg {
+            Some(Rgb(r, g, b)) => color::Bg(color::Rgb(r, g, b)).to_string(),
+            None => color::Bg(color::Reset).to_string(),
+        };
+        self.output.push_str(&fg);
+        self.output.push_str(&bg);
+        Ok(())
+    }
+    fn clear_all(&mut self) -> io::Result<()> {
+        self.output.push_str(&termion::clear::All.to_string());
+        Ok(())
+    }
+    fn clear_until_newline(&mut self) -> io::Result<()> {
+        self.output
+            .push_str(&termion::clear::UntilNewline.to_string());
+        Ok(())
+    }
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        self.output.push_str(text);
+        Ok(())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(self.output.as_bytes())?;
+        self.output.clear();
+        stdout.flush()
+    }
+    fn copy_to_clipboard(&mut self, selection: Selection, text: &str) -> io::Result<()> {
+        self.output.push_str(&clipboard::osc52(selection, text));
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct CrosstermBackend {
+    output: Vec<u8>,
+    events: Events,
+}
+
+fn crossterm_color(color: Option<Rgb>) -> crossterm::style::Color {
+    match color {
+        Some(Rgb(r, g, b)) => crossterm::style::Color::Rgb { r, g, b },
+        None => crossterm::style::Color::Reset,
+    }
+}
+
+// Translates a key press into termion's `Key`. Releases, repeats reported as
+// separate events and keys termion has no name for are dropped.
+fn crossterm_key(event: &crossterm::event::KeyEvent) -> Option<Key> {
+    use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers};
+    if event.kind == KeyEventKind::Release {
+        return None;
+    }
+    let key = match event.code {
+        KeyCode::Char(c) if event.modifiers.contains(KeyModifiers::CONTROL) => Key::Ctrl(c),
+        KeyCode::Char(c) if event.modifiers.contains(KeyModifiers::ALT) => Key::Alt(c),
+        KeyCode::Char(c) => Key::Char(c),
+        KeyCode::Enter => Key::Char('\n'),
+        KeyCode::Tab => Key::Char('\t'),
+        KeyCode::BackTab => Key::BackTab,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Esc => Key::Esc,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::F(n) => Key::F(n),
+        KeyCode::Null => Key::Null,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// Reads events on their own thread, so a `Waker` can interrupt the wait
+// for them.
+fn crossterm_events(sender: Sender<io::Result<Event>>) {
+    thread::spawn(move || {
+        loop {
+            let event = match crossterm::event::read() {
+                Ok(crossterm::event::Event::Key(event)) => match crossterm_key(&event) {
+                    Some(key) => Ok(Event::Key(key)),
+                    None => continue,
+                },
+                Ok(crossterm::event::Event::Resize(width, height)) => {
+                    Ok(Event::Resize(width, height))
+                }
+                // Terminals send pasted newlines as carriage returns.
+                Ok(crossterm::event::Event::Paste(text)) => {
+                    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+                    Ok(Event::Paste(text))
+                }
+                Ok(_) => continue,
+                Err(error) => Err(error),
+            };
+            let failed = event.is_err();
+            if sender.send(event).is_err() || failed {
+                break;
+            }
+        }
+    });
+}
+
+impl Backend for CrosstermBackend {
+    fn size(&self) -> io::Result<(u16, u16)> {
+        crossterm::terminal::size()
+    }
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+        crossterm::terminal::enable_raw_mode()?;
+        crossterm::execute!(io::stdout(), crossterm::event::EnableBracketedPaste)
+    }
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+        crossterm::execute!(io::stdout(), crossterm::event::DisableBracketedPaste)?;
+        crossterm::terminal::disable_raw_mode()
+    }
+    fn poll_event(&mut self, timeout: Option<Duration>) -> io::Result<Option<Event>> {
+        self.events.poll(timeout, crossterm_events)
+    }
+    fn waker(&self) -> Waker {
+        self.events.waker()
+    }
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        crossterm::queue!(self.output, crossterm::cursor::Hide)
+    }
+    fn show_cursor(&mut self) -> io::Result<()> {
+        crossterm::queue!(self.output, crossterm::cursor::Show)
+    }
+    fn move_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+        crossterm::queue!(self.output, crossterm::cursor::MoveTo(x, y))
+    }
+    fn set_style(&mut self, style: Style) -> io::Result<()> {
+        crossterm::queue!(
+            self.output,
+            crossterm::style::SetForegroundColor(crossterm_color(style.fg)),
+            crossterm::style::SetBackgroundColor(crossterm_color(style.bg))
+        )
+    }
+    fn clear_all(&mut self) -> io::Result<()> {
+        crossterm::queue!(
+            self.output,
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
+        )
+    }
+    fn clear_until_newline(&mut self) -> io::Result<()> {
+        crossterm::queue!(
+            self.output,
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine)
+        )
+    }
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        crossterm::queue!(self.output, crossterm::style::Print(text))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&self.output)?;
+        self.output.clear();
+        stdout.flush()
+    }
+    fn copy_to_clipboard(&mut self, selection: Selection, text: &str) -> io::Result<()> {
+        self.output
+            .extend_from_slice(clipboard::osc52(selection, text).as_bytes());
+        Ok(())
+    }
+}
+
+// A terminal in memory for tests: events are queued up front, and output
+// is applied to a grid of cells that can be inspected afterwards.
+#[cfg(test)]
+pub struct MemoryBackend {
+    width: u16,
+    height: u16,
+    // `None` stands for a wait that timed out.
+    events: std::collections::VecDeque<Option<Event>>,
+    cells: Vec<Vec<String>>,
+    cursor: (u16, u16),
+    raw: bool,
+    // The calls made since the last `take_log`, with consecutive prints
+    // merged.
+    log: Vec<String>,
+    clipboard: Option<(Selection, String)>,
+    wakes: Events,
+}
+
+#[cfg(test)]
+impl MemoryBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            events: std::collections::VecDeque::new(),
+            cells: vec![vec![" ".to_string(); width as usize]; height as usize],
+            cursor: (0, 0),
+            raw: false,
+            log: Vec::new(),
+            clipboard: None,
+            wakes: Events::default(),
+        }
+    }
+    pub fn push_keys(&mut self, keys: &[Key]) {
+        self.events
+            .extend(keys.iter().map(|key| Some(Event::Key(key.clone()))));
+    }
+    // The window is resized when the event is read, as if it happened
+    // while the editor was waiting for a key.
+    pub fn push_resize(&mut self, width: u16, height: u16) {
+        self.events.push_back(Some(Event::Resize(width, height)));
+    }
+    pub fn push_paste(&mut self, text: &str) {
+        self.events.push_back(Some(Event::Paste(text.to_string())));
+    }
+    // Lets the editor's timers run once, as if no key was pressed in time.
+    pub fn push_timeout(&mut self) {
+        self.events.push_back(None);
+    }
+    // Waits until a `Waker` is used, as if nothing happened until then.
+    pub fn push_wait(&mut self) {
+        self.events.push_back(Some(Event::Wake));
+    }
+    pub fn line(&self, y: usize) -> String {
+        self.cells
+            .get(y)
+            .map_or_else(String::new, |line| line.concat().trim_end().to_string())
+    }
+    pub fn cursor(&self) -> (u16, u16) {
+        self.cursor
+    }
+    pub fn is_raw(&self) -> bool {
+        self.raw
+    }
+    // What was last copied to the clipboard, and to which selection.
+    pub fn clipboard(&self) -> Option<&(Selection, String)> {
+        self.clipboard.as_ref()
+    }
+    pub fn take_log(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.log)
+    }
+    fn record(&mut self, entry: String) {
+        self.log.push(entry);
+    }
+    fn clear_line_from(&mut self, x: u16, y: u16) {
+        if let Some(line) = self.cells.get_mut(y as usize) {
+            for cell in line.iter_mut().skip(x as usize) {
+                *cell = " ".to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl Backend for MemoryBackend {
+    fn size(&self) -> io::Result<(u16, u16)> {
+        Ok((self.width, self.height))
+    }
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+        self.raw = true;
+        Ok(())
+    }
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+        self.raw = false;
+        Ok(())
+    }
+    // Running out of events ends the session, like a closed tty. Queued
+    // timeouts are returned whatever the requested timeout is.
+    fn poll_event(&mut self, _timeout: Option<Duration>) -> io::Result<Option<Event>> {
+        let event = self
+            .events
+            .pop_front()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no more events"))?;
+        if event == Some(Event::Wake) {
+            return self.wakes.poll(Some(Duration::from_secs(10)), |_| ());
+        }
+        if let Some(Event::Resize(width, height)) = event {
+            self.width = width;
+            self.height = height;
+            self.cells = vec![vec![" ".to_string(); width as usize]; height as usize];
+        }
+        Ok(event)
+    }
+    fn waker(&self) -> Waker {
+        self.wakes.waker()
+    }
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        self.record("hide".to_string());
+        Ok(())
+    }
+    fn show_cursor(&mut self) -> io::Result<()> {
+        self.record("show".to_string());
+        Ok(())
+    }
+    fn move_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+        self.cursor = (x, y);
+        self.record(format!("move {},{}", x, y));
+        Ok(())
+    }
+    fn set_style(&mut self, style: Style) -> io::Result<()> {
+        self.record(format!("style {:?} {:?}", style.fg, style.bg));
+        Ok(())
+    }
+    fn clear_all(&mut self) -> io::Result<()> {
+        for y in 0..self.height {
+            self.clear_line_from(0, y);
+        }
+        self.record("clear all".to_string());
+        Ok(())
+    }
+    fn clear_until_newline(&mut self) -> io::Result<()> {
+        let (x, y) = self.cursor;
+        self.clear_line_from(x, y);
+        self.record("clear line".to_string());
+        Ok(())
+    }
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        use unicode_segmentation::UnicodeSegmentation;
+        use unicode_width::UnicodeWidthStr;
+        // Wide graphemes are followed by empty cells, so lines read back as
+        // they were printed.
+        for grapheme in text.graphemes(true) {
+            let (x, y) = self.cursor;
+            let width = u16::try_from(grapheme.width()).unwrap_or(1);
+            for column in x..x.saturating_add(width) {
+                if let Some(cell) = self
+                    .cells
+                    .get_mut(y as usize)
+                    .and_then(|line| line.get_mut(column as usize))
+                {
+                    *cell = if column == x {
+                        grapheme.to_string()
+                    } else {
+                        String::new()
+                    };
+                }
+            }
+            self.cursor = (x.saturating_add(width), y);
+        }
+        match self.log.last_mut() {
+            Some(entry) if entry.starts_with("print ") => entry.push_str(text),
+            _ => self.record(format!("print {}", text)),
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    fn copy_to_clipboard(&mut self, selection: Selection, text: &str) -> io::Result<()> {
+        self.clipboard = Some((selection, text.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+    #[test]
+    fn test_crossterm_keys() {
+        let key = |code, modifiers| crossterm_key(&KeyEvent::new(code, modifiers));
+        assert_eq!(
+            key(KeyCode::Char('s'), KeyModifiers::CONTROL),
+            Some(Key::Ctrl('s'))
+        );
+        assert_eq!(
+            key(KeyCode::Enter, KeyModifiers::NONE),
+            Some(Key::Char('\n'))
+        );
+        assert_eq!(key(KeyCode::Esc, KeyModifiers::NONE), Some(Key::Esc));
+        assert_eq!(key(KeyCode::CapsLock, KeyModifiers::NONE), None);
+        let mut release = KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE);
+        release.kind = KeyEventKind::Release;
+        assert_eq!(crossterm_key(&release), None);
+    }
+
+    #[test]
+    fn test_pasted_chars() {
+        assert_eq!(pasted_char(&Key::Char('\n')), Some('\n'));
+        assert_eq!(pasted_char(&Key::Char('\t')), Some('\t'));
+        assert_eq!(pasted_char(&Key::Ctrl('a')), Some('\u{1}'));
+        assert_eq!(pasted_char(&Key::Up), None);
+    }
+}
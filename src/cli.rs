@@ -0,0 +1,110 @@
+use crate::export::{self, Format, Settings, ical};
+use crate::org::OrgDocument;
+use crate::tangle;
+use std::fs;
+use std::io::{self, Write};
+
+const USAGE: &str = "Usage: orgonaut [FILE] [--as-org]
+       orgonaut export FORMAT FILE... [-o OUTPUT] [-w WIDTH]
+       orgonaut tangle FILE...
+
+Export formats: ascii, utf8, ics, json
+Use - as FILE to read from standard input.";
+
+// Runs a headless subcommand if one was given. Returns the exit code, or
+// `None` when the editor should start instead.
+pub fn run(args: &[String]) -> Option<i32> {
+    let result = match args.get(1).map(String::as_str) {
+        Some("export") => export(args.get(2..).unwrap_or(&[])),
+        Some("tangle") => tangle(args.get(2..).unwrap_or(&[])),
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => return None,
+    };
+    match result {
+        Ok(()) => Some(0),
+        Err(error) => {
+            eprintln!("orgonaut: {}\n\n{}", error, USAGE);
+            Some(1)
+        }
+    }
+}
+
+fn tangle(files: &[String]) -> Result<(), String> {
+    if files.is_empty() {
+        return Err("missing file".to_string());
+    }
+    for file in files {
+        let text = fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?;
+        let summary = tangle::tangle(&OrgDocument::parse(&text), file)?;
+        println!("{}: {}", file, summary);
+    }
+    Ok(())
+}
+
+fn export(args: &[String]) -> Result<(), String> {
+    let mut format = None;
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut settings = Settings::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(args.next().ok_or("missing output file")?),
+            "-w" | "--width" => {
+                let width = args.next().ok_or("missing width")?;
+                settings.width = width
+                    .parse()
+                    .map_err(|_| format!("invalid width: {}", width))?;
+            }
+            _ if format.is_none() => {
+                format = Some(Format::from(arg).ok_or(format!("unknown format: {}", arg))?);
+            }
+            _ => files.push(arg),
+        }
+    }
+    let format = format.ok_or("missing export format")?;
+    if files.is_empty() {
+        return Err("missing input file".to_string());
+    }
+    if files.len() > 1 && format != Format::Icalendar {
+        return Err("only ics export accepts several input files".to_string());
+    }
+    let mut documents = Vec::new();
+    for file in &files {
+        let text = if file.as_str() == "-" {
+            io::read_to_string(io::stdin())
+        } else {
+            fs::read_to_string(file)
+        }
+        .map_err(|error| format!("{}: {}", file, error))?;
+        documents.push(OrgDocument::parse(&text));
+    }
+    let contents = if format == Format::Icalendar {
+        let calendars: Vec<ical::Calendar<'_>> = files
+            .iter()
+            .zip(&documents)
+            .map(|(file_name, document)| ical::Calendar {
+                file_name,
+                document,
+            })
+            .collect();
+        ical::export(&calendars)
+    } else {
+        let (file_name, document) = files
+            .first()
+            .zip(documents.first())
+            .ok_or("missing input file")?;
+        export::export(file_name, document, format, &settings)
+    };
+    match output {
+        Some(output) => {
+            fs::write(output, contents).map_err(|error| format!("{}: {}", output, error))
+        }
+        None => io::stdout()
+            .write_all(contents.as_bytes())
+            .map_err(|error| error.to_string()),
+    }
+}
@@ -0,0 +1,215 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// The `+` register is the clipboard and `*` the primary selection, as in
+// Vim on X11.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Selection {
+    Clipboard,
+    Primary,
+}
+
+impl Selection {
+    pub fn from_register(name: char) -> Option<Self> {
+        match name {
+            '+' => Some(Self::Clipboard),
+            '*' => Some(Self::Primary),
+            _ => None,
+        }
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let byte = |index: usize| u32::from(chunk.get(index).copied().unwrap_or(0));
+        let group = (byte(0) << 16) | (byte(1) << 8) | byte(2);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(BASE64[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// The OSC 52 sequence that sets `selection` to `text` in the terminal, which
+// also works over SSH. Inside tmux it is passed through to the outer
+// terminal.
+pub fn osc52(selection: Selection, text: &str) -> String {
+    let target = match selection {
+        Selection::Clipboard => 'c',
+        Selection::Primary => 'p',
+    };
+    let sequence = format!("\x1b]52;{};{}\x07", target, base64(text.as_bytes()));
+    if env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+fn words(command: &str) -> Vec<String> {
+    command.split_whitespace().map(String::from).collect()
+}
+
+// Local programs that read and write the system clipboard. Without them,
+// copying still works through the terminal and pasting uses the text
+// copied last.
+#[derive(Default)]
+pub struct Clipboard {
+    copy: Option<Vec<String>>,
+    paste: Option<Vec<String>>,
+    // The tools take a flag for the primary selection.
+    primary: Vec<String>,
+}
+
+impl Clipboard {
+    // `$ORGONAUT_COPY_COMMAND` and `$ORGONAUT_PASTE_COMMAND` if set, or
+    // else wl-copy, xclip or pbcopy, whichever is available.
+    pub fn from_env() -> Self {
+        let configured = |key: &str| env::var(key).ok().map(|command| words(&command));
+        if let (Some(copy), paste) = (
+            configured("ORGONAUT_COPY_COMMAND"),
+            configured("ORGONAUT_PASTE_COMMAND"),
+        ) {
+            return Self {
+                copy: Some(copy),
+                paste,
+                primary: Vec::new(),
+            };
+        }
+        let tools = [
+            (
+                "WAYLAND_DISPLAY",
+                "wl-copy",
+                "wl-paste --no-newline",
+                "--primary",
+            ),
+            (
+                "DISPLAY",
+                "xclip -selection clipboard -in",
+                "xclip -selection clipboard -out",
+                "",
+            ),
+            ("", "pbcopy", "pbpaste", ""),
+        ];
+        for (display, copy, paste, primary) in tools {
+            let copy = words(copy);
+            let usable = (display.is_empty() || env::var_os(display).is_some())
+                && copy.first().is_some_and(|program| in_path(program));
+            if usable {
+                return Self {
+                    copy: Some(copy),
+                    paste: Some(words(paste)),
+                    primary: words(primary),
+                };
+            }
+        }
+        Self::default()
+    }
+    fn command(&self, command: &[String], selection: Selection) -> Option<Command> {
+        let (program, arguments) = command.split_first()?;
+        let mut command = Command::new(program);
+        for argument in arguments {
+            // xclip names the selection in its arguments.
+            if selection == Selection::Primary && argument == "clipboard" {
+                command.arg("primary");
+            } else {
+                command.arg(argument);
+            }
+        }
+        if selection == Selection::Primary {
+            command.args(&self.primary);
+        }
+        Some(command)
+    }
+    // Does nothing without a copy command.
+    pub fn copy(&self, selection: Selection, text: &str) -> io::Result<()> {
+        let Some(mut command) = self
+            .copy
+            .as_ref()
+            .and_then(|copy| self.command(copy, selection))
+        else {
+            return Ok(());
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        child.wait()?;
+        Ok(())
+    }
+    // `None` without a paste command or when it fails.
+    pub fn paste(&self, selection: Selection) -> Option<String> {
+        let mut command = self.command(self.paste.as_ref()?, selection)?;
+        let output = command
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        String::from_utf8(output.stdout).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("héllo\n".as_bytes()), "aMOpbGxvCg==");
+        if env::var_os("TMUX").is_none() {
+            assert_eq!(osc52(Selection::Clipboard, "hi"), "\x1b]52;c;aGk=\x07");
+            assert_eq!(osc52(Selection::Primary, "hi"), "\x1b]52;p;aGk=\x07");
+        }
+    }
+
+    #[test]
+    fn test_local_commands() {
+        let clipboard = Clipboard {
+            copy: Some(words("true")),
+            paste: Some(words("printf pasted")),
+            primary: Vec::new(),
+        };
+        assert!(clipboard.copy(Selection::Clipboard, "text").is_ok());
+        assert_eq!(
+            clipboard.paste(Selection::Primary).as_deref(),
+            Some("pasted")
+        );
+        assert_eq!(Clipboard::default().paste(Selection::Clipboard), None);
+        let xclip = Clipboard {
+            copy: Some(words("xclip -selection clipboard -in")),
+            ..Clipboard::default()
+        };
+        let command = xclip
+            .command(xclip.copy.as_ref().unwrap(), Selection::Primary)
+            .unwrap();
+        let arguments: Vec<_> = command.get_args().collect();
+        assert_eq!(arguments, ["-selection", "primary", "-in"]);
+    }
+}
@@ -2,8 +2,24 @@ use crate::FileType;
 use crate::Position;
 use crate::Row;
 use crate::SearchDirection;
+use crate::highlighting::State;
+use crate::markdown;
+use regex::Regex;
 use std::fs;
 use std::io::{Error, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Rows edited since the last `take_change`: the rows from `start` up to the
+// last `after` ones replaced `old`.
+#[derive(Debug)]
+pub struct Change {
+    start: usize,
+    old: Vec<String>,
+    after: usize,
+}
 
 #[derive(Default)]
 pub struct Document {
@@ -11,22 +27,63 @@ pub struct Document {
     pub file_name: Option<String>,
     dirty: bool,
     file_type: FileType,
+    // The pattern whose matches the rows were last highlighted with.
+    highlighted_pattern: Option<String>,
+    // When the file was last read or written by us.
+    disk_modified: Option<SystemTime>,
+    change: Option<Change>,
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
         let contents = fs::read_to_string(filename)?;
+        Ok(Self::from_contents(filename, &contents, false))
+    }
+    // Opens a Markdown file converted to Org. The buffer is named after the
+    // source with an `.org` extension and starts out dirty, so saving never
+    // overwrites the Markdown original. If that file exists already, which
+    // includes converting an `.org` file, the buffer is left unnamed.
+    pub fn open_as_org(filename: &str) -> Result<Self, std::io::Error> {
+        let contents = markdown::to_org(&fs::read_to_string(filename)?);
+        let org_name = Path::new(filename).with_extension("org");
+        let mut document = Self::from_contents(&org_name.to_string_lossy(), &contents, true);
+        if org_name.exists() {
+            document.file_name = None;
+            document.disk_modified = None;
+        }
+        Ok(document)
+    }
+    // An unnamed buffer, used for editing parts of another document.
+    pub fn from_lines(lines: &[String], file_type: FileType) -> Self {
+        Self {
+            rows: lines.iter().map(|line| Row::from(line.as_str())).collect(),
+            file_name: None,
+            dirty: false,
+            file_type,
+            highlighted_pattern: None,
+            disk_modified: None,
+            change: None,
+        }
+    }
+    // An empty buffer for a file that does not exist yet.
+    pub fn new_file(filename: &str) -> Self {
+        Self::from_contents(filename, "", false)
+    }
+    fn from_contents(filename: &str, contents: &str, dirty: bool) -> Self {
         let file_type = FileType::from(filename);
         let mut rows = Vec::new();
         for value in contents.lines() {
             rows.push(Row::from(value));
         }
-        Ok(Self {
+        Self {
             rows,
             file_name: Some(filename.to_string()),
-            dirty: false,
+            dirty,
             file_type,
-        })
+            highlighted_pattern: None,
+            disk_modified: modified(filename),
+            change: None,
+        }
     }
     pub fn file_type(&self) -> String {
         self.file_type.name()
@@ -59,6 +116,7 @@ impl Document {
             return;
         }
         self.dirty = true;
+        self.record(at.y..at.y.saturating_add(1));
         if c == '\n' {
             self.insert_newline(at);
         } else if at.y == self.rows.len() {
@@ -70,12 +128,18 @@ impl Document {
             let row = &mut self.rows[at.y];
             row.insert(at.x, c);
         }
-        self.unhighlight_rows(at.y);
+        self.unhighlight_row(at.y);
     }
 
-    fn unhighlight_rows(&mut self, start: usize) {
-        let start = start.saturating_sub(1);
-        for row in self.rows.iter_mut().skip(start) {
+    // Only the edited row is invalidated; `highlight` continues with the
+    // rows below as long as their start state changes.
+    fn unhighlight_row(&mut self, index: usize) {
+        if let Some(row) = self.rows.get_mut(index) {
+            row.is_highlighted = false;
+        }
+    }
+    fn unhighlight_all(&mut self) {
+        for row in &mut self.rows {
             row.is_highlighted = false;
         }
     }
@@ -86,6 +150,7 @@ impl Document {
             return;
         }
         self.dirty = true;
+        self.record(at.y..at.y + 2);
         if at.x == self.rows[at.y].len() && at.y + 1 < len {
             let next_row = self.rows.remove(at.y + 1);
             let row = &mut self.rows[at.y];
@@ -94,25 +159,186 @@ impl Document {
             let row = &mut self.rows[at.y];
             row.delete(at.x);
         }
-        self.unhighlight_rows(at.y);
+        self.unhighlight_row(at.y);
     }
     pub fn save(&mut self) -> Result<(), Error> {
         if let Some(file_name) = &self.file_name {
             let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
+            let file_type = FileType::from(file_name);
+            if file_type.name() != self.file_type.name() {
+                self.unhighlight_all();
+            }
+            self.file_type = file_type;
             for row in &mut self.rows {
                 file.write_all(row.as_bytes())?;
                 file.write_all(b"\n")?;
             }
             self.dirty = false;
         }
+        self.disk_modified = self.file_name.as_deref().and_then(modified);
+        if let Some(path) = self.autosave_path() {
+            let _ = fs::remove_file(path);
+        }
         Ok(())
     }
+    // `#notes.org#` next to `notes.org`, like Emacs. It is removed again
+    // when the file is saved.
+    pub fn autosave_path(&self) -> Option<PathBuf> {
+        let path = Path::new(self.file_name.as_ref()?);
+        let name = path.file_name()?.to_string_lossy();
+        Some(path.with_file_name(format!("#{}#", name)))
+    }
+    pub fn autosave(&self) -> Result<(), Error> {
+        match self.autosave_path() {
+            Some(path) => fs::write(path, self.text()),
+            None => Ok(()),
+        }
+    }
+    // Whether the file was modified by another program since it was last
+    // read or written here. Each change is only reported once.
+    pub fn changed_on_disk(&mut self) -> bool {
+        let Some(current) = self.file_name.as_deref().and_then(modified) else {
+            return false;
+        };
+        let changed = self.disk_modified.is_some_and(|known| known != current);
+        self.disk_modified = Some(current);
+        changed
+    }
+    // Replaces the rows in `range` with `lines`.
+    pub fn replace_rows(&mut self, range: Range<usize>, lines: &[String]) {
+        let start = range.start.min(self.rows.len());
+        let end = range.end.clamp(start, self.rows.len());
+        self.record(start..end);
+        self.rows
+            .splice(start..end, lines.iter().map(|line| Row::from(line.as_str())));
+        self.dirty = true;
+    }
+    // Remembers `rows` before they are edited, widening the change since
+    // `take_change` to cover them. Rows already in it are not copied again.
+    fn record(&mut self, rows: Range<usize>) {
+        let len = self.rows.len();
+        let start = rows.start.min(len);
+        let end = rows.end.clamp(start, len);
+        let change = self.change.get_or_insert(Change {
+            start,
+            old: Vec::new(),
+            after: len.saturating_sub(start),
+        });
+        let changed_end = len.saturating_sub(change.after);
+        let new_start = start.min(change.start);
+        let new_end = end.max(changed_end);
+        let above = self.rows.get(new_start..change.start).unwrap_or_default();
+        let below = self.rows.get(changed_end..new_end).unwrap_or_default();
+        change
+            .old
+            .splice(0..0, above.iter().map(|row| row.as_str().to_string()));
+        change
+            .old
+            .extend(below.iter().map(|row| row.as_str().to_string()));
+        change.start = new_start;
+        change.after = len.saturating_sub(new_end);
+    }
+    // The rows edited since the last call, unless they are back to what
+    // they were.
+    pub fn take_change(&mut self) -> Option<Change> {
+        let change = self.change.take()?;
+        let end = self.rows.len().saturating_sub(change.after);
+        let rows = self.rows.get(change.start..end).unwrap_or_default();
+        let unchanged = rows.len() == change.old.len()
+            && rows.iter().zip(&change.old).all(|(row, old)| row.as_str() == old);
+        (!unchanged).then_some(change)
+    }
+    // Puts back the rows `change` replaced and returns the change that
+    // redoes it. Rows outside of it keep their highlighting.
+    pub fn revert(&mut self, change: Change) -> Change {
+        let start = change.start.min(self.rows.len());
+        let end = self.rows.len().saturating_sub(change.after).max(start);
+        let new = self
+            .rows
+            .splice(start..end, change.old.iter().map(|line| Row::from(line.as_str())))
+            .map(|row| row.as_str().to_string())
+            .collect();
+        self.dirty = true;
+        Change {
+            start,
+            old: new,
+            after: change.after,
+        }
+    }
+    // The text from `start` up to `end`, with rows joined by newlines.
+    pub fn text_between(&self, start: &Position, end: &Position) -> String {
+        let mut text = String::new();
+        for y in start.y..=end.y {
+            let Some(row) = self.rows.get(y) else {
+                break;
+            };
+            if y > start.y {
+                text.push('\n');
+            }
+            let from = if y == start.y { start.x } else { 0 };
+            let to = if y == end.y { end.x } else { row.len() };
+            text.push_str(row.slice(from, to));
+        }
+        text
+    }
+    // Removes the text from `start` up to `end`, joining their rows.
+    pub fn delete_between(&mut self, start: &Position, end: &Position) {
+        let Some(first) = self.rows.get(start.y) else {
+            return;
+        };
+        let mut line = first.slice(0, start.x).to_string();
+        if let Some(last) = self.rows.get(end.y) {
+            line.push_str(last.slice(end.x, last.len()));
+        }
+        self.replace_rows(start.y..end.y.saturating_add(1), &[line]);
+    }
+    // Inserts `text`, which may span rows, at `at`. Returns the position
+    // after it.
+    pub fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        let (head, tail) = self.rows.get(at.y).map_or_else(Default::default, |row| {
+            (
+                row.slice(0, at.x).to_string(),
+                row.slice(at.x, row.len()).to_string(),
+            )
+        });
+        let mut lines: Vec<String> = text.split('\n').map(String::from).collect();
+        let last = lines.len().saturating_sub(1);
+        let mut end = Position {
+            x: lines.last().map_or(0, |line| line.graphemes(true).count()),
+            y: at.y.saturating_add(last),
+        };
+        if last == 0 {
+            end.x = end.x.saturating_add(head.graphemes(true).count());
+        }
+        if let Some(first) = lines.first_mut() {
+            first.insert_str(0, &head);
+        }
+        if let Some(line) = lines.last_mut() {
+            line.push_str(&tail);
+        }
+        let replaced = if at.y < self.rows.len() { 1 } else { 0 };
+        self.replace_rows(at.y..at.y.saturating_add(replaced), &lines);
+        end
+    }
+    pub fn lines(&self) -> Vec<String> {
+        self.rows.iter().map(|row| row.as_str().to_string()).collect()
+    }
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in &self.rows {
+            text.push_str(row.as_str());
+            text.push('\n');
+        }
+        text
+    }
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
     #[allow(clippy::indexing_slicing)]
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+    pub fn find(&self, pattern: &Regex, at: &Position, direction: SearchDirection) -> Option<Position> {
         if at.y >= self.rows.len() {
             return None;
         }
@@ -130,7 +356,7 @@ impl Document {
         };
         for _ in start..end {
             if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(&query, position.x, direction) {
+                if let Some(x) = row.find(pattern, position.x, direction) {
                     position.x = x;
                     return Some(position);
                 }
@@ -147,8 +373,15 @@ impl Document {
         }
         None
     }
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
-        let mut start_with_comment = false;
+    // Highlights the rows up to `until`, skipping rows that are highlighted
+    // already and start in the same state as last time. After an edit, this
+    // re-highlights from the edited row until the state converges again.
+    pub fn highlight(&mut self, word: &Option<Regex>, until: Option<usize>) {
+        let pattern = word.as_ref().map(|word| word.as_str().to_string());
+        if pattern != self.highlighted_pattern {
+            self.highlighted_pattern = pattern;
+            self.unhighlight_all();
+        }
         let until = if let Some(until) = until {
             if until.saturating_add(1) < self.rows.len() {
                 until.saturating_add(1)
@@ -158,13 +391,188 @@ impl Document {
         } else {
             self.rows.len()
         };
+        let is_org = self.file_type.is_org();
+        let options = self.file_type.highlighting_options();
+        // File type of the source block the last highlighted row was in.
+        let mut block_type: Option<(String, FileType)> = None;
+        let mut state = State::default();
         #[allow(clippy::indexing_slicing)]
         for row in &mut self.rows[..until] {
-            start_with_comment = row.highlight(
-                &self.file_type.highlighting_options(),
-                word,
-                start_with_comment,
-            );
+            if row.is_highlighted && row.start_state == state {
+                state.clone_from(&row.end_state);
+                continue;
+            }
+            let line = row.as_str().trim();
+            let begin = src_block_language(line)
+                .filter(|_| is_org)
+                .map(ToString::to_string);
+            let is_end = line.eq_ignore_ascii_case("#+end_src");
+            // Source blocks in Org files are highlighted as their own
+            // language. Multiline state is reset at both ends of a block.
+            let end_state = match &state.block {
+                None if begin.is_some() => {
+                    row.highlight(options, word, false);
+                    State {
+                        block: begin,
+                        in_comment: false,
+                    }
+                }
+                Some(_) if is_end => {
+                    row.highlight(options, word, false);
+                    State::default()
+                }
+                Some(language) => {
+                    if block_type.as_ref().is_none_or(|(cached, _)| cached != language) {
+                        block_type = Some((language.clone(), FileType::from_language(language)));
+                    }
+                    let block_options = block_type
+                        .as_ref()
+                        .map_or(options, |(_, file_type)| file_type.highlighting_options());
+                    State {
+                        block: Some(language.clone()),
+                        in_comment: row.highlight(block_options, word, state.in_comment),
+                    }
+                }
+                None => State {
+                    block: None,
+                    in_comment: row.highlight(options, word, state.in_comment),
+                },
+            };
+            row.start_state = std::mem::replace(&mut state, end_state);
+            row.end_state.clone_from(&state);
+            row.is_highlighted = true;
         }
     }
 }
+
+fn modified(file_name: &str) -> Option<SystemTime> {
+    fs::metadata(file_name).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn src_block_language(line: &str) -> Option<&str> {
+    let prefix = line.get(..11)?;
+    if !prefix.eq_ignore_ascii_case("#+begin_src") {
+        return None;
+    }
+    let rest = line.get(11..)?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.split_whitespace().next().unwrap_or(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::Type;
+
+    #[test]
+    fn test_highlight_src_blocks() {
+        let text = "fn 1\n#+BEGIN_SRC rust\nfn /* open\n#+END_SRC\nfn 1\n#+begin_src rust\nlet x = 1;\n#+end_src\n";
+        let mut document = Document::from_contents("notes.org", text, false);
+        document.highlight(&None, None);
+        let types = |index: usize| document.row(index).unwrap().highlighting().to_vec();
+        // Outside of blocks, the Org rules apply.
+        assert_eq!(types(0), vec![Type::None; 4]);
+        assert_eq!(types(2)[0], Type::PrimaryKeywords);
+        assert_eq!(types(2)[3], Type::MultilineComment);
+        // The unterminated comment does not leak out of its block.
+        assert_eq!(types(4), vec![Type::None; 4]);
+        assert_eq!(types(6)[0], Type::PrimaryKeywords);
+        assert_eq!(types(6)[8], Type::Number);
+    }
+
+    #[test]
+    fn test_highlight_until_state_converges() {
+        let text = "fn main() {}\n".repeat(1000);
+        let mut document = Document::from_contents("main.rs", &text, false);
+        document.highlight(&None, None);
+        let type_at =
+            |document: &Document, index: usize| document.row(index).unwrap().highlighting()[0];
+        // An edit that leaves the state unchanged only invalidates its row.
+        document.insert(&Position { x: 0, y: 10 }, ' ');
+        document.highlight(&None, Some(20));
+        assert!(document.row(11).unwrap().is_highlighted);
+        assert!(document.row(999).unwrap().is_highlighted);
+        // Opening a comment carries on to the rows below, even past `until`.
+        document.insert(&Position { x: 0, y: 10 }, '*');
+        document.insert(&Position { x: 0, y: 10 }, '/');
+        document.highlight(&None, Some(20));
+        assert_eq!(type_at(&document, 20), Type::MultilineComment);
+        document.highlight(&None, None);
+        assert_eq!(type_at(&document, 999), Type::MultilineComment);
+        // Closing it again converges back.
+        document.delete(&Position { x: 0, y: 10 });
+        document.highlight(&None, None);
+        assert_eq!(type_at(&document, 999), Type::PrimaryKeywords);
+    }
+
+    #[test]
+    fn test_change_covers_edited_rows() {
+        let lines: Vec<String> = ["a", "b", "c", "d", "e"].map(String::from).to_vec();
+        let mut document = Document::from_lines(&lines, FileType::default());
+        document.highlight(&None, None);
+        document.insert(&Position { x: 1, y: 1 }, 'x');
+        document.insert(&Position { x: 0, y: 3 }, '\n');
+        let change = document.take_change().unwrap();
+        assert_eq!((change.start, change.old.as_slice(), change.after), (1, &lines[1..4], 1));
+        assert!(document.take_change().is_none());
+        // Only the changed rows are put back; the others stay highlighted.
+        let redo = document.revert(change);
+        assert_eq!(document.lines(), lines);
+        assert!(document.row(0).unwrap().is_highlighted);
+        assert!(document.row(4).unwrap().is_highlighted);
+        assert!(!document.row(1).unwrap().is_highlighted);
+        document.revert(redo);
+        assert_eq!(document.lines(), ["a", "bx", "c", "", "d", "e"]);
+        // Edits that cancel out are no change.
+        document.insert(&Position { x: 0, y: 0 }, 'y');
+        document.delete(&Position { x: 0, y: 0 });
+        assert!(document.take_change().is_none());
+    }
+
+    #[test]
+    fn test_open_as_org_keeps_existing_files() {
+        let directory = std::env::temp_dir().join(format!("orgonaut-as-org-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let markdown = directory.join("notes.md");
+        let org = directory.join("notes.org");
+        fs::write(&markdown, "# One\n").unwrap();
+        let document = Document::open_as_org(&markdown.to_string_lossy()).unwrap();
+        assert_eq!(document.file_name, Some(org.to_string_lossy().into_owned()));
+        assert_eq!(document.lines(), ["* One"]);
+        // Neither an existing Org file nor the source itself is named.
+        fs::write(&org, "* Two\n").unwrap();
+        let document = Document::open_as_org(&markdown.to_string_lossy()).unwrap();
+        assert_eq!(document.file_name, None);
+        assert!(document.is_dirty());
+        let document = Document::open_as_org(&org.to_string_lossy()).unwrap();
+        assert_eq!(document.file_name, None);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_autosave_and_changes_on_disk() {
+        let directory =
+            std::env::temp_dir().join(format!("orgonaut-autosave-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("notes.org");
+        fs::write(&path, "* One\n").unwrap();
+        let mut document = Document::open(&path.to_string_lossy()).unwrap();
+        assert!(!document.changed_on_disk());
+        let autosave = document.autosave_path().unwrap();
+        assert_eq!(autosave, directory.join("#notes.org#"));
+        document.insert(&Position { x: 5, y: 0 }, '!');
+        document.autosave().unwrap();
+        assert_eq!(fs::read_to_string(&autosave).unwrap(), "* One!\n");
+        document.save().unwrap();
+        assert!(!autosave.exists());
+        assert!(!document.changed_on_disk());
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(document.changed_on_disk());
+        assert!(!document.changed_on_disk());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
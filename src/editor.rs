@@ -1,16 +1,54 @@
+use crate::babel;
+use crate::backend::Event;
+use crate::clipboard::{Clipboard, Selection};
+use crate::document::Change;
+use crate::ex::{self, History};
+use crate::export;
+use crate::keys;
+use crate::org::{self, OrgDocument};
+use crate::tangle;
+use crate::timestamp::Time;
+use crate::register::{self, Register, Registers};
+use crate::search;
+use crate::vim::{self, Action, Insert, Motion, Operator, Parse, Span, Visual};
 use crate::Document;
+use crate::FileType;
 use crate::Row;
+use crate::screen::{Rgb, Screen, Style};
 use crate::Terminal;
+use regex::Regex;
+use std::collections::VecDeque;
 use std::env;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
-use termion::color;
+use std::time::{SystemTime, UNIX_EPOCH};
 use termion::event::Key;
+use unicode_width::UnicodeWidthStr;
 
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
+const STATUS_FG_COLOR: Rgb = Rgb(63, 63, 63);
+const STATUS_BG_COLOR: Rgb = Rgb(239, 239, 239);
+const SELECTION_BG_COLOR: Rgb = Rgb(68, 71, 90);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const QUIT_TIMES: u8 = 3;
+const DEFAULT_TAB_STOP: usize = 8;
+const STATUS_MESSAGE_DURATION: Duration = Duration::from_secs(5);
+// Idle time after an edit before the buffer is autosaved.
+const AUTOSAVE_DELAY: Duration = Duration::from_secs(30);
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// The columns `>` and `<` shift rows by, as Org nests list items.
+const DEFAULT_SHIFT_WIDTH: usize = 2;
+// Undo steps kept.
+const UNDO_LEVELS: usize = 100;
+// Macros run without reading a key from the terminal before the rest are
+// dropped, which stops a macro that runs itself.
+const MACRO_LIMIT: usize = 10_000;
+
+// The result of a background job, applied to the editor on the main thread.
+type Job = Box<dyn FnOnce(&mut Editor) + Send>;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
@@ -22,9 +60,45 @@ pub enum SearchDirection {
 pub enum Mode {
     Command,
     Insert,
+    Visual,
+    VisualLine,
+    VisualBlock,
 }
 
-#[derive(Default, Clone)]
+impl Mode {
+    fn visual(self) -> Option<Visual> {
+        match self {
+            Self::Visual => Some(Visual::Characters),
+            Self::VisualLine => Some(Visual::Lines),
+            Self::VisualBlock => Some(Visual::Block),
+            Self::Command | Self::Insert => None,
+        }
+    }
+}
+
+// How rows longer than the window are shown.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Wrap {
+    // Scrolled horizontally.
+    None,
+    // Soft-wrapped at the window's width.
+    Characters,
+    // Soft-wrapped after whitespace where possible.
+    Words,
+}
+
+impl Wrap {
+    fn from(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Self::None),
+            "char" => Some(Self::Characters),
+            "word" => Some(Self::Words),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Debug)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -43,6 +117,78 @@ impl StatusMessage {
     }
 }
 
+// A change and the cursor before it, for undo and redo.
+struct Step {
+    change: Change,
+    cursor: Position,
+}
+
+// The answers to query-replace.
+#[derive(PartialEq, Clone, Copy)]
+enum Reply {
+    Yes,
+    No,
+    // This one and the rest without asking.
+    All,
+    // This one and no more.
+    Last,
+    Quit,
+}
+
+// What the editor reads from the terminal.
+#[derive(Clone)]
+enum Input {
+    Key(Key),
+    Paste(String),
+}
+
+// The last put, while the kill ring can still be cycled through in its
+// place.
+struct LastPut {
+    // The rows the put changed, and what they held before.
+    rows: Range<usize>,
+    original: Vec<String>,
+    cursor: Position,
+    before: bool,
+    count: usize,
+    // The kill ring entry that was put.
+    kill: usize,
+}
+
+// A command that changed the buffer, for `.`, with what was typed in insert
+// mode after it.
+#[derive(Clone)]
+struct LastChange {
+    command: vim::Command,
+    inserted: Vec<Input>,
+}
+
+// An insert started from a visual block, copied to the block's other rows
+// when insert mode ends.
+struct BlockInsert {
+    y: usize,
+    // The rows it is copied to, and the display column it goes in on them;
+    // `usize::MAX` for the end of each row. Rows that end before the
+    // column are padded with spaces.
+    others: Vec<usize>,
+    column: usize,
+    // Row `y` and the index in it when insert mode started.
+    original: String,
+    at: usize,
+    document_len: usize,
+}
+
+// The document a source block is being edited from, restored when the
+// block's buffer is closed.
+struct EditSpecial {
+    parent: Document,
+    cursor_position: Position,
+    offset: Position,
+    // The lines of the block's contents in `parent`.
+    range: Range<usize>,
+    indentation: String,
+}
+
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
@@ -50,22 +196,78 @@ pub struct Editor {
     offset: Position,
     document: Document,
     status_message: StatusMessage,
+    // In soft-wrap mode nothing scrolls horizontally, and `offset.x` is the
+    // number of screen lines of row `offset.y` scrolled past instead.
+    wrap: Wrap,
     quit_times: u8,
-    highlighted_word: Option<String>,
+    // Matches of the last search are highlighted until `:noh`.
+    highlighted_pattern: Option<Regex>,
+    search_options: search::Options,
+    // The last pattern searched for, and which way.
+    last_search: Option<(String, SearchDirection)>,
     mode: Mode,
+    edit_special: Option<EditSpecial>,
+    screen: Screen,
+    last_key: Instant,
+    // Whether the buffer was autosaved since the last key press.
+    autosaved: bool,
+    last_file_check: Instant,
+    job_sender: Sender<Job>,
+    job_receiver: Receiver<Job>,
+    running_jobs: usize,
+    tab_stop: usize,
+    shift_width: usize,
+    // The width of plain-text exports.
+    text_width: usize,
+    // Whether soft-wrapping is at words once turned on with `:set wrap`.
+    linebreak: bool,
+    // Command mode keys that do not make a complete command yet.
+    pending_keys: Vec<Key>,
+    registers: Registers,
+    last_put: Option<LastPut>,
+    clipboard: Clipboard,
+    // Where the selection started in visual mode, and whether a block
+    // stretches to the end of every row after `$`.
+    visual_anchor: Position,
+    visual_to_end: bool,
+    block_insert: Option<BlockInsert>,
+    // Input to handle before reading the terminal: what `.` and macros
+    // replay.
+    typeahead: VecDeque<Input>,
+    last_change: Option<LastChange>,
+    // The change being made while insert mode lasts.
+    change_in_progress: Option<LastChange>,
+    // The register a macro is being recorded into, and the keys so far.
+    recording: Option<(char, Vec<Key>)>,
+    last_macro: Option<char>,
+    macros_run: usize,
+    command_history: History,
+    // The rows of the last selection, for `'<` and `'>`.
+    last_selection: Option<Range<usize>>,
+    // Whether a `:g` is running, which cannot be nested.
+    in_global: bool,
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    grouping_undo: bool,
+    // Where the cursor was when the changes since the last step began.
+    step_cursor: Position,
+    // How many undo steps there were when the document was last saved.
+    saved_step: Option<usize>,
+    // The row and graphemes of the match query-replace asks about.
+    current_match: Option<(usize, Range<usize>)>,
 }
 
 impl Editor {
     pub fn run(&mut self) {
         loop {
             if let Err(error) = self.refresh_screen() {
-                die(error);
+                die(&mut self.terminal, &error);
             }
             if self.should_quit {
                 break;
             }
             if let Err(error) = self.process_keypress() {
-                die(error);
+                die(&mut self.terminal, &error);
             }
         }
     }
@@ -74,9 +276,31 @@ impl Editor {
         let mut initial_status =
             String::from("COMMAND MODE: i=insert | a=append | Ctrl-F=find | Ctrl-S=save | Ctrl-Q=quit");
 
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(file_name);
+        let as_org = args.iter().skip(1).any(|arg| arg == "--as-org");
+        let tab_stop = args
+            .iter()
+            .skip(1)
+            .find_map(|arg| arg.strip_prefix("--tab-stop="))
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|tab_stop| *tab_stop > 0);
+        let file_name = args.iter().skip(1).find(|arg| !arg.starts_with("--"));
+
+        let document = if let Some(file_name) = file_name {
+            let doc = if as_org {
+                Document::open_as_org(file_name)
+            } else {
+                Document::open(file_name)
+            };
             if let Ok(doc) = doc {
+                if as_org && doc.file_name.is_none() {
+                    initial_status = format!(
+                        "Converted {} to Org; {} exists, so Ctrl-S asks for a name",
+                        file_name,
+                        Path::new(file_name).with_extension("org").display()
+                    );
+                } else if as_org {
+                    initial_status = format!("Converted {} to Org; Ctrl-S saves it as Org", file_name);
+                }
                 doc
             } else {
                 initial_status = format!("ERR: Could not open file: {}", file_name);
@@ -86,49 +310,131 @@ impl Editor {
             Document::default()
         };
 
+        let terminal = Terminal::default().expect("Failed to initialize terminal");
+        // Org files are mostly prose, so they are wrapped at words unless
+        // asked otherwise.
+        let wrap = args
+            .iter()
+            .skip(1)
+            .find_map(|arg| arg.strip_prefix("--wrap="))
+            .and_then(Wrap::from)
+            .unwrap_or(if document.file_type() == "Org" {
+                Wrap::Words
+            } else {
+                Wrap::None
+            });
+        let mut editor = Self::new(terminal, document, initial_status);
+        editor.tab_stop = tab_stop.unwrap_or(DEFAULT_TAB_STOP);
+        editor.wrap = wrap;
+        editor.linebreak = wrap != Wrap::Characters;
+        editor.clipboard = Clipboard::from_env();
+        if let Some(file) = ex::history_file() {
+            editor.command_history = History::load(file);
+        }
+        editor
+    }
+    pub fn new(terminal: Terminal, document: Document, status: String) -> Self {
+        // The screen includes the status and message bars.
+        let screen = Screen::new(
+            terminal.size().width as usize,
+            (terminal.size().height as usize).saturating_add(2),
+        );
+        let (job_sender, job_receiver) = mpsc::channel();
+        let saved_step = (!document.is_dirty()).then_some(0);
         Self {
             should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
+            terminal,
             document,
             cursor_position: Position::default(),
             offset: Position::default(),
-            status_message: StatusMessage::from(initial_status),
+            status_message: StatusMessage::from(status),
+            wrap: Wrap::None,
             quit_times: QUIT_TIMES,
-            highlighted_word: None,
+            highlighted_pattern: None,
+            search_options: search::Options::default(),
+            last_search: None,
             mode: Mode::Command, // Start in Command Mode
+            edit_special: None,
+            screen,
+            last_key: Instant::now(),
+            autosaved: false,
+            last_file_check: Instant::now(),
+            job_sender,
+            job_receiver,
+            running_jobs: 0,
+            tab_stop: DEFAULT_TAB_STOP,
+            shift_width: DEFAULT_SHIFT_WIDTH,
+            text_width: export::Settings::default().width,
+            linebreak: true,
+            pending_keys: Vec::new(),
+            registers: Registers::default(),
+            last_put: None,
+            clipboard: Clipboard::default(),
+            visual_anchor: Position::default(),
+            visual_to_end: false,
+            block_insert: None,
+            typeahead: VecDeque::new(),
+            last_change: None,
+            change_in_progress: None,
+            recording: None,
+            last_macro: None,
+            macros_run: 0,
+            command_history: History::default(),
+            last_selection: None,
+            in_global: false,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            grouping_undo: false,
+            step_cursor: Position::default(),
+            saved_step,
+            current_match: None,
         }
     }
 
     fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
         if self.should_quit {
-            Terminal::clear_screen();
-            println!("Goodbye.\r");
-        } else {
-            self.document.highlight(
-                &self.highlighted_word,
-                Some(
-                    self.offset
-                        .y
-                        .saturating_add(self.terminal.size().height as usize),
-                ),
-            );
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            });
+            self.terminal.clear_screen()?;
+            self.terminal.print("Goodbye.\r\n")?;
+            return self.terminal.flush();
         }
-        Terminal::cursor_show();
-        Terminal::flush()
+        self.document.highlight(
+            &self.highlighted_pattern,
+            Some(
+                self.offset
+                    .y
+                    .saturating_add(self.terminal.size().height as usize),
+            ),
+        );
+        self.screen.clear();
+        self.draw_rows();
+        self.draw_status_bar();
+        self.draw_message_bar();
+        let cursor = if self.wrap == Wrap::None {
+            Position {
+                x: self.cursor_column().saturating_sub(self.offset.x),
+                y: self.cursor_position.y.saturating_sub(self.offset.y),
+            }
+        } else {
+            let width = self.terminal.size().width as usize;
+            Position {
+                x: self.wrapped_cursor().1.min(width.saturating_sub(1)),
+                y: self.lines_above_cursor(),
+            }
+        };
+        self.screen.set_cursor(cursor);
+        self.terminal.draw(&mut self.screen)
     }
     fn save(&mut self) {
+        if self.edit_special.is_some() {
+            self.write_back_edit_special();
+            return;
+        }
         if self.document.file_name.is_none() {
             let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
-            if new_name.is_none() {
+            let overwrite = |editor: &mut Self, name: &str| {
+                !Path::new(name).exists() || editor.confirm(&format!("{} exists. Overwrite? (y/n) ", name))
+            };
+            if !new_name.as_deref().is_some_and(|name| overwrite(self, name)) {
                 self.status_message = StatusMessage::from("Save aborted.".to_string());
                 return;
             }
@@ -136,64 +442,1705 @@ impl Editor {
         }
 
         if self.document.save().is_ok() {
+            self.mark_saved_step();
             self.status_message = StatusMessage::from("File saved successfully.".to_string());
         } else {
             self.status_message = StatusMessage::from("Error writing file!".to_string());
         }
     }
-    fn search(&mut self) {
-        let old_position = self.cursor_position.clone();
-        let mut direction = SearchDirection::Forward;
-        let query = self
-            .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
-                |editor, key, query| {
-                    let mut moved = false;
-                    match key {
-                        Key::Right | Key::Down => {
-                            direction = SearchDirection::Forward;
-                            editor.move_cursor(Key::Right);
-                            moved = true;
-                        }
-                        Key::Left | Key::Up => direction = SearchDirection::Backward,
-                        _ => direction = SearchDirection::Forward,
-                    }
-                    if let Some(position) =
-                        editor
-                            .document
-                            .find(&query, &editor.cursor_position, direction)
-                    {
-                        editor.cursor_position = position;
-                        editor.scroll();
-                    } else if moved {
-                        editor.move_cursor(Key::Left);
+    fn export(&mut self) {
+        let Some(file_name) = self.document.file_name.clone() else {
+            self.status_message =
+                StatusMessage::from("Save the file before exporting.".to_string());
+            return;
+        };
+        let format = self.prompt("Export as (ascii, utf8, ics, json): ", |_, _, _| {}).unwrap_or(None);
+        let Some(format) = format else {
+            self.status_message = StatusMessage::from("Export aborted.".to_string());
+            return;
+        };
+        let Some(format) = export::Format::from(&format) else {
+            self.status_message =
+                StatusMessage::from(format!("Unknown export format: {}", format));
+            return;
+        };
+        let output = export::output_file_name(&file_name, format);
+        if same_file(&output, &file_name) {
+            self.status_message =
+                StatusMessage::from(format!("Export would overwrite the document itself: {}", output));
+            return;
+        }
+        if Path::new(&output).exists() && !self.confirm(&format!("{} exists. Overwrite? (y/n) ", output)) {
+            self.status_message = StatusMessage::from("Export aborted.".to_string());
+            return;
+        }
+        let org = OrgDocument::parse(&self.document.text());
+        let settings = export::Settings {
+            width: self.text_width,
+        };
+        let contents = export::export(&file_name, &org, format, &settings);
+        self.status_message = match std::fs::write(&output, contents) {
+            Ok(()) => StatusMessage::from(format!("Exported to {}", output)),
+            Err(error) => StatusMessage::from(format!("Error exporting to {}: {}", output, error)),
+        };
+    }
+    fn confirm(&mut self, question: &str) -> bool {
+        let answer = self.prompt(question, |_, _, _| {}).unwrap_or(None);
+        answer.is_some_and(|answer| answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+    }
+    fn execute_block(&mut self) {
+        let org = OrgDocument::parse(&self.document.text());
+        let Some(block) = babel::SourceBlock::at(&org, self.cursor_position.y) else {
+            self.status_message = StatusMessage::from("No source block here.".to_string());
+            return;
+        };
+        if !self.confirm(&format!("Evaluate this {} block? (y/n) ", block.language)) {
+            self.status_message = StatusMessage::from("Evaluation aborted.".to_string());
+            return;
+        }
+        let directory = self
+            .document
+            .file_name
+            .as_deref()
+            .and_then(|file_name| Path::new(file_name).parent().map(Path::to_path_buf));
+        self.status_message = StatusMessage::from(format!("Evaluating {} block...", block.language));
+        self.spawn_job(move || {
+            let output = babel::execute(&block, directory.as_deref());
+            Box::new(move |editor: &mut Self| editor.insert_results(&block, output))
+        });
+    }
+    fn insert_results(&mut self, block: &babel::SourceBlock, output: Result<String, String>) {
+        let output = match output {
+            Ok(output) => output,
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                return;
+            }
+        };
+        if block.is_silent() {
+            let summary = output.lines().collect::<Vec<&str>>().join(" ");
+            self.status_message = StatusMessage::from(format!("Result: {}", summary));
+            return;
+        }
+        // The buffer may have been edited while the block was running.
+        let org = OrgDocument::parse(&self.document.text());
+        if !babel::SourceBlock::at(&org, block.range.start)
+            .is_some_and(|current| current.range == block.range && current.body == block.body)
+        {
+            self.status_message = StatusMessage::from(
+                "The block changed while it was running; results discarded.".to_string(),
+            );
+            return;
+        }
+        let results = babel::format_results(block.name.as_deref(), &output);
+        self.checkpoint();
+        let text = self.document.text();
+        let lines: Vec<&str> = text.lines().collect();
+        match babel::results_range(&lines, block.range.end) {
+            Some(range) => self.document.replace_rows(range, &results),
+            None => {
+                let mut inserted = vec![String::new()];
+                inserted.extend(results);
+                let end = block.range.end;
+                self.document.replace_rows(end..end, &inserted);
+            }
+        }
+        self.status_message = StatusMessage::from("Code block evaluated.".to_string());
+    }
+    fn tangle(&mut self) {
+        let Some(file_name) = self.document.file_name.clone() else {
+            self.status_message =
+                StatusMessage::from("Save the file before tangling.".to_string());
+            return;
+        };
+        let org = OrgDocument::parse(&self.document.text());
+        self.status_message = match tangle::tangle(&org, &file_name) {
+            Ok(summary) | Err(summary) => StatusMessage::from(summary),
+        };
+    }
+    fn open_edit_special(&mut self) {
+        let org = OrgDocument::parse(&self.document.text());
+        let Some(block) = babel::SourceBlock::at(&org, self.cursor_position.y) else {
+            self.status_message = StatusMessage::from("No source block here.".to_string());
+            return;
+        };
+        let range = block.range.start.saturating_add(1)..block.range.end.saturating_sub(1);
+        let contents: Vec<String> = self
+            .document
+            .lines()
+            .get(range.clone())
+            .unwrap_or(&[])
+            .to_vec();
+        let (lines, indentation) = babel::dedent(&contents);
+        let file_type = FileType::from_language(&block.language);
+        let parent = std::mem::replace(&mut self.document, Document::from_lines(&lines, file_type));
+        self.forget_undo();
+        let cursor_position = std::mem::take(&mut self.cursor_position);
+        if range.contains(&cursor_position.y) {
+            self.cursor_position = Position {
+                x: cursor_position.x.saturating_sub(indentation.len()),
+                y: cursor_position.y.saturating_sub(range.start),
+            };
+        }
+        self.edit_special = Some(EditSpecial {
+            parent,
+            cursor_position,
+            offset: std::mem::take(&mut self.offset),
+            range,
+            indentation,
+        });
+        self.scroll();
+        self.status_message = StatusMessage::from(format!(
+            "Editing {} block. Ctrl-S writes back, Ctrl-O finishes, Ctrl-Q aborts.",
+            block.language
+        ));
+    }
+    // Copies the edited block back into the document it came from.
+    fn write_back_edit_special(&mut self) {
+        let Some(edit) = self.edit_special.as_mut() else {
+            return;
+        };
+        if !self.document.is_dirty() {
+            return;
+        }
+        let lines = babel::reindent(&self.document.lines(), &edit.indentation);
+        edit.parent.replace_rows(edit.range.clone(), &lines);
+        edit.range = edit.range.start..edit.range.start.saturating_add(lines.len());
+        self.document.mark_clean();
+        self.mark_saved_step();
+        self.status_message = StatusMessage::from("Block written back.".to_string());
+    }
+    fn close_edit_special(&mut self, write_back: bool) {
+        if write_back {
+            self.write_back_edit_special();
+        }
+        let Some(edit) = self.edit_special.take() else {
+            return;
+        };
+        self.document = edit.parent;
+        self.forget_undo();
+        self.cursor_position = edit.cursor_position;
+        self.offset = edit.offset;
+        self.quit_times = QUIT_TIMES;
+        self.scroll();
+        let message = if write_back { "Block updated." } else { "Edit aborted." };
+        self.status_message = StatusMessage::from(message.to_string());
+    }
+    // `/` and `?`. Matches are highlighted and the cursor moves to the
+    // nearest one as the pattern is typed; the arrow keys go to the next and
+    // previous ones meanwhile. `Esc` goes back.
+    fn search(&mut self, direction: SearchDirection) {
+        let start = self.cursor_position.clone();
+        let highlighted = self.highlighted_pattern.take();
+        let prompt = if direction == SearchDirection::Forward {
+            "/"
+        } else {
+            "?"
+        };
+        // The match the cursor is on, the way it was found and whether the
+        // search wrapped around to it.
+        let mut found: Option<(Position, SearchDirection, bool)> = None;
+        let pattern = self
+            .prompt(prompt, |editor, key, typed| {
+                let (from, way) = match key {
+                    Key::Right | Key::Down => {
+                        (editor.cursor_position.clone(), SearchDirection::Forward)
+                    }
+                    Key::Left | Key::Up => {
+                        (editor.cursor_position.clone(), SearchDirection::Backward)
+                    }
+                    _ => (start.clone(), direction),
+                };
+                editor.highlighted_pattern = editor
+                    .search_options
+                    .regex(typed)
+                    .ok()
+                    .filter(|_| !typed.is_empty());
+                found = editor
+                    .highlighted_pattern
+                    .as_ref()
+                    .and_then(|pattern| editor.next_match(pattern, &from, way))
+                    .map(|(position, wrapped)| (position, way, wrapped));
+                editor.cursor_position = found
+                    .as_ref()
+                    .map_or_else(|| start.clone(), |(position, _, _)| position.clone());
+                editor.scroll();
+            })
+            .unwrap_or(None);
+        let Some(pattern) = pattern else {
+            self.cursor_position = start;
+            self.highlighted_pattern = highlighted;
+            self.scroll();
+            return;
+        };
+        let message = match (self.search_options.regex(&pattern), found) {
+            (Err(error), _) => error,
+            (Ok(_), None) => format!("Pattern not found: {}", pattern),
+            (Ok(_), Some((_, way, wrapped))) => search_message(&pattern, way, wrapped),
+        };
+        self.status_message = StatusMessage::from(message);
+        self.last_search = Some((pattern, direction));
+    }
+    // `n`, or `N` when `reverse`.
+    fn search_next(&mut self, reverse: bool, count: Option<usize>) {
+        let Some((pattern, direction)) = self.last_search.clone() else {
+            self.status_message = StatusMessage::from("No previous search pattern".to_string());
+            return;
+        };
+        let regex = match self.search_options.regex(&pattern) {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                return;
+            }
+        };
+        let direction = match (direction, reverse) {
+            (_, false) => direction,
+            (SearchDirection::Forward, true) => SearchDirection::Backward,
+            (SearchDirection::Backward, true) => SearchDirection::Forward,
+        };
+        let mut position = self.cursor_position.clone();
+        let mut wrapped = false;
+        for _ in 0..count.unwrap_or(1).max(1) {
+            let Some((next, wrapping)) = self.next_match(&regex, &position, direction) else {
+                self.status_message =
+                    StatusMessage::from(format!("Pattern not found: {}", pattern));
+                return;
+            };
+            position = next;
+            wrapped |= wrapping;
+        }
+        self.cursor_position = position;
+        self.highlighted_pattern = Some(regex);
+        self.status_message = StatusMessage::from(search_message(&pattern, direction, wrapped));
+        self.scroll();
+    }
+    // The match after `from`, or before it going backward, and whether the
+    // search wrapped around the end of the document to find it.
+    fn next_match(
+        &self,
+        pattern: &Regex,
+        from: &Position,
+        direction: SearchDirection,
+    ) -> Option<(Position, bool)> {
+        let after = match direction {
+            SearchDirection::Forward => Position {
+                x: from.x.saturating_add(1),
+                y: from.y,
+            },
+            SearchDirection::Backward => from.clone(),
+        };
+        if let Some(position) = self.document.find(pattern, &after, direction) {
+            return Some((position, false));
+        }
+        let last = self.document.len().saturating_sub(1);
+        let wrapped = match direction {
+            SearchDirection::Forward => Position::default(),
+            SearchDirection::Backward => Position {
+                x: self.document.row(last).map_or(0, Row::len),
+                y: last,
+            },
+        };
+        self.document
+            .find(pattern, &wrapped, direction)
+            .map(|position| (position, true))
+    }
+    // `:` and a command line. In visual mode the command acts on the
+    // selected rows, and after a count on that many rows. `Tab` completes
+    // and `Up` and `Down` go through the lines entered before that start
+    // with what is typed.
+    fn command_line(&mut self, count: Option<usize>) {
+        let initial = if let Some(span) = self.selection() {
+            self.last_selection = Some(span.rows());
+            self.enter_command_mode();
+            "'<,'>".to_string()
+        } else {
+            match count {
+                Some(count) if count > 1 => format!(".,.+{}", count.saturating_sub(1)),
+                _ => String::new(),
+            }
+        };
+        let mut completions: Option<(Vec<String>, usize)> = None;
+        // The entry shown, and the line typed before going through them.
+        let mut browsing: Option<(usize, String)> = None;
+        let line = self
+            .prompt_from(":", initial, |editor, key, line| match key {
+                Key::Char('\t') => {
+                    let (candidates, index) = match completions.take() {
+                        Some((candidates, index)) if candidates.get(index) == Some(line) => {
+                            let next = index.saturating_add(1) % candidates.len();
+                            (candidates, next)
+                        }
+                        _ => (ex::complete(line), 0),
+                    };
+                    if let Some(candidate) = candidates.get(index) {
+                        line.clone_from(candidate);
+                    }
+                    completions = Some((candidates, index));
+                    browsing = None;
+                }
+                Key::Up | Key::Down => {
+                    let entries = editor.command_history.entries();
+                    let (index, typed) = browsing
+                        .take()
+                        .unwrap_or_else(|| (entries.len(), line.clone()));
+                    let matches = |entry: &&String| entry.starts_with(typed.as_str());
+                    let found = if key == Key::Up {
+                        entries
+                            .iter()
+                            .enumerate()
+                            .take(index)
+                            .rev()
+                            .find(|(_, entry)| matches(entry))
+                    } else {
+                        entries
+                            .iter()
+                            .enumerate()
+                            .skip(index.saturating_add(1))
+                            .find(|(_, entry)| matches(entry))
+                    };
+                    match found {
+                        Some((found, entry)) => {
+                            line.clone_from(entry);
+                            browsing = Some((found, typed));
+                        }
+                        // Past the newest entry is the line typed.
+                        None if key == Key::Down => line.clone_from(&typed),
+                        None => browsing = Some((index, typed)),
+                    }
+                    completions = None;
+                }
+                _ => {
+                    completions = None;
+                    browsing = None;
+                }
+            })
+            .unwrap_or(None);
+        let Some(line) = line else {
+            return;
+        };
+        self.command_history.add(&line);
+        self.checkpoint();
+        let grouping = std::mem::replace(&mut self.grouping_undo, true);
+        if let Err(error) = self.run_command(&line) {
+            self.status_message = StatusMessage::from(error);
+        }
+        self.grouping_undo = grouping;
+        self.scroll();
+    }
+    fn run_command(&mut self, line: &str) -> Result<(), String> {
+        let command = ex::parse(line)?;
+        let current = self.cursor_position.y;
+        let rows = match &command.range {
+            Some(range) => Some(range.resolve(
+                current,
+                self.document.len(),
+                self.last_selection.as_ref(),
+            )?),
+            None => None,
+        };
+        let line_rows = rows.clone().unwrap_or(current..current.saturating_add(1));
+        match command.action {
+            ex::Action::Goto => {
+                let y = line_rows.end.saturating_sub(1);
+                self.cursor_position = Position {
+                    x: vim::first_non_blank(&self.document, y),
+                    y,
+                };
+            }
+            ex::Action::Write { file, quit, force } => self.write(file, quit, force)?,
+            ex::Action::Quit { force } => {
+                if !force && self.document.is_dirty() {
+                    return Err("No write since last change (add ! to override)".to_string());
+                }
+                if self.edit_special.is_some() {
+                    self.close_edit_special(false);
+                } else {
+                    self.should_quit = true;
+                }
+            }
+            ex::Action::Edit { file, force } => self.edit(file, force)?,
+            ex::Action::Substitute(substitute) => self.substitute(&substitute, line_rows)?,
+            ex::Action::Global {
+                pattern,
+                invert,
+                command,
+            } => {
+                let rows = rows.unwrap_or(0..self.document.len());
+                self.global(&pattern, invert, &command, rows)?;
+            }
+            ex::Action::Delete => self.operate(Operator::Delete, Span::Lines(line_rows), None),
+            ex::Action::NoHighlight => self.highlighted_pattern = None,
+            ex::Action::Normal(text) => {
+                let keys = keys::from_text(&text);
+                self.on_rows(line_rows.collect(), |editor| {
+                    editor.run_keys(&keys);
+                    Ok(())
+                })?;
+            }
+            ex::Action::Set(settings) => {
+                let mut shown = Vec::new();
+                for setting in settings {
+                    shown.extend(self.set_option(setting)?);
+                }
+                if self.highlighted_pattern.is_some() {
+                    self.highlighted_pattern = self
+                        .last_search
+                        .as_ref()
+                        .and_then(|(pattern, _)| self.search_options.regex(pattern).ok());
+                }
+                self.status_message = StatusMessage::from(shown.join(" "));
+            }
+        }
+        Ok(())
+    }
+    // `:w`, `:wq` and `:x`. A file name names an unnamed buffer, or else
+    // writes a copy.
+    fn write(&mut self, file: Option<String>, quit: bool, force: bool) -> Result<(), String> {
+        if self.edit_special.is_some() {
+            if file.is_some() {
+                return Err("The source block is written back to its document".to_string());
+            }
+            if quit {
+                self.close_edit_special(true);
+            } else {
+                self.save();
+            }
+            return Ok(());
+        }
+        if let Some(file) = &file {
+            let own = self.document.file_name.as_deref().is_some_and(|name| same_file(name, file));
+            if !force && !own && Path::new(file).exists() {
+                return Err("File exists (add ! to override)".to_string());
+            }
+        }
+        match file {
+            Some(file) if self.document.file_name.as_deref().is_some_and(|name| !same_file(name, &file)) => {
+                std::fs::write(&file, self.document.text())
+                    .map_err(|error| format!("Error writing {}: {}", file, error))?;
+                self.status_message = StatusMessage::from(format!("Written to {}", file));
+                return Ok(());
+            }
+            Some(file) => self.document.file_name = Some(file),
+            None => (),
+        }
+        self.save();
+        if quit && !self.document.is_dirty() {
+            self.should_quit = true;
+        }
+        Ok(())
+    }
+    // `:e file` opens another file in place of this one; `:e!` reloads the
+    // file from disk.
+    fn edit(&mut self, file: Option<String>, force: bool) -> Result<(), String> {
+        if self.edit_special.is_some() {
+            return Err("Finish editing the source block first".to_string());
+        }
+        if !force && self.document.is_dirty() {
+            return Err("No write since last change (add ! to override)".to_string());
+        }
+        let Some(file) = file.or_else(|| self.document.file_name.clone()) else {
+            return Err("No file name".to_string());
+        };
+        let (document, message) = match Document::open(&file) {
+            Ok(document) => {
+                let message = format!("\"{}\" {} lines", file, document.len());
+                (document, message)
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                (Document::new_file(&file), format!("\"{}\" [New]", file))
+            }
+            Err(error) => return Err(format!("Could not open {}: {}", file, error)),
+        };
+        self.document = document;
+        self.forget_undo();
+        self.cursor_position = Position::default();
+        self.offset = Position::default();
+        self.last_put = None;
+        self.last_selection = None;
+        self.status_message = StatusMessage::from(message);
+        Ok(())
+    }
+    // `:s`, and with `confirm` query-replace, which asks before each
+    // replacement. The cursor ends on the last row changed.
+    fn substitute(
+        &mut self,
+        substitute: &ex::Substitute,
+        rows: Range<usize>,
+    ) -> Result<(), String> {
+        let pattern = self.pattern_or_last(&substitute.pattern)?;
+        let ignore_case = substitute
+            .ignore_case
+            .unwrap_or_else(|| self.search_options.ignores_case(&pattern));
+        let regex = search::regex(&pattern, false, ignore_case)?;
+        let mut ask = substitute.confirm;
+        let highlighted = self.highlighted_pattern.clone();
+        if ask {
+            self.highlighted_pattern = Some(regex.clone());
+        }
+        let mut found = false;
+        let mut done = false;
+        let mut substitutions: usize = 0;
+        let mut lines: usize = 0;
+        let mut last = None;
+        let mut y = rows.start;
+        let mut end = rows.end;
+        while y < end && !done {
+            // The byte of the row to go on matching from.
+            let mut at = 0;
+            let mut changed = false;
+            while let Some(text) = self.document.row(y).map(|row| row.as_str().to_string()) {
+                let Some(captures) = regex.captures_at(&text, at) else {
+                    break;
+                };
+                let Some(range) = captures.get(0).map(|matched| matched.range()) else {
+                    break;
+                };
+                let mut replaced = String::new();
+                captures.expand(&substitute.replacement, &mut replaced);
+                found = true;
+                let reply = if ask {
+                    self.confirm_replacement(y, range.clone(), &replaced)
+                } else {
+                    Reply::Yes
+                };
+                let mut next = range.end;
+                match reply {
+                    Reply::Quit => {
+                        done = true;
+                        break;
+                    }
+                    Reply::No => (),
+                    Reply::Yes | Reply::All | Reply::Last => {
+                        ask = ask && reply == Reply::Yes;
+                        done = reply == Reply::Last;
+                        let mut text = text;
+                        text.replace_range(range.clone(), &replaced);
+                        let replaced_rows: Vec<String> =
+                            text.split('\n').map(String::from).collect();
+                        let added = replaced_rows.len().saturating_sub(1);
+                        self.document.replace_rows(y..y.saturating_add(1), &replaced_rows);
+                        // Matching goes on after the replacement, in its last
+                        // row.
+                        let after = range.start.saturating_add(replaced.len());
+                        next = text
+                            .get(..after)
+                            .and_then(|before| before.rfind('\n'))
+                            .map_or(after, |newline| {
+                                after.saturating_sub(newline.saturating_add(1))
+                            });
+                        y = y.saturating_add(added);
+                        end = end.saturating_add(added);
+                        substitutions = substitutions.saturating_add(1);
+                        if !changed {
+                            changed = true;
+                            lines = lines.saturating_add(1);
+                        }
+                        last = Some(y);
+                    }
+                }
+                if done || !substitute.all {
+                    break;
+                }
+                // An empty match is not matched again where it was.
+                if range.is_empty() {
+                    let Some(c) = self
+                        .document
+                        .row(y)
+                        .and_then(|row| row.as_str().get(next..))
+                        .and_then(|rest| rest.chars().next())
+                    else {
+                        break;
+                    };
+                    next = next.saturating_add(c.len_utf8());
+                }
+                at = next;
+            }
+            y = y.saturating_add(1);
+        }
+        self.highlighted_pattern = highlighted;
+        if !found {
+            return Err(format!("Pattern not found: {}", pattern));
+        }
+        if let Some(y) = last {
+            self.cursor_position = Position {
+                x: vim::first_non_blank(&self.document, y),
+                y,
+            };
+        }
+        self.status_message = StatusMessage::from(format!(
+            "{} substitution{} on {} line{}",
+            substitutions,
+            if substitutions == 1 { "" } else { "s" },
+            lines,
+            if lines == 1 { "" } else { "s" },
+        ));
+        Ok(())
+    }
+    // Shows the match at bytes `range` of row `y` and asks whether to
+    // replace it with `replaced`. Running out of input stops.
+    fn confirm_replacement(&mut self, y: usize, range: Range<usize>, replaced: &str) -> Reply {
+        let graphemes = self.document.row(y).map_or(0..0, |row| {
+            row.grapheme_at(range.start)..row.grapheme_at(range.end)
+        });
+        self.cursor_position = Position {
+            x: graphemes.start,
+            y,
+        };
+        self.current_match = Some((y, graphemes));
+        self.scroll();
+        self.status_message = StatusMessage::from(format!(
+            "Replace with {}? (y/n/a/l/q)",
+            replaced.replace('\n', "^M")
+        ));
+        let reply = loop {
+            if self.refresh_screen().is_err() {
+                break Reply::Quit;
+            }
+            match self.read_input() {
+                Ok(Input::Key(Key::Char('y'))) => break Reply::Yes,
+                Ok(Input::Key(Key::Char('n'))) => break Reply::No,
+                Ok(Input::Key(Key::Char('a'))) => break Reply::All,
+                Ok(Input::Key(Key::Char('l'))) => break Reply::Last,
+                Ok(Input::Key(Key::Char('q') | Key::Esc)) | Err(_) => break Reply::Quit,
+                Ok(_) => (),
+            }
+        };
+        self.current_match = None;
+        reply
+    }
+    // `Alt-%`, as in Emacs: asks for a pattern and a replacement, then
+    // whether to replace each match from the cursor's row on, or in the
+    // selected rows. The replacements are undone together.
+    fn query_replace(&mut self) {
+        let rows = match self.selection() {
+            Some(span) => {
+                self.last_selection = Some(span.rows());
+                self.enter_command_mode();
+                span.rows()
+            }
+            None => self.cursor_position.y..self.document.len(),
+        };
+        let pattern = self.prompt("Query replace: ", |_, _, _| {}).unwrap_or(None);
+        let Some(pattern) = pattern else {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+            return;
+        };
+        // An empty replacement deletes the matches, so only `Esc` aborts.
+        let mut aborted = false;
+        let question = format!("Query replace {} with: ", pattern);
+        let replacement = self
+            .prompt(&question, |_, key, _| aborted = key == Key::Esc)
+            .unwrap_or(None);
+        if aborted {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+            return;
+        }
+        let substitute = ex::Substitute {
+            pattern,
+            replacement: ex::replacement(&replacement.unwrap_or_default()),
+            all: true,
+            ignore_case: None,
+            confirm: true,
+        };
+        self.checkpoint();
+        if let Err(error) = self.substitute(&substitute, rows) {
+            self.status_message = StatusMessage::from(error);
+        }
+        self.scroll();
+    }
+    // Starts a new undo step. Within the command line, changes belong to
+    // the step started before it.
+    fn checkpoint(&mut self) {
+        if self.grouping_undo {
+            return;
+        }
+        self.finish_step();
+        self.step_cursor = self.cursor_position.clone();
+    }
+    // Turns the rows changed since the last checkpoint into an undo step.
+    fn finish_step(&mut self) {
+        let Some(change) = self.document.take_change() else {
+            return;
+        };
+        // A save among the redo steps can no longer be reached.
+        if self.saved_step.is_some_and(|saved| saved > self.undo.len()) {
+            self.saved_step = None;
+        }
+        self.undo.push(Step {
+            change,
+            cursor: self.step_cursor.clone(),
+        });
+        let excess = self.undo.len().saturating_sub(UNDO_LEVELS);
+        self.undo.drain(..excess);
+        self.saved_step = self.saved_step.and_then(|saved| saved.checked_sub(excess));
+        self.redo.clear();
+    }
+    fn mark_saved_step(&mut self) {
+        self.finish_step();
+        self.step_cursor = self.cursor_position.clone();
+        self.saved_step = Some(self.undo.len());
+    }
+    // `u`, or `Ctrl-r` with `redo`. Returning to the saved step makes the
+    // document unmodified again.
+    fn undo(&mut self, count: Option<usize>, redo: bool) {
+        self.finish_step();
+        for _ in 0..count.unwrap_or(1).max(1) {
+            let step = if redo { self.redo.pop() } else { self.undo.pop() };
+            let Some(step) = step else {
+                let message = if redo {
+                    "Already at newest change"
+                } else {
+                    "Already at oldest change"
+                };
+                self.status_message = StatusMessage::from(message.to_string());
+                break;
+            };
+            let inverse = Step {
+                change: self.document.revert(step.change),
+                cursor: self.cursor_position.clone(),
+            };
+            if redo {
+                self.undo.push(inverse);
+            } else {
+                self.redo.push(inverse);
+            }
+            self.cursor_position = step.cursor;
+        }
+        if self.saved_step == Some(self.undo.len()) {
+            self.document.mark_clean();
+        }
+        self.step_cursor = self.cursor_position.clone();
+        self.last_put = None;
+    }
+    // Undo steps belong to one document.
+    fn forget_undo(&mut self) {
+        self.document.take_change();
+        self.undo.clear();
+        self.redo.clear();
+        self.step_cursor = self.cursor_position.clone();
+        self.saved_step = (!self.document.is_dirty()).then_some(0);
+    }
+    // An empty pattern of `:s` or `:g` is the last one searched for.
+    fn pattern_or_last(&self, pattern: &str) -> Result<String, String> {
+        if !pattern.is_empty() {
+            return Ok(pattern.to_string());
+        }
+        self.last_search
+            .as_ref()
+            .map(|(pattern, _)| pattern.clone())
+            .ok_or_else(|| "No previous search pattern".to_string())
+    }
+    // `:g`: runs `command` on each row in `rows` that matches `pattern`, or
+    // with `invert` that does not.
+    fn global(
+        &mut self,
+        pattern: &str,
+        invert: bool,
+        command: &str,
+        rows: Range<usize>,
+    ) -> Result<(), String> {
+        if self.in_global {
+            return Err("Cannot do :global recursively".to_string());
+        }
+        let pattern = self.pattern_or_last(pattern)?;
+        let ignore_case = self.search_options.ignores_case(&pattern);
+        let regex = search::regex(&pattern, false, ignore_case)?;
+        let marked: Vec<usize> = rows
+            .filter(|y| {
+                self.document
+                    .row(*y)
+                    .is_some_and(|row| regex.is_match(row.as_str()) != invert)
+            })
+            .collect();
+        if marked.is_empty() {
+            return Err(format!("Pattern not found: {}", pattern));
+        }
+        self.in_global = true;
+        let result = self.on_rows(marked, |editor| editor.run_command(command));
+        self.in_global = false;
+        result
+    }
+    // Runs `action` with the cursor at the start of each of `rows`, in
+    // order. Rows the action adds or removes move the ones after it.
+    fn on_rows<F>(&mut self, rows: Vec<usize>, mut action: F) -> Result<(), String>
+    where
+        F: FnMut(&mut Self) -> Result<(), String>,
+    {
+        let mut moved: isize = 0;
+        for y in rows {
+            let Some(y) = y.checked_add_signed(moved).filter(|y| *y < self.document.len()) else {
+                break;
+            };
+            self.cursor_position = Position { x: 0, y };
+            let len = self.document.len();
+            action(self)?;
+            let difference = isize::try_from(self.document.len())
+                .unwrap_or(isize::MAX)
+                .saturating_sub(isize::try_from(len).unwrap_or(isize::MAX));
+            moved = moved.saturating_add(difference);
+        }
+        Ok(())
+    }
+    // Handles `keys` as if typed, for `:normal`. Two `Esc`s after them
+    // cancel whatever they leave unfinished: insert mode, a selection, a
+    // prompt or a partly typed command.
+    fn run_keys(&mut self, keys: &[Key]) {
+        let typeahead = std::mem::take(&mut self.typeahead);
+        self.typeahead.extend(keys.iter().cloned().map(Input::Key));
+        self.typeahead.extend([Input::Key(Key::Esc), Input::Key(Key::Esc)]);
+        while !self.typeahead.is_empty() && !self.should_quit {
+            if self.process_keypress().is_err() {
+                break;
+            }
+        }
+        self.typeahead = typeahead;
+    }
+    // One setting of `:set`; returns the value to show for a query.
+    fn set_option(&mut self, setting: ex::Setting) -> Result<Option<String>, String> {
+        let name = match &setting {
+            ex::Setting::Assign(name, _)
+            | ex::Setting::Enable(name)
+            | ex::Setting::Disable(name)
+            | ex::Setting::Query(name) => name.as_str(),
+        };
+        let invalid = || {
+            let argument = match &setting {
+                ex::Setting::Assign(name, value) => format!("{}={}", name, value),
+                ex::Setting::Disable(name) => format!("no{}", name),
+                ex::Setting::Enable(name) | ex::Setting::Query(name) => name.clone(),
+            };
+            Err(format!("Invalid argument: {}", argument))
+        };
+        match name {
+            "tabstop" | "ts" | "shiftwidth" | "sw" | "textwidth" | "tw" => {
+                let (name, current) = match name {
+                    "tabstop" | "ts" => ("tabstop", &mut self.tab_stop),
+                    "shiftwidth" | "sw" => ("shiftwidth", &mut self.shift_width),
+                    _ => ("textwidth", &mut self.text_width),
+                };
+                let value = match &setting {
+                    ex::Setting::Assign(_, value) => value,
+                    ex::Setting::Enable(_) | ex::Setting::Query(_) => {
+                        return Ok(Some(format!("{}={}", name, current)));
+                    }
+                    ex::Setting::Disable(_) => return invalid(),
+                };
+                let Some(value) = value.parse::<usize>().ok().filter(|value| *value > 0) else {
+                    return invalid();
+                };
+                *current = value;
+            }
+            "wrap" | "linebreak" | "lbr" => {
+                let wrap = name == "wrap";
+                let enable = match setting {
+                    ex::Setting::Enable(_) => true,
+                    ex::Setting::Disable(_) => false,
+                    ex::Setting::Query(_) => {
+                        let on = if wrap {
+                            self.wrap != Wrap::None
+                        } else {
+                            self.linebreak
+                        };
+                        let name = if wrap { "wrap" } else { "linebreak" };
+                        return Ok(Some(format!("{}{}", if on { "" } else { "no" }, name)));
+                    }
+                    ex::Setting::Assign(..) => return invalid(),
+                };
+                if !wrap {
+                    self.linebreak = enable;
+                }
+                let wrapped = if wrap { enable } else { self.wrap != Wrap::None };
+                self.wrap = match (wrapped, self.linebreak) {
+                    (false, _) => Wrap::None,
+                    (true, true) => Wrap::Words,
+                    (true, false) => Wrap::Characters,
+                };
+                // `offset.x` counts screen lines or columns depending on
+                // the mode.
+                self.offset.x = 0;
+            }
+            "ignorecase" | "ic" | "smartcase" | "scs" | "literal" => {
+                let (full, flag) = match name {
+                    "ignorecase" | "ic" => ("ignorecase", &mut self.search_options.ignore_case),
+                    "smartcase" | "scs" => ("smartcase", &mut self.search_options.smart_case),
+                    _ => ("literal", &mut self.search_options.literal),
+                };
+                match setting {
+                    ex::Setting::Enable(_) => *flag = true,
+                    ex::Setting::Disable(_) => *flag = false,
+                    ex::Setting::Query(_) => {
+                        return Ok(Some(format!("{}{}", if *flag { "" } else { "no" }, full)));
+                    }
+                    ex::Setting::Assign(..) => return invalid(),
+                }
+            }
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+        Ok(None)
+    }
+    fn enter_insert_mode(&mut self) {
+        self.mode = Mode::Insert;
+        self.status_message = StatusMessage::from("-- INSERT MODE --".to_string());
+    }
+    
+    fn enter_command_mode(&mut self) {
+        self.mode = Mode::Command;
+        self.status_message = StatusMessage::from("-- COMMAND MODE --".to_string());
+    }
+    // Collects command mode keys until they make a complete command.
+    fn command_key(&mut self, key: Key) {
+        let visual = self.mode.visual().is_some();
+        if visual && key == Key::Esc && self.pending_keys.is_empty() {
+            self.enter_command_mode();
+            return;
+        }
+        if self.recording.is_some() && key == Key::Char('q') && self.pending_keys.is_empty() {
+            self.stop_recording();
+            return;
+        }
+        self.pending_keys.push(key);
+        let parse = if visual {
+            vim::parse_visual(&self.pending_keys)
+        } else {
+            vim::parse(&self.pending_keys)
+        };
+        match parse {
+            Parse::Pending => return,
+            Parse::Complete(command) => {
+                self.pending_keys.clear();
+                if is_undoable(command.action) {
+                    self.checkpoint();
+                }
+                self.execute(command);
+                if !visual && is_change(command.action) {
+                    let change = LastChange {
+                        command,
+                        inserted: Vec::new(),
+                    };
+                    if self.mode == Mode::Insert {
+                        self.change_in_progress = Some(change);
+                    } else {
+                        self.last_change = Some(change);
+                    }
+                }
+            }
+            Parse::Invalid => (),
+        }
+        self.pending_keys.clear();
+    }
+    fn execute(&mut self, command: vim::Command) {
+        match command.action {
+            Action::Move(motion) => {
+                self.move_by(motion, command.count);
+                match motion {
+                    Motion::LineEnd => self.visual_to_end = true,
+                    Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine => (),
+                    _ => self.visual_to_end = false,
+                }
+            }
+            Action::Operate(operator, target) => {
+                if let Some(span) =
+                    vim::span(&self.document, &self.cursor_position, operator, target, command.count)
+                {
+                    self.operate(operator, span, command.register);
+                }
+            }
+            Action::Insert(insert) if self.mode.visual().is_some() => {
+                self.insert_on_selection(insert);
+            }
+            Action::Insert(insert) => self.start_insert(insert),
+            Action::Put { before } => self.put(command.register, before, command.count),
+            Action::Visual(visual) => self.visual_mode(visual),
+            Action::OtherEnd => std::mem::swap(&mut self.visual_anchor, &mut self.cursor_position),
+            Action::OperateOnSelection(operator) => {
+                let Some(span) = self.selection() else {
+                    return;
+                };
+                self.mode = Mode::Command;
+                self.status_message = StatusMessage::from(String::new());
+                // `3>` shifts three times.
+                let times = if matches!(operator, Operator::Indent | Operator::Outdent) {
+                    command.count.unwrap_or(1).max(1)
+                } else {
+                    1
+                };
+                for _ in 0..times {
+                    self.operate(operator, span.clone(), command.register);
+                }
+            }
+            Action::Repeat => self.repeat_change(command.count),
+            Action::Record(name) => {
+                self.recording = Some((name, Vec::new()));
+                self.status_message = StatusMessage::from(format!("recording @{}", name));
+            }
+            Action::Replay(name) => self.replay(name, command.count),
+            Action::CommandLine => self.command_line(command.count),
+            Action::Search { backward } => self.search(if backward {
+                SearchDirection::Backward
+            } else {
+                SearchDirection::Forward
+            }),
+            Action::SearchNext { reverse } => self.search_next(reverse, command.count),
+            Action::Undo => self.undo(command.count, false),
+        }
+    }
+    // Runs the last change again, with `count` in place of its own.
+    fn repeat_change(&mut self, count: Option<usize>) {
+        let Some(mut change) = self.last_change.clone() else {
+            return;
+        };
+        if count.is_some() {
+            change.command.count = count;
+        }
+        self.execute(change.command);
+        if self.mode == Mode::Insert {
+            for input in change.inserted.iter().rev() {
+                self.typeahead.push_front(input.clone());
+            }
+        }
+        self.last_change = Some(change);
+    }
+    // The `q` that stopped the recording is not part of the macro.
+    fn stop_recording(&mut self) {
+        let Some((name, mut recorded)) = self.recording.take() else {
+            return;
+        };
+        recorded.pop();
+        let text = keys::to_text(&recorded);
+        self.registers
+            .record(name, Register::new(text, register::Kind::Characters));
+        self.status_message = StatusMessage::from(String::new());
+    }
+    // Queues the keys in register `name` to run `count` times, or in visual
+    // mode once from the start of each selected row.
+    fn replay(&mut self, name: char, count: Option<usize>) {
+        let name = if name == '@' {
+            let Some(name) = self.last_macro else {
+                self.status_message = StatusMessage::from("No previous macro".to_string());
+                return;
+            };
+            name
+        } else {
+            name
+        };
+        self.macros_run = self.macros_run.saturating_add(1);
+        if self.macros_run > MACRO_LIMIT {
+            self.typeahead.clear();
+            self.status_message =
+                StatusMessage::from(format!("Stopped @{} after {} runs", name, MACRO_LIMIT));
+            return;
+        }
+        let Some(register) = self.registers.get(Some(name)) else {
+            self.status_message = StatusMessage::from(format!("Register {} is empty", name));
+            return;
+        };
+        let keys: Vec<Input> = keys::from_text(&register.text)
+            .into_iter()
+            .map(Input::Key)
+            .collect();
+        self.last_macro = Some(name);
+        let mut queued = Vec::new();
+        if let Some(span) = self.selection() {
+            let rows = span.rows();
+            self.mode = Mode::Command;
+            self.status_message = StatusMessage::from(String::new());
+            for y in rows {
+                let jump = format!("{}G0", y.saturating_add(1));
+                queued.extend(jump.chars().map(|c| Input::Key(Key::Char(c))));
+                queued.extend(keys.iter().cloned());
+            }
+        } else {
+            for _ in 0..count.unwrap_or(1).max(1) {
+                queued.extend(keys.iter().cloned());
+            }
+        }
+        for input in queued.into_iter().rev() {
+            self.typeahead.push_front(input);
+        }
+    }
+    // Starts or switches visual mode, or leaves it when it is the current
+    // one.
+    fn visual_mode(&mut self, visual: Visual) {
+        if self.mode.visual() == Some(visual) {
+            self.enter_command_mode();
+            return;
+        }
+        if self.mode.visual().is_none() {
+            self.visual_anchor = self.cursor_position.clone();
+            self.visual_to_end = false;
+        }
+        let (mode, name) = match visual {
+            Visual::Characters => (Mode::Visual, "VISUAL"),
+            Visual::Lines => (Mode::VisualLine, "VISUAL LINE"),
+            Visual::Block => (Mode::VisualBlock, "VISUAL BLOCK"),
+        };
+        self.mode = mode;
+        self.status_message = StatusMessage::from(format!("-- {} --", name));
+    }
+    fn selection(&self) -> Option<Span> {
+        let visual = self.mode.visual()?;
+        Some(vim::selection(
+            &self.document,
+            visual,
+            &self.visual_anchor,
+            &self.cursor_position,
+            self.tab_stop,
+            self.visual_to_end,
+        ))
+    }
+    // `I` and `A` on a selection: before or after it, and on a block on
+    // every row.
+    fn insert_on_selection(&mut self, insert: Insert) {
+        let Some(span) = self.selection() else {
+            return;
+        };
+        let append = insert == Insert::LineEnd;
+        self.mode = Mode::Command;
+        match span {
+            Span::Block(rows, columns) => {
+                let column = if append { columns.end } else { columns.start };
+                // `I` skips rows that end before the block.
+                let others = rows
+                    .clone()
+                    .skip(1)
+                    .filter(|y| {
+                        append
+                            || self.document.row(*y).is_some_and(|row| {
+                                row.index(column, self.tab_stop) < row.len()
+                            })
+                    })
+                    .collect();
+                self.start_block_insert(rows.start, others, column);
+            }
+            Span::Lines(rows) => {
+                let y = if append { rows.end.saturating_sub(1) } else { rows.start };
+                let x = if append {
+                    self.document.row(y).map_or(0, Row::len)
+                } else {
+                    vim::first_non_blank(&self.document, y)
+                };
+                self.cursor_position = Position { x, y };
+            }
+            Span::Text(start, end) => {
+                self.cursor_position = if append { end } else { start };
+            }
+        }
+        self.enter_insert_mode();
+    }
+    // Moves the cursor to `column` in row `y`, padding the row if it is
+    // shorter, and remembers what to copy to the `others` rows.
+    fn start_block_insert(&mut self, y: usize, others: Vec<usize>, column: usize) {
+        let Some(row) = self.document.row(y) else {
+            return;
+        };
+        let width = row.column(row.len(), self.tab_stop);
+        if column != usize::MAX && width < column {
+            let padded = format!("{}{}", row.as_str(), " ".repeat(column.saturating_sub(width)));
+            self.document.replace_rows(y..y.saturating_add(1), &[padded]);
+        }
+        let Some(row) = self.document.row(y) else {
+            return;
+        };
+        let at = if column == usize::MAX {
+            row.len()
+        } else {
+            row.index(column, self.tab_stop)
+        };
+        self.block_insert = Some(BlockInsert {
+            y,
+            others,
+            column,
+            original: row.as_str().to_string(),
+            at,
+            document_len: self.document.len(),
+        });
+        self.cursor_position = Position { x: at, y };
+        self.enter_insert_mode();
+    }
+    // Copies the text typed on the first row of a block insert to the other
+    // rows, unless the insert went beyond that row.
+    fn finish_block_insert(&mut self) {
+        let Some(insert) = self.block_insert.take() else {
+            return;
+        };
+        if self.document.len() != insert.document_len {
+            return;
+        }
+        let original = Row::from(insert.original.as_str());
+        let head = original.slice(0, insert.at);
+        let tail = original.slice(insert.at, original.len());
+        let Some(text) = self.document.row(insert.y).map(Row::as_str) else {
+            return;
+        };
+        if text.len() < head.len().saturating_add(tail.len())
+            || !text.starts_with(head)
+            || !text.ends_with(tail)
+        {
+            return;
+        }
+        let inserted = text
+            .get(head.len()..text.len().saturating_sub(tail.len()))
+            .unwrap_or_default()
+            .to_string();
+        if inserted.is_empty() {
+            return;
+        }
+        for y in insert.others {
+            let Some(row) = self.document.row(y) else {
+                break;
+            };
+            let width = row.column(row.len(), self.tab_stop);
+            let (index, padding) = if insert.column == usize::MAX {
+                (row.len(), 0)
+            } else {
+                (
+                    row.index(insert.column, self.tab_stop),
+                    insert.column.saturating_sub(width),
+                )
+            };
+            let line = format!(
+                "{}{}{}{}",
+                row.slice(0, index),
+                " ".repeat(padding),
+                inserted,
+                row.slice(index, row.len())
+            );
+            self.document.replace_rows(y..y.saturating_add(1), &[line]);
+        }
+    }
+    fn move_by(&mut self, motion: Motion, count: Option<usize>) {
+        match motion {
+            // Like the arrow keys, these keep the display column and move by
+            // screen line when wrapping.
+            Motion::Up | Motion::Down => {
+                let key = if motion == Motion::Up { Key::Up } else { Key::Down };
+                for _ in 0..count.unwrap_or(1) {
+                    self.move_cursor(key);
+                }
+            }
+            _ => {
+                if let Some(destination) =
+                    vim::destination(&self.document, &self.cursor_position, motion, count)
+                {
+                    self.cursor_position = destination.position;
+                }
+            }
+        }
+    }
+    fn operate(&mut self, operator: Operator, span: Span, register: Option<char>) {
+        // An empty span changes nothing, and `c` just starts inserting.
+        if let Span::Text(start, end) = &span {
+            if start == end {
+                if operator == Operator::Change {
+                    self.cursor_position = start.clone();
+                    self.enter_insert_mode();
+                }
+                return;
+            }
+        }
+        if matches!(operator, Operator::Delete | Operator::Change | Operator::Yank) {
+            let text = match &span {
+                Span::Lines(rows) => Register::new(
+                    rows.clone()
+                        .filter_map(|y| self.document.row(y).map(Row::as_str))
+                        .collect::<Vec<&str>>()
+                        .join("\n"),
+                    register::Kind::Lines,
+                ),
+                Span::Text(start, end) => Register::new(
+                    self.document.text_between(start, end),
+                    register::Kind::Characters,
+                ),
+                Span::Block(rows, _) => Register::new(
+                    rows.clone()
+                        .filter_map(|y| {
+                            let row = self.document.row(y)?;
+                            let selected = span.graphemes(y, row, self.tab_stop);
+                            Some(row.slice(selected.start, selected.end))
+                        })
+                        .collect::<Vec<&str>>()
+                        .join("\n"),
+                    register::Kind::Block,
+                ),
+            };
+            if let Some(selection) = register.and_then(Selection::from_register) {
+                self.copy_to_clipboard(selection, &text);
+            }
+            if operator == Operator::Yank {
+                self.registers.yank(register, text);
+            } else {
+                self.registers.delete(register, text);
+            }
+        }
+        match (operator, span) {
+            (Operator::Indent | Operator::Outdent, span) => {
+                let rows = match span {
+                    Span::Lines(rows) | Span::Block(rows, _) => rows,
+                    Span::Text(start, end) => start.y..end.y.saturating_add(1),
+                };
+                self.shift_rows(rows, operator == Operator::Indent);
+            }
+            (Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase, span) => {
+                self.replace_in_span(&span, |text| change_case(operator, text));
+                self.cursor_position = self.span_start(&span);
+            }
+            (Operator::Yank, Span::Block(rows, columns)) => {
+                self.cursor_position = self.span_start(&Span::Block(rows.clone(), columns));
+                self.status_message =
+                    StatusMessage::from(format!("block of {} lines yanked", rows.len()));
+            }
+            (_, Span::Block(rows, columns)) => {
+                let span = Span::Block(rows.clone(), columns.clone());
+                // `c` goes on in the rows the block covered.
+                let others: Vec<usize> = rows
+                    .clone()
+                    .skip(1)
+                    .filter(|y| {
+                        self.document.row(*y).is_some_and(|row| {
+                            !span.graphemes(*y, row, self.tab_stop).is_empty()
+                        })
+                    })
+                    .collect();
+                self.replace_in_span(&span, |_| String::new());
+                self.cursor_position = self.span_start(&span);
+                if operator == Operator::Change {
+                    self.start_block_insert(rows.start, others, columns.start);
+                }
+            }
+            (Operator::Yank, Span::Lines(rows)) => {
+                self.cursor_position.y = rows.start;
+                self.status_message = StatusMessage::from(format!("{} lines yanked", rows.len()));
+            }
+            (Operator::Yank, Span::Text(start, end)) => {
+                let text = self.document.text_between(&start, &end);
+                self.status_message =
+                    StatusMessage::from(format!("{} characters yanked", text.chars().count()));
+                self.cursor_position = start;
+            }
+            (_, Span::Lines(rows)) => {
+                let y = rows.start;
+                if operator == Operator::Change {
+                    // The first line's indentation is kept.
+                    let indentation: String = self
+                        .document
+                        .row(y)
+                        .map(|row| row.as_str().chars().take_while(|c| c.is_whitespace()).collect())
+                        .unwrap_or_default();
+                    self.document.replace_rows(rows, &[indentation]);
+                    self.cursor_position = Position {
+                        x: vim::first_non_blank(&self.document, y),
+                        y,
+                    };
+                    self.enter_insert_mode();
+                } else {
+                    self.document.replace_rows(rows, &[]);
+                    let y = y.min(self.document.len().saturating_sub(1));
+                    self.cursor_position = Position {
+                        x: vim::first_non_blank(&self.document, y),
+                        y,
+                    };
+                }
+            }
+            (_, Span::Text(start, end)) => {
+                self.document.delete_between(&start, &end);
+                self.cursor_position = start;
+                if operator == Operator::Change {
+                    self.enter_insert_mode();
+                }
+            }
+        }
+    }
+    // Replaces the graphemes of each row within `span` with what `replace`
+    // makes of them.
+    fn replace_in_span<F>(&mut self, span: &Span, replace: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        let rows = match span {
+            Span::Lines(rows) | Span::Block(rows, _) => rows.clone(),
+            Span::Text(start, end) => start.y..end.y.saturating_add(1),
+        };
+        for y in rows {
+            let Some(row) = self.document.row(y) else {
+                break;
+            };
+            let selected = span.graphemes(y, row, self.tab_stop);
+            if selected.is_empty() {
+                continue;
+            }
+            let line = format!(
+                "{}{}{}",
+                row.slice(0, selected.start),
+                replace(row.slice(selected.start, selected.end)),
+                row.slice(selected.end, row.len())
+            );
+            self.document.replace_rows(y..y.saturating_add(1), &[line]);
+        }
+    }
+    // Where the cursor goes after an operator on `span`.
+    fn span_start(&self, span: &Span) -> Position {
+        match span {
+            Span::Text(start, _) => start.clone(),
+            Span::Lines(rows) => Position {
+                x: vim::first_non_blank(&self.document, rows.start),
+                y: rows.start,
+            },
+            Span::Block(rows, columns) => Position {
+                x: self
+                    .document
+                    .row(rows.start)
+                    .map_or(0, |row| row.index(columns.start, self.tab_stop)),
+                y: rows.start,
+            },
+        }
+    }
+    // Indents or outdents `rows` by `shift_width` columns. Empty rows are
+    // left alone.
+    fn shift_rows(&mut self, rows: Range<usize>, indent: bool) {
+        let start = rows.start;
+        for y in rows {
+            let Some(row) = self.document.row(y) else {
+                break;
+            };
+            let line = row.as_str();
+            let shifted = if indent {
+                if line.is_empty() {
+                    continue;
+                }
+                format!("{}{}", " ".repeat(self.shift_width), line)
+            } else if let Some(rest) = line.strip_prefix('\t') {
+                rest.to_string()
+            } else {
+                let spaces = line.chars().take(self.shift_width).take_while(|c| *c == ' ').count();
+                line.get(spaces..).unwrap_or_default().to_string()
+            };
+            self.document.replace_rows(y..y.saturating_add(1), &[shifted]);
+        }
+        self.cursor_position = Position {
+            x: vim::first_non_blank(&self.document, start),
+            y: start,
+        };
+    }
+    // Lines are copied with a newline at the end, so they paste as lines
+    // elsewhere too.
+    fn copy_to_clipboard(&mut self, selection: Selection, register: &Register) {
+        let mut text = register.text.clone();
+        if register.kind == register::Kind::Lines {
+            text.push('\n');
+        }
+        let copied = self
+            .terminal
+            .copy_to_clipboard(selection, &text)
+            .and_then(|()| self.clipboard.copy(selection, &text));
+        if let Err(error) = copied {
+            self.status_message =
+                StatusMessage::from(format!("Could not copy to the clipboard: {}", error));
+        }
+    }
+    // What the system clipboard holds, if it can be read and is not empty.
+    fn paste_from_clipboard(&self, selection: Selection) -> Option<Register> {
+        let text = self
+            .clipboard
+            .paste(selection)?
+            .replace("\r\n", "\n");
+        if text.is_empty() {
+            return None;
+        }
+        Some(match text.strip_suffix('\n') {
+            Some(lines) => Register::new(lines.to_string(), register::Kind::Lines),
+            None => Register::new(text, register::Kind::Characters),
+        })
+    }
+    fn put(&mut self, name: Option<char>, before: bool, count: Option<usize>) {
+        let pasted = name
+            .and_then(Selection::from_register)
+            .and_then(|selection| self.paste_from_clipboard(selection));
+        let Some(register) = pasted.or_else(|| self.registers.get(name).cloned()) else {
+            self.status_message =
+                StatusMessage::from(format!("Register {} is empty", name.unwrap_or('"')));
+            return;
+        };
+        let cursor = self.cursor_position.clone();
+        let count = count.unwrap_or(1).max(1);
+        let (rows, original) = self.put_register(&register, before, count);
+        self.last_put = Some(LastPut {
+            rows,
+            original,
+            cursor,
+            before,
+            count,
+            kill: 0,
+        });
+    }
+    // Puts `register` `count` times after or before the cursor. Returns the
+    // rows that changed and what they held before.
+    fn put_register(
+        &mut self,
+        register: &Register,
+        before: bool,
+        count: usize,
+    ) -> (Range<usize>, Vec<String>) {
+        let Position { x, y } = self.cursor_position.clone();
+        let row_len = self.document.row(y).map_or(0, Row::len);
+        let after = if before || row_len == 0 {
+            x
+        } else {
+            x.saturating_add(1).min(row_len)
+        };
+        match register.kind {
+            register::Kind::Characters => {
+                let original: Vec<String> = self
+                    .document
+                    .row(y)
+                    .map(|row| row.as_str().to_string())
+                    .into_iter()
+                    .collect();
+                let at = Position { x: after, y };
+                let end = self.document.insert_text(&at, &register.text.repeat(count));
+                // On the last character put, unless it spans rows.
+                self.cursor_position = if end.y == y {
+                    Position {
+                        x: end.x.saturating_sub(1),
+                        y,
+                    }
+                } else {
+                    at
+                };
+                (y..end.y.saturating_add(1), original)
+            }
+            register::Kind::Lines => {
+                let at = if before { y } else { y.saturating_add(1) }.min(self.document.len());
+                let lines: Vec<String> = std::iter::repeat_n(register.lines(), count)
+                    .flatten()
+                    .map(String::from)
+                    .collect();
+                self.document.replace_rows(at..at, &lines);
+                self.cursor_position = Position {
+                    x: vim::first_non_blank(&self.document, at),
+                    y: at,
+                };
+                (at..at.saturating_add(lines.len()), Vec::new())
+            }
+            // Each line goes into its own row at the same display column,
+            // padded to the width of the block when text follows it.
+            register::Kind::Block => {
+                let column = self
+                    .document
+                    .row(y)
+                    .map_or(0, |row| row.column(after, self.tab_stop));
+                let lines = register.lines();
+                let width = lines.iter().map(|line| line.width()).max().unwrap_or(0);
+                let end = y.saturating_add(lines.len());
+                let original: Vec<String> = (y..end.min(self.document.len()))
+                    .filter_map(|y| self.document.row(y).map(|row| row.as_str().to_string()))
+                    .collect();
+                let mut changed = Vec::new();
+                for (offset, line) in lines.iter().enumerate() {
+                    let row = self
+                        .document
+                        .row(y.saturating_add(offset))
+                        .map_or_else(Row::default, |row| Row::from(row.as_str()));
+                    let index = row.index(column, self.tab_stop);
+                    let padding = column.saturating_sub(row.column(row.len(), self.tab_stop));
+                    let mut piece = line.repeat(count);
+                    if index < row.len() {
+                        let short = width.saturating_mul(count).saturating_sub(piece.width());
+                        piece.push_str(&" ".repeat(short));
+                    }
+                    changed.push(format!(
+                        "{}{}{}{}",
+                        row.slice(0, index),
+                        " ".repeat(padding),
+                        piece,
+                        row.slice(index, row.len())
+                    ));
+                }
+                self.document.replace_rows(y..y.saturating_add(original.len()), &changed);
+                self.cursor_position = Position {
+                    x: self
+                        .document
+                        .row(y)
+                        .map_or(0, |row| row.index(column, self.tab_stop)),
+                    y,
+                };
+                (y..y.saturating_add(changed.len()), original)
+            }
+        }
+    }
+    // Like Emacs' `M-y`: replaces the text just put with the next older kill.
+    fn cycle_kill_ring(&mut self) {
+        let Some(put) = self.last_put.take() else {
+            self.status_message = StatusMessage::from("Previous command was not a put".to_string());
+            return;
+        };
+        let kill = put.kill.saturating_add(1);
+        let Some(register) = self.registers.kill(kill).cloned() else {
+            return;
+        };
+        self.document.replace_rows(put.rows.clone(), &put.original);
+        self.cursor_position = put.cursor.clone();
+        let (rows, _) = self.put_register(&register, put.before, put.count);
+        self.last_put = Some(LastPut { rows, kill, ..put });
+    }
+    fn start_insert(&mut self, insert: Insert) {
+        match insert {
+            Insert::Before => (),
+            Insert::After => {
+                if let Some(row) = self.document.row(self.cursor_position.y) {
+                    if !row.is_empty() && self.cursor_position.x < row.len() {
+                        self.move_cursor(Key::Right);
                     }
-                    editor.highlighted_word = Some(query.to_string());
-                },
-            )
-            .unwrap_or(None);
-
-        if query.is_none() {
-            self.cursor_position = old_position;
-            self.scroll();
+                }
+            }
+            Insert::LineEnd => self.move_cursor(Key::End),
+            Insert::LineStart => self.move_cursor(Key::Home),
+            Insert::Below => {
+                self.move_cursor(Key::End);
+                self.document.insert(&self.cursor_position, '\n');
+                self.move_cursor(Key::Down);
+            }
+            Insert::Above => {
+                self.move_cursor(Key::Home);
+                self.document.insert(&self.cursor_position, '\n');
+                self.move_cursor(Key::Up);
+            }
         }
-        self.highlighted_word = None;
-    }
-    fn enter_insert_mode(&mut self) {
-        self.mode = Mode::Insert;
-        self.status_message = StatusMessage::from("-- INSERT MODE --".to_string());
+        self.enter_insert_mode();
     }
-    
-    fn enter_command_mode(&mut self) {
-        self.mode = Mode::Command;
-        self.status_message = StatusMessage::from("-- COMMAND MODE --".to_string());
+
+    // Inserts pasted text as it is, in either mode, rather than running it
+    // as keys.
+    fn paste(&mut self, text: &str) {
+        if self.mode != Mode::Insert {
+            self.checkpoint();
+        }
+        self.pending_keys.clear();
+        self.last_put = None;
+        self.cursor_position = self.document.insert_text(&self.cursor_position, text);
     }
-    
+
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+        let input = self.read_input()?;
+        if self.mode == Mode::Insert {
+            if let Some(change) = self.change_in_progress.as_mut() {
+                change.inserted.push(input.clone());
+            }
+        }
+        let pressed_key = match input {
+            Input::Key(key) => key,
+            Input::Paste(text) => {
+                self.paste(&text);
+                self.scroll();
+                return Ok(());
+            }
+        };
+        if pressed_key != Key::Alt('y') {
+            self.last_put = None;
+        }
         
         match self.mode {
-            Mode::Command => {
+            Mode::Command | Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
                 match pressed_key {
                     Key::Ctrl('q') => {
                         if self.quit_times > 0 && self.document.is_dirty() {
@@ -204,45 +2151,32 @@ impl Editor {
                             self.quit_times -= 1;
                             return Ok(());
                         }
-                        self.should_quit = true
+                        if self.edit_special.is_some() {
+                            self.close_edit_special(false);
+                        } else {
+                            self.should_quit = true
+                        }
                     }
                     Key::Ctrl('s') => self.save(),
-                    Key::Ctrl('f') => self.search(),
-                    Key::Char('i') => self.enter_insert_mode(),
-                    Key::Char('a') => {
-                        // Move cursor right then enter insert mode (append)
-                        if let Some(row) = self.document.row(self.cursor_position.y) {
-                            if !row.is_empty() && self.cursor_position.x < row.len() {
-                                self.move_cursor(Key::Right);
-                            }
+                    Key::Ctrl('f') => self.search(SearchDirection::Forward),
+                    Key::Ctrl('e') => self.export(),
+                    Key::Ctrl('c') => self.execute_block(),
+                    Key::Ctrl('t') => self.tangle(),
+                    Key::Ctrl('o') => {
+                        if self.edit_special.is_some() {
+                            self.close_edit_special(true);
+                        } else {
+                            self.open_edit_special();
                         }
-                        self.enter_insert_mode();
                     }
-                    Key::Char('A') => {
-                        // Move to end of line and enter insert mode
-                        self.move_cursor(Key::End);
-                        self.enter_insert_mode();
+                    Key::Alt('y') => self.cycle_kill_ring(),
+                    Key::Alt('%') => self.query_replace(),
+                    Key::Ctrl('r') => self.undo(None, true),
+                    Key::Ctrl('v') => {
+                        self.pending_keys.clear();
+                        self.visual_mode(Visual::Block);
                     }
-                    Key::Char('I') => {
-                        // Move to start of line and enter insert mode
-                        self.move_cursor(Key::Home);
-                        self.enter_insert_mode();
-                    }
-                    Key::Char('o') => {
-                        // Open line below cursor and enter insert mode
-                        self.move_cursor(Key::End);
-                        self.document.insert(&self.cursor_position, '\n');
-                        self.move_cursor(Key::Down);
-                        self.enter_insert_mode();
-                    }
-                    Key::Char('O') => {
-                        // Open line above cursor and enter insert mode
-                        self.move_cursor(Key::Home);
-                        self.document.insert(&self.cursor_position, '\n');
-                        self.move_cursor(Key::Up);
-                        self.enter_insert_mode();
-                    }
-                    Key::Char('x') => self.document.delete(&self.cursor_position),
+                    Key::Char(_) | Key::Esc => self.command_key(pressed_key),
                     Key::Up
                     | Key::Down
                     | Key::Left
@@ -256,7 +2190,13 @@ impl Editor {
             }
             Mode::Insert => {
                 match pressed_key {
-                    Key::Esc => self.enter_command_mode(),
+                    Key::Esc => {
+                        self.finish_block_insert();
+                        if let Some(change) = self.change_in_progress.take() {
+                            self.last_change = Some(change);
+                        }
+                        self.enter_command_mode();
+                    }
                     Key::Ctrl('q') => {
                         if self.quit_times > 0 && self.document.is_dirty() {
                             self.status_message = StatusMessage::from(format!(
@@ -266,7 +2206,11 @@ impl Editor {
                             self.quit_times -= 1;
                             return Ok(());
                         }
-                        self.should_quit = true
+                        if self.edit_special.is_some() {
+                            self.close_edit_special(false);
+                        } else {
+                            self.should_quit = true
+                        }
                     }
                     Key::Ctrl('s') => self.save(),
                     Key::Char(c) => {
@@ -300,10 +2244,233 @@ impl Editor {
         }
         Ok(())
     }
-    fn scroll(&mut self) {
+    // Waits for the next key or paste. Meanwhile resizes, timers and
+    // finished background jobs are handled, and the screen is kept up to
+    // date.
+    fn read_input(&mut self) -> Result<Input, std::io::Error> {
+        if let Some(input) = self.typeahead.pop_front() {
+            return Ok(input);
+        }
+        self.macros_run = 0;
+        loop {
+            let timeout = self
+                .next_deadline()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let input = match self.terminal.poll_event(timeout)? {
+                Some(Event::Key(key)) => Some(Input::Key(key)),
+                Some(Event::Paste(text)) => Some(Input::Paste(text)),
+                Some(Event::Resize(..)) => {
+                    let size = self.terminal.size();
+                    self.screen.resize(
+                        size.width as usize,
+                        (size.height as usize).saturating_add(2),
+                    );
+                    self.scroll();
+                    None
+                }
+                // A background job finished.
+                Some(Event::Wake) | None => None,
+            };
+            if let Some(input) = input {
+                self.last_key = Instant::now();
+                self.autosaved = false;
+                if let Some((_, recorded)) = self.recording.as_mut() {
+                    match &input {
+                        Input::Key(key) => recorded.push(key.clone()),
+                        Input::Paste(text) => recorded.extend(text.chars().map(Key::Char)),
+                    }
+                }
+                return Ok(input);
+            }
+            self.run_timers();
+            self.refresh_screen()?;
+        }
+    }
+    // When something next needs to happen without a key press.
+    fn next_deadline(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let has_file = self.document.file_name.is_some();
+        let expiry = self.status_message.time + STATUS_MESSAGE_DURATION;
+        [
+            Some(expiry).filter(|expiry| *expiry > now),
+            // The clock in the status bar.
+            Some(now + until_next_minute()),
+            Some(self.last_key + AUTOSAVE_DELAY)
+                .filter(|_| has_file && self.document.is_dirty() && !self.autosaved),
+            Some(self.last_file_check + FILE_CHECK_INTERVAL).filter(|_| has_file),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+    fn run_timers(&mut self) {
+        while let Ok(job) = self.job_receiver.try_recv() {
+            self.running_jobs = self.running_jobs.saturating_sub(1);
+            job(self);
+        }
+        let now = Instant::now();
+        if self.document.is_dirty()
+            && !self.autosaved
+            && now.saturating_duration_since(self.last_key) >= AUTOSAVE_DELAY
+        {
+            // Only reported when it fails, and then once per edit.
+            if let Err(error) = self.document.autosave() {
+                self.status_message = StatusMessage::from(format!("Autosave failed: {}", error));
+            }
+            self.autosaved = true;
+        }
+        if now.saturating_duration_since(self.last_file_check) >= FILE_CHECK_INTERVAL {
+            self.last_file_check = now;
+            if self.document.changed_on_disk() {
+                let name = self.document.file_name.clone().unwrap_or_default();
+                self.status_message = StatusMessage::from(format!(
+                    "WARNING! {} was changed on disk by another program.",
+                    name
+                ));
+            }
+        }
+    }
+    // Runs `work` on another thread. The job it returns is applied to the
+    // editor once it is done, which wakes up the wait for input.
+    fn spawn_job<W>(&mut self, work: W)
+    where
+        W: FnOnce() -> Job + Send + 'static,
+    {
+        let sender = self.job_sender.clone();
+        let waker = self.terminal.waker();
+        self.running_jobs = self.running_jobs.saturating_add(1);
+        thread::spawn(move || {
+            let _ = sender.send(work());
+            waker.wake();
+        });
+    }
+    // The display column of the cursor.
+    fn cursor_column(&self) -> usize {
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
+        self.document
+            .row(y)
+            .map_or(0, |row| row.column(x, self.tab_stop))
+    }
+    // How `row` is split into screen lines in soft-wrap mode: the index
+    // each line starts at, and the column continuation lines start in.
+    fn wrap_row(&self, row: &Row) -> (Vec<usize>, usize) {
+        let width = (self.terminal.size().width as usize).max(1);
+        let indent = org::wrap_indent(row.as_str()).min(width / 2);
+        let starts = row.wrap(width, self.tab_stop, self.wrap == Wrap::Words, indent);
+        (starts, indent)
+    }
+    fn wrapped_line_count(&self, y: usize) -> usize {
+        self.document
+            .row(y)
+            .map_or(1, |row| self.wrap_row(row).0.len())
+    }
+    // The cursor's screen line within its row and its screen column, in
+    // soft-wrap mode.
+    fn wrapped_cursor(&self) -> (usize, usize) {
+        let Position { x, y } = self.cursor_position;
+        let Some(row) = self.document.row(y) else {
+            return (0, 0);
+        };
+        let (starts, indent) = self.wrap_row(row);
+        let line = starts.iter().rposition(|start| *start <= x).unwrap_or(0);
+        let start = starts.get(line).copied().unwrap_or(0);
+        let column = if line == 0 { 0 } else { indent };
+        (line, row.wrapped_column(start, x, column, self.tab_stop))
+    }
+    // The number of screen lines between the top of the screen and the
+    // cursor, in soft-wrap mode.
+    fn lines_above_cursor(&self) -> usize {
+        (self.offset.y..self.cursor_position.y)
+            .map(|y| self.wrapped_line_count(y))
+            .sum::<usize>()
+            .saturating_sub(self.offset.x)
+            .saturating_add(self.wrapped_cursor().0)
+    }
+    fn scroll_wrapped(&mut self) {
+        let height = (self.terminal.size().height as usize).max(1);
+        let y = self.cursor_position.y;
+        let (line, _) = self.wrapped_cursor();
+        // Rows are at least one line high, so rows further up are never
+        // visible.
+        if y.saturating_sub(self.offset.y) > height {
+            self.offset = Position {
+                x: 0,
+                y: y.saturating_sub(height),
+            };
+        }
+        if self.offset.x >= self.wrapped_line_count(self.offset.y) {
+            self.offset.x = 0;
+        }
+        if (y, line) < (self.offset.y, self.offset.x) {
+            self.offset = Position { x: line, y };
+            return;
+        }
+        let mut lines = self.lines_above_cursor();
+        while lines >= height {
+            self.offset.x = self.offset.x.saturating_add(1);
+            if self.offset.x >= self.wrapped_line_count(self.offset.y) {
+                self.offset.y = self.offset.y.saturating_add(1);
+                self.offset.x = 0;
+            }
+            lines = lines.saturating_sub(1);
+        }
+    }
+    // Moves the cursor a screen line up or down in soft-wrap mode, keeping
+    // its screen column.
+    fn move_wrapped(&mut self, down: bool) {
+        let mut y = self.cursor_position.y;
+        let (mut line, column) = self.wrapped_cursor();
+        if down {
+            if line.saturating_add(1) < self.wrapped_line_count(y) {
+                line = line.saturating_add(1);
+            } else if y < self.document.len() {
+                y = y.saturating_add(1);
+                line = 0;
+            } else {
+                return;
+            }
+        } else if line > 0 {
+            line = line.saturating_sub(1);
+        } else if y > 0 {
+            y = y.saturating_sub(1);
+            line = self.wrapped_line_count(y).saturating_sub(1);
+        } else {
+            return;
+        }
+        let x = self.document.row(y).map_or(0, |row| {
+            let (starts, indent) = self.wrap_row(row);
+            let start = starts.get(line).copied().unwrap_or(0);
+            let next = starts.get(line.saturating_add(1)).copied();
+            let end = next.unwrap_or(row.len());
+            let first_column = if line == 0 { 0 } else { indent };
+            let x = row.wrapped_index(start, end, first_column, column, self.tab_stop);
+            // Past the end of a line that continues below, the cursor stays
+            // on its last grapheme.
+            if next.is_some() && x == end {
+                end.saturating_sub(1)
+            } else {
+                x
+            }
+        });
+        self.cursor_position = Position { x, y };
+    }
+    fn scroll(&mut self) {
+        if self.wrap != Wrap::None {
+            self.scroll_wrapped();
+            return;
+        }
+        let y = self.cursor_position.y;
+        let x = self.cursor_column();
+        // The column after the cursor's grapheme, which may be wide.
+        let x_end = self
+            .document
+            .row(y)
+            .map_or(0, |row| row.column(self.cursor_position.x.saturating_add(1), self.tab_stop))
+            .max(x.saturating_add(1));
+        // A window too small for any text still keeps the cursor's line and
+        // column in view.
+        let width = (self.terminal.size().width as usize).max(1);
+        let height = (self.terminal.size().height as usize).max(1);
         let offset = &mut self.offset;
         if y < offset.y {
             offset.y = y;
@@ -312,11 +2479,15 @@ impl Editor {
         }
         if x < offset.x {
             offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        } else if x_end > offset.x.saturating_add(width) {
+            offset.x = x_end.saturating_sub(width);
         }
     }
     fn move_cursor(&mut self, key: Key) {
+        if self.wrap != Wrap::None && matches!(key, Key::Up | Key::Down) {
+            self.move_wrapped(key == Key::Down);
+            return;
+        }
         let terminal_height = self.terminal.size().height as usize;
         let Position { mut y, mut x } = self.cursor_position;
         let height = self.document.len();
@@ -325,6 +2496,7 @@ impl Editor {
         } else {
             0
         };
+        let column = self.cursor_column();
         match key {
             Key::Up => y = y.saturating_sub(1),
             Key::Down => {
@@ -370,6 +2542,13 @@ impl Editor {
             Key::End => x = width,
             _ => (),
         }
+        // Moving between lines keeps the display column, not the index.
+        if matches!(key, Key::Up | Key::Down | Key::PageUp | Key::PageDown) {
+            x = self
+                .document
+                .row(y)
+                .map_or(0, |row| row.index(column, self.tab_stop));
+        }
         width = if let Some(row) = self.document.row(y) {
             row.len()
         } else {
@@ -381,7 +2560,7 @@ impl Editor {
 
         self.cursor_position = Position { x, y }
     }
-    fn draw_welcome_message(&self) {
+    fn draw_welcome_message(&mut self, y: usize) {
         let mut welcome_message = format!("Orgonaut editor -- version {}", VERSION);
         let width = self.terminal.size().width as usize;
         let len = welcome_message.len();
@@ -390,33 +2569,112 @@ impl Editor {
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{}{}", spaces, welcome_message);
         welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+        self.screen.put_str(0, y, &welcome_message, Style::default());
     }
-    pub fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row)
+    pub fn draw_row(
+        screen: &mut Screen,
+        y: usize,
+        row: &Row,
+        start: usize,
+        end: usize,
+        tab_stop: usize,
+        selected: &Range<usize>,
+    ) {
+        let mut x = 0;
+        for (grapheme, highlighting_type, selected) in row.render(start, end, tab_stop, selected) {
+            let style = Style {
+                fg: Some(highlighting_type.to_color()),
+                bg: selected.then_some(SELECTION_BG_COLOR),
+            };
+            x = screen.put_str(x, y, &grapheme, style);
+        }
+    }
+    // The graphemes of `row`, the row at `index`, drawn as selected: those
+    // in the selection, or the match query-replace asks about.
+    fn selected(&self, selection: Option<&Span>, index: usize, row: &Row) -> Range<usize> {
+        match &self.current_match {
+            Some((y, graphemes)) if *y == index => graphemes.clone(),
+            Some(_) => 0..0,
+            None => selection.map_or(0..0, |span| span.graphemes(index, row, self.tab_stop)),
+        }
+    }
+    #[allow(clippy::integer_division)]
+    fn draw_wrapped_rows(&mut self) {
+        let height = self.terminal.size().height as usize;
+        let mut y = 0;
+        let mut index = self.offset.y;
+        let mut skip = self.offset.x;
+        let selection = self.selection();
+        while y < height {
+            let Some(row) = self.document.row(index) else {
+                break;
+            };
+            let selected = self.selected(selection.as_ref(), index, row);
+            let (starts, indent) = self.wrap_row(row);
+            for (line, start) in starts.iter().enumerate().skip(skip) {
+                if y >= height {
+                    break;
+                }
+                let end = starts
+                    .get(line.saturating_add(1))
+                    .copied()
+                    .unwrap_or(row.len());
+                let column = if line == 0 { 0 } else { indent };
+                let mut x = column;
+                for (grapheme, highlighting_type, selected) in
+                    row.render_wrapped(*start, end, column, self.tab_stop, &selected)
+                {
+                    let style = Style {
+                        fg: Some(highlighting_type.to_color()),
+                        bg: selected.then_some(SELECTION_BG_COLOR),
+                    };
+                    x = self.screen.put_str(x, y, &grapheme, style);
+                }
+                y = y.saturating_add(1);
+            }
+            skip = 0;
+            index = index.saturating_add(1);
+        }
+        for y in y..height {
+            if self.document.is_empty() && y == height / 3 {
+                self.draw_welcome_message(y);
+            } else {
+                self.screen.put_str(0, y, "~", Style::default());
+            }
+        }
     }
     #[allow(clippy::integer_division, clippy::integer_arithmetic)]
-    fn draw_rows(&self) {
+    fn draw_rows(&mut self) {
+        if self.wrap != Wrap::None {
+            self.draw_wrapped_rows();
+            return;
+        }
         let height = self.terminal.size().height;
+        let width = self.terminal.size().width as usize;
+        let selection = self.selection();
         for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                self.draw_row(row);
+            let y = terminal_row as usize;
+            let index = self.offset.y.saturating_add(terminal_row as usize);
+            if let Some(row) = self.document.row(index) {
+                let end = self.offset.x.saturating_add(width);
+                let selected = self.selected(selection.as_ref(), index, row);
+                Self::draw_row(
+                    &mut self.screen,
+                    y,
+                    row,
+                    self.offset.x,
+                    end,
+                    self.tab_stop,
+                    &selected,
+                );
             } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
+                self.draw_welcome_message(y);
             } else {
-                println!("~\r");
+                self.screen.put_str(0, y, "~", Style::default());
             }
         }
     }
-    fn draw_status_bar(&self) {
+    fn draw_status_bar(&mut self) {
         let mut status;
         let width = self.terminal.size().width as usize;
         let modified_indicator = if self.document.is_dirty() {
@@ -427,61 +2685,96 @@ impl Editor {
 
         let mut file_name = "[No Name]".to_string();
         if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(20);
+            file_name = name.chars().take(20).collect();
         }
         
         // Add mode to status bar
         let mode_str = match self.mode {
             Mode::Command => "COMMAND",
             Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "VISUAL LINE",
+            Mode::VisualBlock => "VISUAL BLOCK",
         };
         
+        let recording = self
+            .recording
+            .as_ref()
+            .map_or_else(String::new, |(name, _)| format!(" recording @{}", name));
         status = format!(
-            "{} - {} lines{} | {}",
+            "{} - {} lines{} | {}{}",
             file_name,
             self.document.len(),
             modified_indicator,
-            mode_str
+            mode_str,
+            recording
         );
 
-        let line_indicator = format!(
+        let mut line_indicator = format!(
             "{} | {}/{}",
             self.document.file_type(),
             self.cursor_position.y.saturating_add(1),
             self.document.len()
         );
-        #[allow(clippy::integer_arithmetic)]
-        let len = status.len() + line_indicator.len();
+        if let Some(time) = Time::now() {
+            line_indicator = format!("{} | {}", line_indicator, time);
+        }
+        let len = status.width().saturating_add(line_indicator.width());
         status.push_str(&" ".repeat(width.saturating_sub(len)));
         status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
-    }
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
+        let style = Style {
+            fg: Some(STATUS_FG_COLOR),
+            bg: Some(STATUS_BG_COLOR),
+        };
+        let y = self.terminal.size().height as usize;
+        let x = self.screen.put_str(0, y, &status, style);
+        // Pad with the bar's background if the text was shorter.
+        self.screen.put_str(x, y, &" ".repeat(width.saturating_sub(x)), style);
+    }
+    fn draw_message_bar(&mut self) {
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
+        if Instant::now() - message.time < STATUS_MESSAGE_DURATION {
+            let text = message.text.clone();
+            let y = (self.terminal.size().height as usize).saturating_add(1);
+            self.screen.put_str(0, y, &text, Style::default());
         }
     }
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, std::io::Error>
     where
-        C: FnMut(&mut Self, Key, &String),
+        C: FnMut(&mut Self, Key, &mut String),
     {
-        let mut result = String::new();
+        self.prompt_from(prompt, String::new(), callback)
+    }
+    // A prompt with `initial` already typed. The callback may change what
+    // is typed.
+    fn prompt_from<C>(
+        &mut self,
+        prompt: &str,
+        initial: String,
+        mut callback: C,
+    ) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &mut String),
+    {
+        let mut result = initial;
         loop {
             self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_screen()?;
-            let key = Terminal::read_key()?;
+            let key = match self.read_input()? {
+                Input::Key(key) => key,
+                // Pasted text is typed in, without its newlines.
+                Input::Paste(text) => {
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        result.push(c);
+                        callback(self, Key::Char(c), &mut result);
+                    }
+                    continue;
+                }
+            };
             match key {
-                Key::Backspace => result.truncate(result.len().saturating_sub(1)),
+                Key::Backspace => {
+                    result.pop();
+                }
                 Key::Char('\n') => break,
                 Key::Char(c) => {
                     if !c.is_control() {
@@ -494,7 +2787,7 @@ impl Editor {
                 }
                 _ => (),
             }
-            callback(self, key, &result);
+            callback(self, key, &mut result);
         }
         self.status_message = StatusMessage::from(String::new());
         if result.is_empty() {
@@ -504,14 +2797,77 @@ impl Editor {
     }
 }
 
-fn die(e: std::io::Error) {
-    Terminal::clear_screen();
+// What the message bar shows after a search: the pattern, or that it
+// continued from the other end.
+fn search_message(pattern: &str, direction: SearchDirection, wrapped: bool) -> String {
+    match (wrapped, direction) {
+        (false, SearchDirection::Forward) => format!("/{}", pattern),
+        (false, SearchDirection::Backward) => format!("?{}", pattern),
+        (true, SearchDirection::Forward) => "search hit BOTTOM, continuing at TOP".to_string(),
+        (true, SearchDirection::Backward) => "search hit TOP, continuing at BOTTOM".to_string(),
+    }
+}
+
+// Whether `action` may change the document, and so makes an undo step.
+// Whether `a` and `b` name the same file, however they are spelled.
+fn same_file(a: &str, b: &str) -> bool {
+    let canonical = |path: &str| std::fs::canonicalize(path).ok();
+    a == b || canonical(a).is_some_and(|path| Some(path) == canonical(b))
+}
+
+fn is_undoable(action: Action) -> bool {
+    match action {
+        Action::OperateOnSelection(operator) => operator != Operator::Yank,
+        Action::Repeat => true,
+        _ => is_change(action),
+    }
+}
+
+// Whether `.` can repeat `action`.
+fn is_change(action: Action) -> bool {
+    match action {
+        Action::Operate(operator, _) => operator != Operator::Yank,
+        Action::Insert(_) | Action::Put { .. } => true,
+        _ => false,
+    }
+}
+
+fn change_case(operator: Operator, text: &str) -> String {
+    match operator {
+        Operator::Lowercase => text.to_lowercase(),
+        Operator::Uppercase => text.to_uppercase(),
+        _ => text
+            .chars()
+            .map(|c| {
+                if c.is_lowercase() {
+                    c.to_uppercase().to_string()
+                } else {
+                    c.to_lowercase().to_string()
+                }
+            })
+            .collect(),
+    }
+}
+
+fn until_next_minute() -> Duration {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let into_minute = Duration::new(since_epoch.as_secs() % 60, since_epoch.subsec_nanos());
+    Duration::from_secs(60).saturating_sub(into_minute)
+}
+
+fn die(terminal: &mut Terminal, e: &std::io::Error) {
+    let _ = terminal.clear_screen();
+    let _ = terminal.flush();
     panic!("{}", e);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::highlighting;
     use std::io;
     
     // Mock structs for testing
@@ -644,7 +3000,7 @@ mod tests {
         
         fn process_key(&mut self, key: Key) {
             match self.mode {
-                Mode::Command => {
+                Mode::Command | Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
                     match key {
                         Key::Ctrl('q') => {
                             if self.quit_times > 0 && self.document.is_dirty() {
@@ -1129,4 +3485,694 @@ mod tests {
         // The document should now be dirty (modified)
         assert!(editor.document.is_dirty());
     }
+
+    // A real `Editor` on an in-memory terminal, fed `keys`.
+    fn memory_editor(lines: &[&str], keys: &[Key]) -> Editor {
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(keys);
+        editor_on(lines, backend)
+    }
+
+    fn editor_on(lines: &[&str], backend: MemoryBackend) -> Editor {
+        let terminal = Terminal::new(Box::new(backend)).unwrap();
+        let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        let document = Document::from_lines(&lines, FileType::default());
+        Editor::new(terminal, document, String::new())
+    }
+
+    // Runs until the keys run out or the editor quits.
+    fn drive(editor: &mut Editor) {
+        loop {
+            editor.refresh_screen().unwrap();
+            if editor.should_quit || editor.process_keypress().is_err() {
+                break;
+            }
+        }
+    }
+
+    fn memory(editor: &Editor) -> &MemoryBackend {
+        let backend: &dyn std::any::Any = editor.terminal.backend();
+        backend.downcast_ref::<MemoryBackend>().unwrap()
+    }
+
+    #[test]
+    fn test_typing_on_a_memory_terminal() {
+        let mut keys = vec![Key::Char('A')];
+        keys.extend(", world".chars().map(Key::Char));
+        keys.push(Key::Esc);
+        let mut editor = memory_editor(&["hello", "second"], &keys);
+        drive(&mut editor);
+        let backend = memory(&editor);
+        assert!(backend.is_raw());
+        assert_eq!(backend.line(0), "hello, world");
+        assert_eq!(backend.line(1), "second");
+        assert_eq!(backend.line(2), "~");
+        assert_eq!(backend.line(5), "-- COMMAND MODE --");
+        assert_eq!(backend.cursor(), (12, 0));
+    }
+
+    #[test]
+    fn test_quitting_on_a_memory_terminal() {
+        let mut editor = memory_editor(&["text"], &[Key::Ctrl('q')]);
+        drive(&mut editor);
+        assert!(editor.should_quit);
+        assert_eq!(memory(&editor).line(0), "Goodbye.");
+    }
+
+    #[test]
+    fn test_resize_while_waiting_for_a_key() {
+        let lines: Vec<String> = (0..10).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&[Key::Down; 3]);
+        backend.push_resize(12, 4);
+        let mut editor = editor_on(&lines, backend);
+        drive(&mut editor);
+        let backend = memory(&editor);
+        // Two text lines are left, and the cursor's line stays in view.
+        assert_eq!(backend.line(0), "line 2");
+        assert_eq!(backend.line(1), "line 3");
+        assert_eq!(backend.line(2), "[No Name] -");
+        assert_eq!(backend.cursor(), (0, 1));
+    }
+
+    fn ago(seconds: u64) -> Instant {
+        Instant::now()
+            .checked_sub(Duration::from_secs(seconds))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_timers_run_while_waiting_for_a_key() {
+        let directory =
+            std::env::temp_dir().join(format!("orgonaut-timers-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("notes.org");
+        std::fs::write(&path, "* One\n").unwrap();
+        let mut backend = MemoryBackend::new(60, 6);
+        backend.push_keys(&[Key::Char('x')]);
+        backend.push_timeout();
+        backend.push_timeout();
+        let terminal = Terminal::new(Box::new(backend)).unwrap();
+        let document = Document::open(&path.to_string_lossy()).unwrap();
+        let mut editor = Editor::new(terminal, document, "Welcome".to_string());
+        editor.refresh_screen().unwrap();
+        assert_eq!(memory(&editor).line(5), "Welcome");
+        // The message expires without another key press.
+        editor.status_message.time = ago(10);
+        editor.process_keypress().unwrap();
+        editor.refresh_screen().unwrap();
+        editor.process_keypress().unwrap_err();
+        assert_eq!(memory(&editor).line(5), "");
+        // An idle, modified buffer is autosaved once.
+        editor.last_key = ago(60);
+        editor.run_timers();
+        let autosave = directory.join("#notes.org#");
+        assert_eq!(std::fs::read_to_string(&autosave).unwrap(), " One\n");
+        std::fs::remove_file(&autosave).unwrap();
+        editor.run_timers();
+        assert!(!autosave.exists());
+        // Changes by other programs are reported.
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        editor.last_file_check = ago(10);
+        editor.run_timers();
+        assert!(editor.status_message.text.contains("changed on disk"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_background_jobs() {
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_wait();
+        let mut editor = editor_on(&[], backend);
+        editor.spawn_job(|| {
+            let text = "done".to_string();
+            Box::new(move |editor: &mut Editor| {
+                editor.status_message = StatusMessage::from(text);
+            })
+        });
+        // The finished job ends the wait for input, without a timer.
+        drive(&mut editor);
+        assert_eq!(editor.running_jobs, 0);
+        assert_eq!(editor.status_message.text, "done");
+    }
+
+    #[test]
+    fn test_cursor_columns_with_wide_characters_and_tabs() {
+        let mut editor = memory_editor(&["中文ab", "a\tb"], &[Key::Right, Key::Right]);
+        drive(&mut editor);
+        assert_eq!(memory(&editor).line(0), "中文ab");
+        assert_eq!(memory(&editor).line(1), "a       b");
+        assert_eq!(memory(&editor).cursor(), (4, 0));
+        // Column 4 is inside the tab, so the cursor lands on it.
+        let mut editor = memory_editor(&["中文ab", "a\tb"], &[Key::Right, Key::Right, Key::Down]);
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position.x, 1);
+        assert_eq!(memory(&editor).cursor(), (1, 1));
+        // Scrolling keeps all of a wide character in view.
+        let wide = "中".repeat(20);
+        let mut editor = memory_editor(&[&wide], &[Key::Right; 15]);
+        drive(&mut editor);
+        assert_eq!(editor.offset.x, 2);
+        assert_eq!(memory(&editor).line(0), "中".repeat(15));
+        assert_eq!(memory(&editor).cursor(), (28, 0));
+    }
+
+    #[test]
+    fn test_soft_wrap() {
+        let lines = ["- one two three four five", "x"];
+        let mut backend = MemoryBackend::new(12, 6);
+        backend.push_keys(&[Key::Down, Key::Down]);
+        let mut editor = editor_on(&lines, backend);
+        editor.wrap = Wrap::Words;
+        drive(&mut editor);
+        let backend = memory(&editor);
+        assert_eq!(backend.line(0), "- one two");
+        assert_eq!(backend.line(1), "  three");
+        assert_eq!(backend.line(2), "  four five");
+        assert_eq!(backend.line(3), "x");
+        // Up and Down move by screen line.
+        assert_eq!(editor.cursor_position.x, 16);
+        assert_eq!(backend.cursor(), (2, 2));
+        // Scrolling goes by screen line too.
+        let mut backend = MemoryBackend::new(12, 5);
+        backend.push_keys(&[Key::Down; 3]);
+        let mut editor = editor_on(&lines, backend);
+        editor.wrap = Wrap::Words;
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position.y, 1);
+        assert_eq!(memory(&editor).line(0), "  three");
+        assert_eq!(memory(&editor).cursor(), (1, 2));
+    }
+
+    #[test]
+    fn test_operators_motions_and_counts() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let lines = ["one two three four", "  five", "six", "seven"];
+        let mut editor = memory_editor(&lines, &keys("2dwjdd"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["three four", "six", "seven"]);
+        assert_eq!(editor.cursor_position, Position { x: 0, y: 1 });
+        let mut keys = keys("wcwTWO");
+        keys.push(Key::Esc);
+        keys.extend("G>>gg$".chars().map(Key::Char));
+        let mut editor = memory_editor(&lines, &keys);
+        drive(&mut editor);
+        assert_eq!(editor.mode, Mode::Command);
+        assert_eq!(editor.document.lines()[0], "one TWO three four");
+        assert_eq!(editor.document.lines()[3], "  seven");
+        assert_eq!(editor.cursor_position, Position { x: 17, y: 0 });
+        // An incomplete command waits for more keys, and Esc cancels it.
+        let mut editor = memory_editor(&lines, &[Key::Char('d'), Key::Esc, Key::Char('x')]);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines()[0], "ne two three four");
+    }
+
+    #[test]
+    fn test_org_text_objects() {
+        let lines = ["* Tasks", "| name | count |", "** Done", "old", "* Next"];
+        let mut keys = vec![Key::Char('j'), Key::Char('w'), Key::Char('w')];
+        keys.extend("ciesum".chars().map(Key::Char));
+        keys.push(Key::Esc);
+        keys.extend("jjdar".chars().map(Key::Char));
+        let mut editor = memory_editor(&lines, &keys);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["* Tasks", "| name | sum |", "* Next"]);
+    }
+
+    #[test]
+    fn test_put_and_kill_ring() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut editor = memory_editor(&["one two", "three", "end"], &keys("dwjddp"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["two", "end", "three"]);
+        assert_eq!(editor.cursor_position, Position { x: 0, y: 2 });
+        // Alt-y swaps what was put for the kill before it.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("dwjddp"));
+        backend.push_keys(&[Key::Alt('y')]);
+        let mut editor = editor_on(&["one two", "three", "end"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["two", "eone nd"]);
+        let mut editor = memory_editor(&["alpha beta"], &keys("\"ayw$\"ap2yyP"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["alpha betaalpha ", "alpha betaalpha "]);
+    }
+
+    #[test]
+    fn test_visual_modes() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut editor = memory_editor(&["one two", "three"], &keys("lvjd"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["oree"]);
+        assert_eq!(editor.mode, Mode::Command);
+        let mut editor = memory_editor(&["a", "b", "c"], &keys("jVjyP2>"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["a", "b", "c", "b", "c"]);
+        let mut editor = memory_editor(&["say hello", "World"], &keys("wvjU0vl~"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["SAy HELLO", "WORLD"]);
+        // The selection is drawn with its own background.
+        let mut editor = memory_editor(&["abc"], &keys("vl"));
+        drive(&mut editor);
+        let background = |x| editor.screen.style(x, 0).and_then(|style| style.bg);
+        assert_eq!(background(1), Some(SELECTION_BG_COLOR));
+        assert_eq!(background(2), None);
+    }
+
+    #[test]
+    fn test_visual_block() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let table = ["| a | b |", "| c | d |", "| e | f |"];
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("2l"));
+        backend.push_keys(&[Key::Ctrl('v')]);
+        backend.push_keys(&keys("jj3ld$p"));
+        let mut editor = editor_on(&table, backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["| b |a | ", "| d |c | ", "| f |e | "]);
+        // `I` skips rows that end before the block; `$A` appends to every
+        // row.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("2l"));
+        backend.push_keys(&[Key::Ctrl('v')]);
+        backend.push_keys(&keys("3j2lI[ ] "));
+        backend.push_keys(&[Key::Esc, Key::Ctrl('v')]);
+        backend.push_keys(&keys("3j$A;"));
+        backend.push_keys(&[Key::Esc]);
+        let mut editor = editor_on(&["- one", "- two", "", "- three"], backend);
+        drive(&mut editor);
+        assert_eq!(
+            editor.document.lines(),
+            ["- [ ] one;", "- [ ] two;", ";", "- [ ] three;"]
+        );
+        // `A` pads short rows; `c` changes the block on each row.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&[Key::Ctrl('v')]);
+        backend.push_keys(&keys("jlA|"));
+        backend.push_keys(&[Key::Esc, Key::Char('0'), Key::Ctrl('v')]);
+        backend.push_keys(&keys("jcX"));
+        backend.push_keys(&[Key::Esc]);
+        let mut editor = editor_on(&["ab", "c"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["Xb|", "X |"]);
+    }
+
+    #[test]
+    fn test_repeat() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut editor = memory_editor(&["one two three four five six"], &keys("dw.2."));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["five six"]);
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("ciwfoo"));
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys("w.jAend"));
+        backend.push_paste("!");
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys("k."));
+        let mut editor = editor_on(&["a b", "c"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["foo fooend!", "cend!"]);
+        // Yanks and motions leave the change to repeat alone.
+        let mut editor = memory_editor(&["- a", "- b", "- c"], &keys(">>jyyj."));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["  - a", "- b", "  - c"]);
+    }
+
+    #[test]
+    fn test_macros() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("qaI- "));
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys("jq@a2@@"));
+        let mut editor = editor_on(&["a", "b", "c", "d", "e"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["- a", "- b", "- c", "- d", "e"]);
+        // A macro is text in its register: put, edited and yanked back, it
+        // runs on each selected line.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("qbA;"));
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys("qGo"));
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys("\"bp0lcl,"));
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys("0\"by$ddggjVj@b"));
+        let mut editor = editor_on(&["x", "y", "z"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.registers.get(Some('b')).unwrap().text, "A,<Esc>");
+        assert_eq!(editor.document.lines(), ["x;", "y,", "z,"]);
+    }
+
+    #[test]
+    fn test_clipboard_and_paste() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut editor = memory_editor(&["one two", "three"], &keys("\"+yyj\"*yw\"+P"));
+        drive(&mut editor);
+        // Without a paste command the register holds what was copied.
+        assert_eq!(editor.document.lines(), ["one two", "one two", "three"]);
+        assert_eq!(
+            memory(&editor).clipboard(),
+            Some(&(Selection::Primary, "three".to_string()))
+        );
+        // Pasted text goes in verbatim: no keys are run, even in command
+        // mode, and newlines are not indented.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("A "));
+        backend.push_paste("dd\n  - item\n");
+        backend.push_keys(&[Key::Esc, Key::Char('0')]);
+        backend.push_paste("x");
+        let mut editor = editor_on(&["- list"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["- list dd", "  - item", "x"]);
+        assert_eq!(editor.cursor_position, Position { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn test_command_line() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut editor = memory_editor(
+            &["one two", "three", "four one"],
+            &keys(":%s/o(\\w)/<\\1&>/g\n"),
+        );
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["<non>e two", "three", "f<uou>r <non>e"]);
+        assert_eq!(editor.status_message.text, "3 substitutions on 2 lines");
+        assert_eq!(editor.cursor_position.y, 2);
+        // `:g` runs on the rows that matched, wherever earlier runs moved
+        // them.
+        let mut editor = memory_editor(&["a1", "b", "a2"], &keys(":g/a/normal o-\n:v/-/s/$/!/\n"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["a1!", "-", "b!", "a2!", "-"]);
+        assert_eq!(editor.mode, Mode::Command);
+        // A selection or a count gives the range.
+        let mut editor = memory_editor(&["a", "b", "c", "d"], &keys("jVj:s/^/# /\n:1\n2:d\n"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["# c", "d"]);
+        let mut editor = memory_editor(
+            &["x"],
+            &keys(":set ts=4 sw=3 nowrap\n:se ts?\n>>:set sw=0\n"),
+        );
+        drive(&mut editor);
+        assert_eq!((editor.tab_stop, editor.wrap), (4, Wrap::None));
+        assert_eq!(editor.document.lines(), ["   x"]);
+        assert_eq!(editor.status_message.text, "Invalid argument: sw=0");
+        let mut editor = memory_editor(&["x"], &keys(":se ts?\n:foo\n"));
+        drive(&mut editor);
+        assert_eq!(editor.status_message.text, "Not an editor command: foo");
+        let mut editor = memory_editor(&["x"], &keys("x:q\n"));
+        drive(&mut editor);
+        assert!(!editor.should_quit);
+        assert_eq!(
+            editor.status_message.text,
+            "No write since last change (add ! to override)"
+        );
+    }
+
+    #[test]
+    fn test_command_line_history() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys(":se\t ts=3\n:s/x/y/\n:"));
+        backend.push_keys(&[Key::Up, Key::Up, Key::Down, Key::Char('\n')]);
+        backend.push_keys(&keys(":se"));
+        backend.push_keys(&[Key::Up, Key::Char('\n')]);
+        let mut editor = editor_on(&["xxx"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.tab_stop, 3);
+        assert_eq!(editor.document.lines(), ["yyx"]);
+        assert_eq!(editor.command_history.entries(), ["s/x/y/", "set ts=3"]);
+    }
+
+    #[test]
+    fn test_write_and_edit() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let directory = std::env::temp_dir().join(format!("orgonaut-ex-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("notes.org");
+        let copy = directory.join("copy.org");
+        let commands = format!(
+            ":w {}\nx:w {}\n:e {}\n:e!\n",
+            file.display(),
+            copy.display(),
+            copy.display()
+        );
+        let mut editor = memory_editor(&["one"], &keys(&commands));
+        drive(&mut editor);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "one\n");
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "ne\n");
+        // `:w file` wrote a copy, so the buffer is still modified and `:e`
+        // refused; `:e!` reloaded it.
+        assert_eq!(editor.document.lines(), ["one"]);
+        assert!(!editor.document.is_dirty());
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys(&format!(":e {}\nitwo", copy.display())));
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys(":wq\n"));
+        let mut editor = editor_on(&[], backend);
+        drive(&mut editor);
+        assert!(editor.should_quit);
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "twone\n");
+        // Other existing files are only written over with `!`.
+        let mut editor = memory_editor(&["new"], &keys(&format!(":w {}\n", file.display())));
+        drive(&mut editor);
+        assert_eq!(editor.status_message.text, "File exists (add ! to override)");
+        assert_eq!(editor.document.file_name, None);
+        let commands = format!(":e {}\n:w {}\n", copy.display(), file.display());
+        let mut editor = memory_editor(&["new"], &keys(&commands));
+        drive(&mut editor);
+        assert_eq!(editor.status_message.text, "File exists (add ! to override)");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "one\n");
+        let mut editor = memory_editor(&["new"], &keys(&format!(":w! {}\n", file.display())));
+        drive(&mut editor);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new\n");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_export_overwrite() {
+        let directory = std::env::temp_dir().join(format!("orgonaut-export-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let export = |file: &std::path::Path, answer: &str| {
+            let mut backend = MemoryBackend::new(30, 6);
+            backend.push_keys(&[Key::Ctrl('e')]);
+            backend.push_keys(&format!("ascii\n{}", answer).chars().map(Key::Char).collect::<Vec<_>>());
+            let mut editor = editor_on(&["text"], backend);
+            editor.document.file_name = Some(file.display().to_string());
+            drive(&mut editor);
+            editor.status_message.text
+        };
+        // A buffer named `notes.txt` would export onto itself.
+        let text = directory.join("notes.txt");
+        std::fs::write(&text, "source\n").unwrap();
+        assert!(export(&text, "").starts_with("Export would overwrite the document itself"));
+        assert_eq!(std::fs::read_to_string(&text).unwrap(), "source\n");
+        let org = directory.join("notes.org");
+        assert_eq!(export(&org, "n\n"), "Export aborted.");
+        assert_eq!(std::fs::read_to_string(&text).unwrap(), "source\n");
+        assert_eq!(export(&org, "y\n"), format!("Exported to {}", text.display()));
+        assert_eq!(std::fs::read_to_string(&text).unwrap(), "text\n");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_export_text_width() {
+        let directory = std::env::temp_dir().join(format!("orgonaut-export-width-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("notes.org");
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&":set tw=10\n".chars().map(Key::Char).collect::<Vec<_>>());
+        backend.push_keys(&[Key::Ctrl('e')]);
+        backend.push_keys(&"ascii\n".chars().map(Key::Char).collect::<Vec<_>>());
+        let mut editor = editor_on(&["one two three four"], backend);
+        editor.document.file_name = Some(file.display().to_string());
+        drive(&mut editor);
+        let exported = std::fs::read_to_string(directory.join("notes.txt")).unwrap();
+        assert_eq!(exported, "one two\nthree four\n");
+        assert_eq!(editor.set_option(ex::Setting::Query("tw".to_string())), Ok(Some("textwidth=10".to_string())));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_save_as_existing_file() {
+        let directory = std::env::temp_dir().join(format!("orgonaut-save-as-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("notes.org");
+        std::fs::write(&file, "* Kept\n").unwrap();
+        let save_as = |answer: &str| {
+            let mut backend = MemoryBackend::new(30, 6);
+            backend.push_keys(&[Key::Ctrl('s')]);
+            let typed = format!("{}\n{}", file.display(), answer);
+            backend.push_keys(&typed.chars().map(Key::Char).collect::<Vec<_>>());
+            let mut editor = editor_on(&["* New"], backend);
+            drive(&mut editor);
+            editor.status_message.text
+        };
+        assert_eq!(save_as("n\n"), "Save aborted.");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "* Kept\n");
+        assert_eq!(save_as("y\n"), "File saved successfully.");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "* New\n");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_search() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let at = |x, y| Position { x, y };
+        let lines = ["One two", "one Two", "three one"];
+        // Lowercase patterns ignore case, and `n` wraps around the end.
+        let mut editor = memory_editor(&lines, &keys("/one\nn"));
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position, at(6, 2));
+        let mut editor = memory_editor(&lines, &keys("/one\nnn"));
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position, at(0, 0));
+        assert_eq!(
+            editor.status_message.text,
+            "search hit BOTTOM, continuing at TOP"
+        );
+        let mut editor = memory_editor(&lines, &keys("/one\nnnN"));
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position, at(6, 2));
+        let mut editor = memory_editor(&lines, &keys("G?Two\n"));
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position, at(4, 1));
+        let mut editor = memory_editor(&["a 12", "b 345"], &keys("/\\d{3}\n"));
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position, at(2, 1));
+        let mut editor = memory_editor(&["a.b", "axb"], &keys(":set literal\n/x.b\n"));
+        drive(&mut editor);
+        assert_eq!(editor.status_message.text, "Pattern not found: x.b");
+        // An empty `:s` pattern is the last search.
+        let mut editor = memory_editor(&lines, &keys("/t\\w+\n:%s//2/g\n"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["One 2", "one 2", "2 one"]);
+    }
+
+    #[test]
+    fn test_incremental_search() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let matched = |editor: &Editor, x| {
+            editor.screen.style(x, 1).and_then(|style| style.fg)
+                == Some(highlighting::Type::Match.to_color())
+        };
+        // The arrow keys go on to the next match before `Enter`, and the
+        // matches stay highlighted.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("/ab"));
+        backend.push_keys(&[Key::Right, Key::Char('\n')]);
+        let mut editor = editor_on(&["ab", "b ab abc"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position, Position { x: 5, y: 1 });
+        assert!(!matched(&editor, 1));
+        assert!([2, 3, 5, 6].iter().all(|x| matched(&editor, *x)));
+        // `Esc` goes back, without highlighting.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("/ab"));
+        backend.push_keys(&[Key::Right, Key::Esc]);
+        let mut editor = editor_on(&["ab", "b ab abc"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.cursor_position, Position { x: 0, y: 0 });
+        assert!(editor.highlighted_pattern.is_none());
+    }
+
+    #[test]
+    fn test_empty_operations() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        // `x` on an empty row and `d0` at the start of one change nothing.
+        let mut editor = memory_editor(&["one", ""], &keys("ywjxkd0"));
+        drive(&mut editor);
+        assert!(!editor.document.is_dirty());
+        assert_eq!(editor.registers.get(None).unwrap().text, "one");
+        editor.undo(None, false);
+        assert_eq!(editor.status_message.text, "Already at oldest change");
+        // `c` still starts inserting.
+        let mut editor = memory_editor(&["", "two"], &keys("clx"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["x", "two"]);
+    }
+
+    #[test]
+    fn test_undo() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("dwxAend"));
+        backend.push_keys(&[Key::Esc]);
+        backend.push_keys(&keys("u2u"));
+        backend.push_keys(&[Key::Ctrl('r')]);
+        let mut editor = editor_on(&["one two"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["two"]);
+        assert_eq!(editor.cursor_position, Position { x: 0, y: 0 });
+        // A command line is one step, however many rows it changes.
+        let mut editor = memory_editor(&["a", "b", "a"], &keys(":g/a/normal ix\n:%s/$/;/\nu"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["xa", "b", "xa"]);
+        let mut editor = memory_editor(&["a"], &keys("uu"));
+        drive(&mut editor);
+        assert_eq!(editor.status_message.text, "Already at oldest change");
+        // Undoing back to the saved text leaves the document unmodified.
+        let directory = std::env::temp_dir().join(format!("orgonaut-undo-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("notes.org");
+        let undo = |extra: &[Key]| {
+            let _ = std::fs::remove_file(&file);
+            let mut backend = MemoryBackend::new(30, 6);
+            backend.push_keys(&keys(&format!("x:w {}\nxu", file.display())));
+            backend.push_keys(extra);
+            let mut editor = editor_on(&["abc"], backend);
+            drive(&mut editor);
+            (editor.document.lines(), editor.document.is_dirty())
+        };
+        assert_eq!(undo(&[]), (vec!["bc".to_string()], false));
+        assert_eq!(undo(&[Key::Char('u')]), (vec!["abc".to_string()], true));
+        assert_eq!(undo(&[Key::Char('u'), Key::Ctrl('r')]), (vec!["bc".to_string()], false));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_query_replace() {
+        let keys = |text: &str| -> Vec<Key> { text.chars().map(Key::Char).collect() };
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&[Key::Alt('%')]);
+        backend.push_keys(&keys("a(\\d)\n<\\1>\nyna"));
+        let mut editor = editor_on(&["a1 a2", "a3 a4"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["<1> a2", "<3> <4>"]);
+        assert_eq!(editor.status_message.text, "3 substitutions on 2 lines");
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&[Key::Alt('%')]);
+        backend.push_keys(&keys("a(\\d)\n<\\1>\nynau"));
+        let mut editor = editor_on(&["a1 a2", "a3 a4"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["a1 a2", "a3 a4"]);
+        // In a selection; an empty replacement deletes. `l` replaces one
+        // more and stops, `q` stops.
+        let mut backend = MemoryBackend::new(30, 6);
+        backend.push_keys(&keys("jVj"));
+        backend.push_keys(&[Key::Alt('%')]);
+        backend.push_keys(&keys("x\n\nyyy:%s/x/z/gc\nl"));
+        let mut editor = editor_on(&["x", "xx", "x"], backend);
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["z", "", ""]);
+        let mut editor = memory_editor(&["x", "x"], &keys(":%s/x/y/c\nq"));
+        drive(&mut editor);
+        assert_eq!(editor.document.lines(), ["x", "x"]);
+        assert_eq!(editor.status_message.text, "0 substitutions on 0 lines");
+    }
+
+    #[test]
+    fn test_current_match() {
+        let mut editor = memory_editor(&["abc"], &[]);
+        editor.current_match = Some((0, 1..2));
+        editor.refresh_screen().unwrap();
+        let background = |x| editor.screen.style(x, 0).and_then(|style| style.bg);
+        assert_eq!(background(0), None);
+        assert_eq!(background(1), Some(SELECTION_BG_COLOR));
+        assert_eq!(background(2), None);
+    }
 }
@@ -0,0 +1,697 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// Command lines kept in the history file.
+const HISTORY_SIZE: usize = 100;
+
+// The commands with the shortest abbreviation of each, as in Vim: `s` is
+// `substitute` and `se` is `set`.
+const COMMANDS: [(&str, usize); 12] = [
+    ("delete", 1),
+    ("edit", 1),
+    ("global", 1),
+    ("nohlsearch", 3),
+    ("normal", 4),
+    ("quit", 1),
+    ("set", 2),
+    ("substitute", 1),
+    ("vglobal", 1),
+    ("wq", 2),
+    ("write", 1),
+    ("xit", 1),
+];
+
+// The options `:set` knows, for completion.
+pub const OPTIONS: [&str; 8] = [
+    "ignorecase",
+    "linebreak",
+    "literal",
+    "shiftwidth",
+    "smartcase",
+    "tabstop",
+    "textwidth",
+    "wrap",
+];
+
+#[derive(PartialEq, Clone, Debug)]
+enum Base {
+    Current,
+    Last,
+    // As typed, counting from 1.
+    Line(usize),
+    // `'<` and `'>`, the first and last rows of the last selection.
+    SelectionStart,
+    SelectionEnd,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Address {
+    base: Base,
+    offset: isize,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum LineRange {
+    // `%`.
+    Whole,
+    Between(Address, Address),
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Setting {
+    // `name=value`.
+    Assign(String, String),
+    // `name`, which shows the value of an option that is not a flag.
+    Enable(String),
+    // `noname`.
+    Disable(String),
+    // `name?`.
+    Query(String),
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Substitute {
+    // Empty for the last search pattern, as for `:g`.
+    pub pattern: String,
+    // In the `regex` crate's syntax: `${1}` for a group.
+    pub replacement: String,
+    // `g`, every match in a row rather than the first.
+    pub all: bool,
+    // `i` and `I`; `None` without either.
+    pub ignore_case: Option<bool>,
+    // `c`, asking before each replacement.
+    pub confirm: bool,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Action {
+    // A range alone moves to its last row.
+    Goto,
+    Write {
+        file: Option<String>,
+        quit: bool,
+        force: bool,
+    },
+    Quit {
+        force: bool,
+    },
+    Edit {
+        file: Option<String>,
+        force: bool,
+    },
+    Substitute(Substitute),
+    // `:g/pattern/command`, or `:g!` and `:v` for the rows that do not
+    // match.
+    Global {
+        pattern: String,
+        invert: bool,
+        command: String,
+    },
+    Delete,
+    // `:noh` hides the highlighted search matches until the next search.
+    NoHighlight,
+    // Keys, in the notation of macros.
+    Normal(String),
+    Set(Vec<Setting>),
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Command {
+    pub range: Option<LineRange>,
+    pub action: Action,
+}
+
+impl Address {
+    // `current` and the result count from 0.
+    fn resolve(
+        &self,
+        current: usize,
+        len: usize,
+        selection: Option<&Range<usize>>,
+    ) -> Result<usize, String> {
+        let no_selection = || "No previous selection".to_string();
+        let base = match self.base {
+            Base::Current => current,
+            Base::Last => len.saturating_sub(1),
+            Base::Line(line) => line.saturating_sub(1),
+            Base::SelectionStart => selection.ok_or_else(no_selection)?.start,
+            Base::SelectionEnd => selection.ok_or_else(no_selection)?.end.saturating_sub(1),
+        };
+        base.checked_add_signed(self.offset)
+            .filter(|line| *line < len.max(1))
+            .ok_or_else(|| "Invalid range".to_string())
+    }
+}
+
+impl LineRange {
+    // The rows of the range; a backwards range is turned around.
+    pub fn resolve(
+        &self,
+        current: usize,
+        len: usize,
+        selection: Option<&Range<usize>>,
+    ) -> Result<Range<usize>, String> {
+        match self {
+            Self::Whole => Ok(0..len),
+            Self::Between(first, last) => {
+                let first = first.resolve(current, len, selection)?;
+                let last = last.resolve(current, len, selection)?;
+                Ok(first.min(last)..first.max(last).saturating_add(1))
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = self.rest.get(c.len_utf8()..).unwrap_or_default();
+        Some(c)
+    }
+    fn eat(&mut self, c: char) -> bool {
+        let eaten = self.peek() == Some(c);
+        if eaten {
+            self.next();
+        }
+        eaten
+    }
+    fn skip_blanks(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+    fn number(&mut self) -> Option<usize> {
+        let digits = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let number = self.rest.get(..digits)?.parse().ok()?;
+        self.rest = self.rest.get(digits..).unwrap_or_default();
+        Some(number)
+    }
+    fn address(&mut self) -> Result<Option<Address>, String> {
+        let base = match self.peek() {
+            Some(c) if c.is_ascii_digit() => self.number().map(Base::Line),
+            Some('.') => {
+                self.next();
+                Some(Base::Current)
+            }
+            Some('$') => {
+                self.next();
+                Some(Base::Last)
+            }
+            Some('\'') => {
+                self.next();
+                match self.next() {
+                    Some('<') => Some(Base::SelectionStart),
+                    Some('>') => Some(Base::SelectionEnd),
+                    _ => return Err("Unknown mark".to_string()),
+                }
+            }
+            _ => None,
+        };
+        let mut offset: isize = 0;
+        let mut has_offset = false;
+        while let Some(sign) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.next();
+            has_offset = true;
+            let amount = isize::try_from(self.number().unwrap_or(1)).unwrap_or(isize::MAX);
+            offset = if sign == '+' {
+                offset.saturating_add(amount)
+            } else {
+                offset.saturating_sub(amount)
+            };
+        }
+        if base.is_none() && !has_offset {
+            return Ok(None);
+        }
+        Ok(Some(Address {
+            base: base.unwrap_or(Base::Current),
+            offset,
+        }))
+    }
+    fn range(&mut self) -> Result<Option<LineRange>, String> {
+        self.skip_blanks();
+        if self.eat('%') {
+            return Ok(Some(LineRange::Whole));
+        }
+        let first = self.address()?;
+        if !self.eat(',') && !self.eat(';') {
+            return Ok(first.map(|first| LineRange::Between(first.clone(), first)));
+        }
+        let current = Address {
+            base: Base::Current,
+            offset: 0,
+        };
+        let last = self.address()?.unwrap_or_else(|| current.clone());
+        Ok(Some(LineRange::Between(first.unwrap_or(current), last)))
+    }
+    fn name(&mut self) -> &str {
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(self.rest.len());
+        let (name, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        name
+    }
+    // Up to an unescaped `delimiter`, which is dropped along with the
+    // backslash escaping it. Returns whether the delimiter was found.
+    fn delimited(&mut self, delimiter: char) -> (String, bool) {
+        let mut text = String::new();
+        while let Some(c) = self.next() {
+            if c == delimiter {
+                return (text, true);
+            }
+            if c == '\\' {
+                match self.next() {
+                    Some(escaped) if escaped == delimiter => text.push(escaped),
+                    Some(escaped) => {
+                        text.push('\\');
+                        text.push(escaped);
+                    }
+                    None => text.push('\\'),
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        (text, false)
+    }
+    fn delimiter(&mut self) -> Result<char, String> {
+        match self.next() {
+            Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => Ok(c),
+            _ => Err("Missing pattern".to_string()),
+        }
+    }
+    // Arguments of commands that take none.
+    fn end(&mut self) -> Result<(), String> {
+        self.skip_blanks();
+        match self.rest {
+            "" => Ok(()),
+            rest => Err(format!("Trailing characters: {}", rest)),
+        }
+    }
+    fn argument(&mut self) -> Option<String> {
+        let argument = self.rest.trim();
+        self.rest = "";
+        (!argument.is_empty()).then(|| argument.to_string())
+    }
+}
+
+fn command_name(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|(full, shortest)| name.len() >= *shortest && full.starts_with(name))
+        .map(|(full, _)| *full)
+}
+
+// Turns a Vim replacement into the `regex` crate's: `\1` and `&` are groups,
+// `\r` and `\n` line breaks and `\t` a tab.
+pub fn replacement(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => result.push_str("${0}"),
+            '$' => result.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => result.push_str(&format!("${{{}}}", digit)),
+                Some('r' | 'n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            },
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn substitute(parser: &mut Parser) -> Result<Action, String> {
+    let delimiter = parser.delimiter()?;
+    let (pattern, _) = parser.delimited(delimiter);
+    let (replacement_text, _) = parser.delimited(delimiter);
+    let mut substitute = Substitute {
+        pattern,
+        replacement: replacement(&replacement_text),
+        all: false,
+        ignore_case: None,
+        confirm: false,
+    };
+    parser.skip_blanks();
+    while let Some(flag) = parser.next() {
+        match flag {
+            'g' => substitute.all = true,
+            'c' => substitute.confirm = true,
+            'i' => substitute.ignore_case = Some(true),
+            'I' => substitute.ignore_case = Some(false),
+            _ if flag.is_whitespace() => (),
+            _ => return Err(format!("Invalid flag: {}", flag)),
+        }
+    }
+    Ok(Action::Substitute(substitute))
+}
+
+fn settings(arguments: &str) -> Vec<Setting> {
+    arguments
+        .split_whitespace()
+        .map(|argument| {
+            if let Some((name, value)) = argument.split_once(['=', ':']) {
+                Setting::Assign(name.to_string(), value.to_string())
+            } else if let Some(name) = argument.strip_suffix('?') {
+                Setting::Query(name.to_string())
+            } else if let Some(name) = argument.strip_prefix("no") {
+                Setting::Disable(name.to_string())
+            } else {
+                Setting::Enable(argument.to_string())
+            }
+        })
+        .collect()
+}
+
+// Parses a command line typed after `:`.
+pub fn parse(line: &str) -> Result<Command, String> {
+    let mut parser = Parser { rest: line };
+    let range = parser.range()?;
+    parser.skip_blanks();
+    let typed = parser.name().to_string();
+    if typed.is_empty() {
+        parser.end()?;
+        return match range {
+            Some(range) => Ok(Command {
+                range: Some(range),
+                action: Action::Goto,
+            }),
+            None => Err("Missing command".to_string()),
+        };
+    }
+    let name =
+        command_name(&typed).ok_or_else(|| format!("Not an editor command: {}", line.trim()))?;
+    let force = parser.eat('!');
+    let action = match name {
+        "write" | "wq" | "xit" => Action::Write {
+            file: parser.argument(),
+            quit: name != "write",
+            force,
+        },
+        "quit" => {
+            parser.end()?;
+            Action::Quit { force }
+        }
+        "edit" => Action::Edit {
+            file: parser.argument(),
+            force,
+        },
+        "substitute" => substitute(&mut parser)?,
+        "global" | "vglobal" => {
+            let delimiter = parser.delimiter()?;
+            let (pattern, _) = parser.delimited(delimiter);
+            let command = parser.rest.trim().to_string();
+            if command.is_empty() {
+                return Err("Missing command".to_string());
+            }
+            Action::Global {
+                pattern,
+                invert: force || name == "vglobal",
+                command,
+            }
+        }
+        "delete" => {
+            parser.end()?;
+            Action::Delete
+        }
+        "nohlsearch" => {
+            parser.end()?;
+            Action::NoHighlight
+        }
+        "normal" => Action::Normal(parser.rest.trim_start().to_string()),
+        _ => Action::Set(settings(parser.rest)),
+    };
+    let ranged = !matches!(
+        action,
+        Action::Write { .. }
+            | Action::Quit { .. }
+            | Action::Edit { .. }
+            | Action::NoHighlight
+            | Action::Set(_)
+    );
+    if range.is_some() && !ranged {
+        return Err("No range allowed".to_string());
+    }
+    Ok(Command { range, action })
+}
+
+// The paths starting with `typed`; directories end with a slash. Hidden
+// files are left out unless `typed` names one.
+fn complete_path(typed: &str) -> Vec<String> {
+    let (directory, prefix) = match typed.rfind('/') {
+        Some(slash) => typed.split_at(slash.saturating_add(1)),
+        None => ("", typed),
+    };
+    let listed = if directory.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(directory)
+    };
+    let mut paths: Vec<String> = fs::read_dir(listed)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", directory, name, slash))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+// The ways to complete `line`: command names, file names after `:w` and
+// `:e`, and option names after `:set`. Each is the whole line.
+pub fn complete(line: &str) -> Vec<String> {
+    let start = line
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(line.len());
+    let (range, rest) = line.split_at(start);
+    let Some((typed, argument)) = rest.split_once(' ') else {
+        return COMMANDS
+            .iter()
+            .filter(|(name, _)| name.starts_with(rest))
+            .map(|(name, _)| format!("{}{}", range, name))
+            .collect();
+    };
+    let head = format!("{}{} ", range, typed);
+    let argument = argument.trim_start();
+    match command_name(typed.trim_end_matches('!')) {
+        Some("write" | "wq" | "xit" | "edit") => complete_path(argument)
+            .into_iter()
+            .map(|path| format!("{}{}", head, path))
+            .collect(),
+        Some("set") => {
+            let (before, last) = argument.rsplit_once(' ').unwrap_or(("", argument));
+            let before = if before.is_empty() {
+                String::new()
+            } else {
+                format!("{} ", before)
+            };
+            OPTIONS
+                .iter()
+                .filter(|option| option.starts_with(last))
+                .map(|option| format!("{}{}{}", head, before, option))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+// `$XDG_STATE_HOME/orgonaut/history`, or `~/.local/state/orgonaut/history`.
+pub fn history_file() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+        })?;
+    Some(state.join("orgonaut").join("history"))
+}
+
+// The command lines entered, oldest first. With a file, they are kept
+// between sessions.
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    pub fn load(file: PathBuf) -> Self {
+        let entries = fs::read_to_string(&file)
+            .map(|text| text.lines().map(String::from).collect())
+            .unwrap_or_default();
+        Self {
+            entries,
+            file: Some(file),
+        }
+    }
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+    // Moves a repeated line to the end. The file is written right away;
+    // failing to is not worth interrupting the command for.
+    pub fn add(&mut self, line: &str) {
+        self.entries.retain(|entry| entry != line);
+        self.entries.push(line.to_string());
+        let excess = self.entries.len().saturating_sub(HISTORY_SIZE);
+        self.entries.drain(..excess);
+        if let Some(file) = &self.file {
+            if let Some(directory) = file.parent() {
+                let _ = fs::create_dir_all(directory);
+            }
+            let _ = fs::write(file, self.entries.join("\n") + "\n");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line: usize) -> Address {
+        Address {
+            base: Base::Line(line),
+            offset: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("w notes.org"),
+            Ok(Command {
+                range: None,
+                action: Action::Write {
+                    file: Some("notes.org".to_string()),
+                    quit: false,
+                    force: false,
+                },
+            })
+        );
+        assert_eq!(
+            parse("wq").map(|command| command.action),
+            Ok(Action::Write {
+                file: None,
+                quit: true,
+                force: false,
+            })
+        );
+        assert_eq!(
+            parse("w! notes.org").map(|command| command.action),
+            Ok(Action::Write {
+                file: Some("notes.org".to_string()),
+                quit: false,
+                force: true,
+            })
+        );
+        assert_eq!(
+            parse("q!").map(|command| command.action),
+            Ok(Action::Quit { force: true })
+        );
+        assert_eq!(
+            parse("12"),
+            Ok(Command {
+                range: Some(LineRange::Between(line(12), line(12))),
+                action: Action::Goto,
+            })
+        );
+        assert_eq!(
+            parse("%s/a\\/b/[&\\1$]/gci"),
+            Ok(Command {
+                range: Some(LineRange::Whole),
+                action: Action::Substitute(Substitute {
+                    pattern: "a/b".to_string(),
+                    replacement: "[${0}${1}$$]".to_string(),
+                    all: true,
+                    ignore_case: Some(true),
+                    confirm: true,
+                }),
+            })
+        );
+        assert_eq!(
+            parse("g!/^\\*/normal >>").map(|command| command.action),
+            Ok(Action::Global {
+                pattern: "^\\*".to_string(),
+                invert: true,
+                command: "normal >>".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("se ts=4 nowrap wrap?").map(|command| command.action),
+            Ok(Action::Set(vec![
+                Setting::Assign("ts".to_string(), "4".to_string()),
+                Setting::Disable("wrap".to_string()),
+                Setting::Query("wrap".to_string()),
+            ]))
+        );
+        assert_eq!(parse("foo"), Err("Not an editor command: foo".to_string()));
+        assert_eq!(parse("s/a/b/z"), Err("Invalid flag: z".to_string()));
+        assert_eq!(parse("1,2q"), Err("No range allowed".to_string()));
+        assert_eq!(parse("g/a/"), Err("Missing command".to_string()));
+        assert_eq!(parse("d x"), Err("Trailing characters: x".to_string()));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let rows = |text: &str| {
+            let range = parse(text).ok()?.range?;
+            range.resolve(4, 10, Some(&(2..5))).ok()
+        };
+        assert_eq!(rows("3,5d"), Some(2..5));
+        assert_eq!(rows(".,+2d"), Some(4..7));
+        assert_eq!(rows("-,$d"), Some(3..10));
+        assert_eq!(rows("'<,'>d"), Some(2..5));
+        assert_eq!(rows("5,3d"), Some(2..5));
+        assert_eq!(rows("$+1"), None);
+        assert_eq!(rows("%d"), Some(0..10));
+    }
+
+    #[test]
+    fn test_completion() {
+        assert_eq!(complete("se"), ["set"]);
+        assert_eq!(complete("'<,'>nor"), ["'<,'>normal"]);
+        assert_eq!(complete("w"), ["wq", "write"]);
+        assert_eq!(complete("set tabstop=2 w"), ["set tabstop=2 wrap"]);
+        let directory =
+            std::env::temp_dir().join(format!("orgonaut-complete-{}", std::process::id()));
+        fs::create_dir_all(directory.join("notes")).unwrap();
+        fs::write(directory.join("note.org"), "").unwrap();
+        let typed = format!("e {}/not", directory.display());
+        let completed: Vec<String> = complete(&typed)
+            .iter()
+            .map(|line| line.replace(&directory.display().to_string(), "~"))
+            .collect();
+        assert_eq!(completed, ["e ~/note.org", "e ~/notes/"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_history() {
+        let file = std::env::temp_dir().join(format!("orgonaut-history-{}", std::process::id()));
+        let mut history = History::load(file.clone());
+        history.add("w");
+        history.add("s/a/b/");
+        history.add("w");
+        assert_eq!(History::load(file.clone()).entries(), ["s/a/b/", "w"]);
+        fs::remove_file(&file).unwrap();
+    }
+}
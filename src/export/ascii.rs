@@ -0,0 +1,476 @@
+use crate::org::{
+    Block, Element, ElementKind, Headline, Inline, List, ListItem, OrgDocument, SourceText, Table,
+    TableRow, parse_inline,
+};
+use std::collections::HashMap;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Charset {
+    Ascii,
+    Utf8,
+}
+
+pub struct Options {
+    pub width: usize,
+    pub charset: Charset,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            width: 72,
+            charset: Charset::Ascii,
+        }
+    }
+}
+
+struct Exporter<'a> {
+    options: &'a Options,
+    definitions: HashMap<String, String>,
+    footnotes: Vec<String>,
+    chunks: Vec<String>,
+}
+
+pub fn export(document: &OrgDocument, options: &Options) -> String {
+    let mut exporter = Exporter {
+        options,
+        definitions: HashMap::new(),
+        footnotes: Vec::new(),
+        chunks: Vec::new(),
+    };
+    exporter.collect_definitions(&document.section);
+    for headline in &document.headlines {
+        exporter.collect_headline_definitions(headline);
+    }
+    if let Some(title) = document.title() {
+        let title = exporter.render_inline(&parse_inline(title));
+        exporter.push_heading(&title, 1);
+    }
+    exporter.render_section(&document.section);
+    for headline in &document.headlines {
+        exporter.render_headline(headline);
+    }
+    exporter.render_footnotes();
+    let mut output = exporter.chunks.join("\n\n");
+    output.push('\n');
+    output
+}
+
+impl Exporter<'_> {
+    fn collect_definitions(&mut self, section: &[Element]) {
+        for element in section {
+            if let ElementKind::FootnoteDefinition(definition) = &element.kind {
+                self.definitions.insert(
+                    definition.label.clone(),
+                    SourceText::join(&definition.lines),
+                );
+            }
+        }
+    }
+    fn collect_headline_definitions(&mut self, headline: &Headline) {
+        self.collect_definitions(&headline.section);
+        for child in &headline.children {
+            self.collect_headline_definitions(child);
+        }
+    }
+    fn underline(&self, level: usize) -> &'static str {
+        match (self.options.charset, level) {
+            (Charset::Ascii, 1) => "=",
+            (Charset::Ascii, 2) => "-",
+            (Charset::Ascii, 3) => "~",
+            (Charset::Ascii, _) => ".",
+            (Charset::Utf8, 1) => "═",
+            (Charset::Utf8, 2) => "─",
+            (Charset::Utf8, 3) => "┄",
+            (Charset::Utf8, _) => "·",
+        }
+    }
+    fn push_heading(&mut self, text: &str, level: usize) {
+        let underline = self.underline(level).repeat(width_of(text));
+        self.chunks.push(format!("{}\n{}", text, underline));
+    }
+    fn render_headline(&mut self, headline: &Headline) {
+        let mut text = String::new();
+        if let Some(keyword) = &headline.keyword {
+            text.push_str(keyword);
+            text.push(' ');
+        }
+        if let Some(priority) = headline.priority {
+            text.push_str(&format!("[#{}] ", priority));
+        }
+        text.push_str(&self.render_inline(&parse_inline(&headline.title)));
+        if !headline.tags.is_empty() {
+            text.push_str(&format!("  :{}:", headline.tags.join(":")));
+        }
+        self.push_heading(text.trim_end(), headline.level);
+        self.render_section(&headline.section);
+        for child in &headline.children {
+            self.render_headline(child);
+        }
+    }
+    fn render_section(&mut self, section: &[Element]) {
+        for element in section {
+            let lines = match &element.kind {
+                ElementKind::Paragraph(paragraph) => {
+                    let text =
+                        self.render_inline(&parse_inline(&SourceText::join(&paragraph.lines)));
+                    wrap(&text, self.options.width, "", "")
+                }
+                ElementKind::List(list) => self.render_list(list),
+                ElementKind::Table(table) => self.render_table(table),
+                ElementKind::Block(block) => self.render_block(block),
+                ElementKind::HorizontalRule => vec!["-".repeat(self.options.width)],
+                ElementKind::Drawer(_)
+                | ElementKind::Keyword(_)
+                | ElementKind::Comment(_)
+                | ElementKind::FootnoteDefinition(_) => continue,
+            };
+            if !lines.is_empty() {
+                self.chunks.push(lines.join("\n"));
+            }
+        }
+    }
+    fn render_list(&mut self, list: &List) -> Vec<String> {
+        let mut lines = Vec::new();
+        for item in &list.items {
+            self.render_list_item(item, "", &mut lines);
+        }
+        lines
+    }
+    fn render_list_item(&mut self, item: &ListItem, indent: &str, lines: &mut Vec<String>) {
+        let mut prefix = format!("{}{} ", indent, item.bullet);
+        if let Some(checkbox) = item.checkbox {
+            prefix.push_str(&format!("[{}] ", checkbox));
+        }
+        let hanging = " ".repeat(width_of(&prefix));
+        let text = self.render_inline(&parse_inline(&SourceText::join(&item.lines)));
+        lines.extend(wrap(&text, self.options.width, &prefix, &hanging));
+        let child_indent = " ".repeat(
+            width_of(indent)
+                .saturating_add(width_of(&item.bullet))
+                .saturating_add(1),
+        );
+        for child in &item.children {
+            self.render_list_item(child, &child_indent, lines);
+        }
+    }
+    fn render_table(&mut self, table: &Table) -> Vec<String> {
+        let rows: Vec<Option<Vec<String>>> = table
+            .rows
+            .iter()
+            .map(|row| match row {
+                TableRow::Rule => None,
+                TableRow::Cells(cells) => Some(
+                    cells
+                        .iter()
+                        .map(|cell| self.render_inline(&parse_inline(&cell.text)))
+                        .collect(),
+                ),
+            })
+            .collect();
+        let columns = rows.iter().flatten().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        let mut numeric = vec![(0_usize, 0_usize); columns];
+        for cells in rows.iter().flatten() {
+            for (column, cell) in cells.iter().enumerate() {
+                if let (Some(width), Some(count)) =
+                    (widths.get_mut(column), numeric.get_mut(column))
+                {
+                    *width = (*width).max(width_of(cell));
+                    if !cell.is_empty() {
+                        count.1 = count.1.saturating_add(1);
+                        if is_number(cell) {
+                            count.0 = count.0.saturating_add(1);
+                        }
+                    }
+                }
+            }
+        }
+        let (horizontal, vertical, corners) = match self.options.charset {
+            Charset::Ascii => (
+                "-",
+                "|",
+                [["+", "+", "+"], ["+", "+", "+"], ["+", "+", "+"]],
+            ),
+            Charset::Utf8 => (
+                "─",
+                "│",
+                [["┌", "┬", "┐"], ["├", "┼", "┤"], ["└", "┴", "┘"]],
+            ),
+        };
+        let rule = |[left, middle, right]: [&str; 3]| {
+            let segments: Vec<String> = widths
+                .iter()
+                .map(|width| horizontal.repeat(width.saturating_add(2)))
+                .collect();
+            format!("{}{}{}", left, segments.join(middle), right)
+        };
+        let [top, middle, bottom] = corners;
+        let mut lines = vec![rule(top)];
+        for row in &rows {
+            match row {
+                None => lines.push(rule(middle)),
+                Some(cells) => {
+                    let mut line = vertical.to_string();
+                    for (column, width) in widths.iter().enumerate() {
+                        let cell = cells.get(column).map_or("", String::as_str);
+                        let padding = " ".repeat(width.saturating_sub(width_of(cell)));
+                        let right_align = numeric
+                            .get(column)
+                            .is_some_and(|(numbers, total)| numbers.saturating_mul(2) > *total);
+                        if right_align {
+                            line.push_str(&format!(" {}{} {}", padding, cell, vertical));
+                        } else {
+                            line.push_str(&format!(" {}{} {}", cell, padding, vertical));
+                        }
+                    }
+                    lines.push(line);
+                }
+            }
+        }
+        // A trailing rule in the source would otherwise be doubled.
+        if rows.last().is_some_and(Option::is_none) {
+            lines.pop();
+        }
+        lines.push(rule(bottom));
+        lines
+    }
+    fn render_block(&mut self, block: &Block) -> Vec<String> {
+        match block.kind.as_str() {
+            "quote" => {
+                let mut lines = Vec::new();
+                for paragraph in block.contents.split(|line| line.trim().is_empty()) {
+                    if paragraph.is_empty() {
+                        continue;
+                    }
+                    if !lines.is_empty() {
+                        lines.push(String::new());
+                    }
+                    let joined: Vec<&str> = paragraph.iter().map(|line| line.trim()).collect();
+                    let text = self.render_inline(&parse_inline(&joined.join(" ")));
+                    lines.extend(wrap(&text, self.options.width, "  ", "  "));
+                }
+                lines
+            }
+            "center" => block
+                .contents
+                .iter()
+                .map(|line| {
+                    let text = self.render_inline(&parse_inline(line.trim()));
+                    let padding = self.options.width.saturating_sub(width_of(&text)) / 2;
+                    format!("{}{}", " ".repeat(padding), text)
+                })
+                .collect(),
+            "verse" => block
+                .contents
+                .iter()
+                .map(|line| self.render_inline(&parse_inline(line)))
+                .collect(),
+            "comment" => Vec::new(),
+            _ => {
+                let indent = block
+                    .contents
+                    .iter()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| line.len().saturating_sub(line.trim_start().len()))
+                    .min()
+                    .unwrap_or(0);
+                block
+                    .contents
+                    .iter()
+                    .map(|line| {
+                        let line = line.get(indent..).unwrap_or_else(|| line.trim_start());
+                        format!("  {}", line).trim_end().to_string()
+                    })
+                    .collect()
+            }
+        }
+    }
+    fn render_footnotes(&mut self) {
+        if self.footnotes.is_empty() {
+            return;
+        }
+        self.push_heading("Footnotes", 1);
+        let mut index = 0;
+        // Definitions may reference further footnotes, growing the list.
+        while let Some(label) = self.footnotes.get(index).cloned() {
+            let number = index.saturating_add(1);
+            let text = match self.definitions.get(&label).cloned() {
+                Some(definition) => self.render_inline(&parse_inline(&definition)),
+                None => String::new(),
+            };
+            let prefix = format!("[{}] ", number);
+            let hanging = " ".repeat(width_of(&prefix));
+            self.chunks
+                .push(wrap(&text, self.options.width, &prefix, &hanging).join("\n"));
+            index = number;
+        }
+    }
+    fn footnote_number(&mut self, label: &str) -> usize {
+        let index = if let Some(index) = self.footnotes.iter().position(|l| l == label) {
+            index
+        } else {
+            self.footnotes.push(label.to_string());
+            self.footnotes.len().saturating_sub(1)
+        };
+        index.saturating_add(1)
+    }
+    fn render_inline(&mut self, objects: &[Inline]) -> String {
+        let mut result = String::new();
+        for object in objects {
+            match object {
+                Inline::Text(text) => result.push_str(text),
+                Inline::Bold(inner) => {
+                    let inner = self.render_inline(inner);
+                    result.push_str(&format!("*{}*", inner));
+                }
+                Inline::Italic(inner) => {
+                    let inner = self.render_inline(inner);
+                    result.push_str(&format!("/{}/", inner));
+                }
+                Inline::Underline(inner) => {
+                    let inner = self.render_inline(inner);
+                    result.push_str(&format!("_{}_", inner));
+                }
+                Inline::StrikeThrough(inner) => {
+                    let inner = self.render_inline(inner);
+                    result.push_str(&format!("+{}+", inner));
+                }
+                Inline::Verbatim(text) | Inline::Code(text) => {
+                    result.push_str(&format!("`{}`", text));
+                }
+                Inline::Link {
+                    target,
+                    description,
+                } => result.push_str(&render_link(target, description.as_deref())),
+                Inline::FootnoteReference(label) => {
+                    let number = self.footnote_number(label);
+                    result.push_str(&format!("[{}]", number));
+                }
+                Inline::Timestamp(timestamp) => result.push_str(&timestamp.raw),
+            }
+        }
+        result
+    }
+}
+
+fn render_link(target: &str, description: Option<&str>) -> String {
+    // Internal links (headlines, custom ids, targets) have nothing useful
+    // to show outside of the document besides their description.
+    let internal = target.starts_with('*') || target.starts_with('#') || !target.contains(':');
+    match description {
+        Some(description) if internal || description == target => description.to_string(),
+        Some(description) => format!("{} ({})", description, target),
+        None if internal => target.trim_start_matches(['*', '#']).to_string(),
+        None => target.to_string(),
+    }
+}
+
+// Columns on a terminal, where CJK characters take two.
+fn width_of(text: &str) -> usize {
+    text.width()
+}
+
+fn is_number(text: &str) -> bool {
+    let text = text.trim_start_matches(['-', '+']);
+    !text.is_empty()
+        && text.chars().any(|c| c.is_ascii_digit())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == ',' || c == '%')
+}
+
+// Greedily fills lines up to `width`, starting the first line with `first`
+// and every following one with `rest`. Words longer than a line are kept
+// whole.
+pub fn wrap(text: &str, width: usize, first: &str, rest: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = first.to_string();
+    let mut line_width = width_of(first);
+    let mut empty = true;
+    for word in text.split_whitespace() {
+        let word_width = width_of(word);
+        if !empty && line_width.saturating_add(1).saturating_add(word_width) > width {
+            lines.push(line);
+            line = rest.to_string();
+            line_width = width_of(rest);
+            empty = true;
+        }
+        if !empty {
+            line.push(' ');
+            line_width = line_width.saturating_add(1);
+        }
+        line.push_str(word);
+        line_width = line_width.saturating_add(word_width);
+        empty = false;
+    }
+    lines.push(line.trim_end().to_string());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export_with_width(text: &str, width: usize) -> String {
+        let options = Options {
+            width,
+            ..Options::default()
+        };
+        export(&OrgDocument::parse(text), &options)
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap("one two three four", 9, "- ", "  "),
+            vec!["- one two", "  three", "  four"]
+        );
+    }
+
+    #[test]
+    fn test_export_headlines_and_paragraphs() {
+        let text = "#+TITLE: Plan\n* TODO Shopping :errand:\nWe need to buy quite a lot of things today.\n** Groceries";
+        assert_eq!(
+            export_with_width(text, 20),
+            "Plan\n====\n\nTODO Shopping  :errand:\n=======================\n\nWe need to buy quite\na lot of things\ntoday.\n\nGroceries\n---------\n"
+        );
+    }
+
+    #[test]
+    fn test_export_list_with_checkboxes() {
+        let text = "- [X] milk and some other things\n- [ ] eggs\n  - brown";
+        assert_eq!(
+            export_with_width(text, 16),
+            "- [X] milk and\n      some other\n      things\n- [ ] eggs\n  - brown\n"
+        );
+    }
+
+    #[test]
+    fn test_export_table() {
+        let text = "| Item | Qty |\n|------+-----|\n| Milk | 2 |\n| Eggs | 12 |";
+        assert_eq!(
+            export_with_width(text, 72),
+            "+------+-----+\n| Item | Qty |\n+------+-----+\n| Milk |   2 |\n| Eggs |  12 |\n+------+-----+\n"
+        );
+    }
+
+    #[test]
+    fn test_export_wide_characters() {
+        let text = "* 買い物\n| 品目 | 数 |\n|------+----|\n| 苹果 | 3 |\n| Milk | 12 |";
+        assert_eq!(
+            export_with_width(text, 72),
+            "買い物\n======\n\n+------+----+\n| 品目 | 数 |\n+------+----+\n| 苹果 |  3 |\n| Milk | 12 |\n+------+----+\n"
+        );
+    }
+
+    #[test]
+    fn test_export_footnotes_and_links() {
+        let text = "See [[https://orgmode.org][Org]] for more.[fn:org]\n\n[fn:org] The manual.";
+        assert_eq!(
+            export_with_width(text, 72),
+            "See Org (https://orgmode.org) for more.[1]\n\nFootnotes\n=========\n\n[1] The manual.\n"
+        );
+    }
+}
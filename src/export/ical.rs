@@ -0,0 +1,422 @@
+use crate::org::{ElementKind, Headline, Inline, OrgDocument, TodoKeywords, parse_inline};
+use crate::timestamp::{self, Date, Time, Timestamp, Unit};
+use std::collections::HashMap;
+
+// Every component needs a DTSTAMP; using the wall clock would make two
+// exports of the same files differ, so components without a date of their
+// own fall back to this one.
+const FALLBACK_DTSTAMP: &str = "19700101T000000Z";
+
+pub struct Calendar<'a> {
+    pub file_name: &'a str,
+    pub document: &'a OrgDocument,
+}
+
+struct Exporter {
+    lines: Vec<String>,
+    uids: HashMap<String, usize>,
+}
+
+pub fn export(calendars: &[Calendar<'_>]) -> String {
+    let name = match calendars {
+        [calendar] => calendar.document.title().unwrap_or(calendar.file_name),
+        _ => "Orgonaut",
+    };
+    let mut exporter = Exporter {
+        lines: vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            "PRODID:-//Orgonaut//Org export//EN".to_string(),
+            "CALSCALE:GREGORIAN".to_string(),
+            format!("X-WR-CALNAME:{}", escape(name)),
+        ],
+        uids: HashMap::new(),
+    };
+    for calendar in calendars {
+        let base_name = calendar
+            .file_name
+            .rsplit('/')
+            .next()
+            .unwrap_or(calendar.file_name);
+        for headline in &calendar.document.headlines {
+            exporter.export_headline(headline, &calendar.document.todo_keywords, base_name);
+        }
+    }
+    exporter.lines.push("END:VCALENDAR".to_string());
+    let mut output = String::new();
+    for line in &exporter.lines {
+        output.push_str(&fold(line));
+        output.push_str("\r\n");
+    }
+    output
+}
+
+impl Exporter {
+    fn export_headline(&mut self, headline: &Headline, todo_keywords: &TodoKeywords, path: &str) {
+        let path = format!("{}\u{0}{}", path, headline.title);
+        let uid = self.uid(headline, &path);
+        let summary = plain_text(&parse_inline(&headline.title));
+        let categories = headline
+            .tags
+            .iter()
+            .map(|tag| escape(tag))
+            .collect::<Vec<String>>()
+            .join(",");
+        let planning = &headline.planning;
+        if let Some(deadline) = &planning.deadline {
+            self.push_event(
+                &format!("DL-{}", uid),
+                &format!("DL: {}", summary),
+                deadline,
+                &categories,
+            );
+        }
+        if let Some(scheduled) = &planning.scheduled {
+            self.push_event(
+                &format!("SC-{}", uid),
+                &format!("S: {}", summary),
+                scheduled,
+                &categories,
+            );
+        }
+        for (index, timestamp) in active_timestamps(headline).iter().enumerate() {
+            let prefix = if index == 0 {
+                "TS".to_string()
+            } else {
+                format!("TS{}", index.saturating_add(1))
+            };
+            self.push_event(
+                &format!("{}-{}", prefix, uid),
+                &summary,
+                timestamp,
+                &categories,
+            );
+        }
+        if let Some(keyword) = &headline.keyword {
+            self.push_todo(
+                headline,
+                todo_keywords,
+                keyword,
+                &uid,
+                &summary,
+                &categories,
+            );
+        }
+        for child in &headline.children {
+            self.export_headline(child, todo_keywords, &path);
+        }
+    }
+    // Prefers the headline's ID property; otherwise hashes the file name and
+    // outline path so the UID survives edits elsewhere in the file.
+    fn uid(&mut self, headline: &Headline, path: &str) -> String {
+        let base = match headline.property("ID") {
+            Some(id) => id.to_string(),
+            None => format!("{:016x}", fnv1a(path.as_bytes())),
+        };
+        let count = self.uids.entry(base.clone()).or_insert(0);
+        *count = count.saturating_add(1);
+        if *count == 1 {
+            base
+        } else {
+            format!("{}-{}", base, count)
+        }
+    }
+    fn push_event(&mut self, uid: &str, summary: &str, timestamp: &Timestamp, categories: &str) {
+        self.lines.push("BEGIN:VEVENT".to_string());
+        self.lines.push(format!("UID:{}", escape(uid)));
+        self.lines.push(format!("DTSTAMP:{}", dtstamp(timestamp)));
+        self.lines
+            .push(date_property("DTSTART", timestamp.date, timestamp.start));
+        let end_date = timestamp.end_date.unwrap_or(timestamp.date);
+        match (timestamp.start, timestamp.end) {
+            (None, _) => self
+                .lines
+                .push(date_property("DTEND", end_date.succ(), None)),
+            (Some(_), Some(end)) => self.lines.push(date_property("DTEND", end_date, Some(end))),
+            (Some(start), None) if timestamp.end_date.is_some() => {
+                self.lines
+                    .push(date_property("DTEND", end_date, Some(start)));
+            }
+            (Some(_), None) => (),
+        }
+        if let Some(rrule) = rrule(timestamp) {
+            self.lines.push(rrule);
+        }
+        self.lines.push(format!("SUMMARY:{}", escape(summary)));
+        if !categories.is_empty() {
+            self.lines.push(format!("CATEGORIES:{}", categories));
+        }
+        self.lines.push("END:VEVENT".to_string());
+    }
+    fn push_todo(
+        &mut self,
+        headline: &Headline,
+        todo_keywords: &TodoKeywords,
+        keyword: &str,
+        uid: &str,
+        summary: &str,
+        categories: &str,
+    ) {
+        let planning = &headline.planning;
+        let stamp = planning
+            .closed
+            .as_ref()
+            .or(planning.scheduled.as_ref())
+            .or(planning.deadline.as_ref())
+            .map_or_else(|| FALLBACK_DTSTAMP.to_string(), dtstamp);
+        self.lines.push("BEGIN:VTODO".to_string());
+        self.lines.push(format!("UID:TODO-{}", escape(uid)));
+        self.lines.push(format!("DTSTAMP:{}", stamp));
+        if let Some(scheduled) = &planning.scheduled {
+            self.lines
+                .push(date_property("DTSTART", scheduled.date, scheduled.start));
+            if let Some(rrule) = rrule(scheduled) {
+                self.lines.push(rrule);
+            }
+        }
+        if let Some(deadline) = &planning.deadline {
+            self.lines
+                .push(date_property("DUE", deadline.date, deadline.start));
+        }
+        self.lines.push(format!("SUMMARY:{}", escape(summary)));
+        if !categories.is_empty() {
+            self.lines.push(format!("CATEGORIES:{}", categories));
+        }
+        let status = if !todo_keywords.is_done(keyword) {
+            "NEEDS-ACTION"
+        } else if keyword.to_uppercase().starts_with("CANCEL") {
+            "CANCELLED"
+        } else {
+            "COMPLETED"
+        };
+        self.lines.push(format!("STATUS:{}", status));
+        if status == "COMPLETED" {
+            if let Some(closed) = &planning.closed {
+                self.lines.push(format!("COMPLETED:{}", dtstamp(closed)));
+            }
+        }
+        if let Some(priority) = headline.priority {
+            let priority = match priority {
+                'A' => 1,
+                'B' => 5,
+                _ => 9,
+            };
+            self.lines.push(format!("PRIORITY:{}", priority));
+        }
+        self.lines.push("END:VTODO".to_string());
+    }
+}
+
+// Active timestamps in the headline itself and in its own section, but not
+// in those of its children.
+fn active_timestamps(headline: &Headline) -> Vec<Timestamp> {
+    let mut texts = vec![headline.title.clone()];
+    for element in &headline.section {
+        match &element.kind {
+            ElementKind::Paragraph(paragraph) => {
+                texts.extend(paragraph.lines.iter().map(|line| line.text.clone()));
+            }
+            ElementKind::List(list) => {
+                for item in &list.items {
+                    texts.extend(item.lines.iter().map(|line| line.text.clone()));
+                }
+            }
+            _ => (),
+        }
+    }
+    let mut timestamps = Vec::new();
+    for text in texts {
+        collect_timestamps(&parse_inline(&text), &mut timestamps);
+    }
+    timestamps
+}
+
+fn collect_timestamps(objects: &[Inline], timestamps: &mut Vec<Timestamp>) {
+    for object in objects {
+        match object {
+            Inline::Timestamp(timestamp) if timestamp.active => timestamps.push(timestamp.clone()),
+            Inline::Bold(inner)
+            | Inline::Italic(inner)
+            | Inline::Underline(inner)
+            | Inline::StrikeThrough(inner) => collect_timestamps(inner, timestamps),
+            _ => (),
+        }
+    }
+}
+
+fn plain_text(objects: &[Inline]) -> String {
+    let mut result = String::new();
+    for object in objects {
+        match object {
+            Inline::Text(text) | Inline::Verbatim(text) | Inline::Code(text) => {
+                result.push_str(text);
+            }
+            Inline::Bold(inner)
+            | Inline::Italic(inner)
+            | Inline::Underline(inner)
+            | Inline::StrikeThrough(inner) => result.push_str(&plain_text(inner)),
+            Inline::Link {
+                target,
+                description,
+            } => result.push_str(description.as_deref().unwrap_or(target)),
+            Inline::FootnoteReference(_) | Inline::Timestamp(_) => (),
+        }
+    }
+    result.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn format_date(date: Date) -> String {
+    format!("{:04}{:02}{:02}", date.year, date.month, date.day)
+}
+
+fn format_time(time: Time) -> String {
+    format!("{:02}{:02}00", time.hour, time.minute)
+}
+
+fn date_property(name: &str, date: Date, time: Option<Time>) -> String {
+    match time {
+        Some(time) => format!("{}:{}T{}", name, format_date(date), format_time(time)),
+        None => format!("{};VALUE=DATE:{}", name, format_date(date)),
+    }
+}
+
+// DTSTAMP and COMPLETED are in UTC, while Org timestamps are local times.
+fn dtstamp(timestamp: &Timestamp) -> String {
+    let time = timestamp.start.unwrap_or(Time { hour: 0, minute: 0 });
+    match timestamp::local_to_utc(timestamp.date, time) {
+        Some((date, time)) => format!("{}T{}Z", format_date(date), format_time(time)),
+        None => FALLBACK_DTSTAMP.to_string(),
+    }
+}
+
+fn rrule(timestamp: &Timestamp) -> Option<String> {
+    let repeater = timestamp.repeater?;
+    if repeater.value == 0 {
+        return None;
+    }
+    let frequency = match repeater.unit {
+        Unit::Hour => "HOURLY",
+        Unit::Day => "DAILY",
+        Unit::Week => "WEEKLY",
+        Unit::Month => "MONTHLY",
+        Unit::Year => "YEARLY",
+    };
+    Some(format!(
+        "RRULE:FREQ={};INTERVAL={}",
+        frequency, repeater.value
+    ))
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\n' => result.push_str("\\n"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+// Content lines longer than 75 octets are folded onto continuation lines
+// starting with a space (RFC 5545, section 3.1).
+fn fold(line: &str) -> String {
+    let mut result = String::new();
+    let mut length: usize = 0;
+    for c in line.chars() {
+        let width = c.len_utf8();
+        if length.saturating_add(width) > 75 {
+            result.push_str("\r\n ");
+            length = 1;
+        }
+        result.push(c);
+        length = length.saturating_add(width);
+    }
+    result
+}
+
+// 64-bit FNV-1a: stable across platforms and compiler versions, unlike the
+// standard library's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export_text(text: &str) -> String {
+        let document = OrgDocument::parse(text);
+        export(&[Calendar {
+            file_name: "tasks.org",
+            document: &document,
+        }])
+    }
+
+    #[test]
+    fn test_export_scheduled_todo() {
+        let text = "* TODO [#A] Water plants :home:\nSCHEDULED: <2024-05-01 Wed 09:00 +1w>\n:PROPERTIES:\n:ID: plants\n:END:";
+        let output = export_text(text);
+        let lines: Vec<&str> = output.split("\r\n").collect();
+        // 09:00 local time, in UTC.
+        let (date, time) = timestamp::local_to_utc(
+            Date {
+                year: 2024,
+                month: 5,
+                day: 1,
+            },
+            Time { hour: 9, minute: 0 },
+        )
+        .unwrap();
+        let stamp = format!("DTSTAMP:{}T{}Z", format_date(date), format_time(time));
+        let event = [
+            "BEGIN:VEVENT",
+            "UID:SC-plants",
+            &stamp,
+            "DTSTART:20240501T090000",
+            "RRULE:FREQ=WEEKLY;INTERVAL=1",
+            "SUMMARY:S: Water plants",
+            "CATEGORIES:home",
+            "END:VEVENT",
+            "BEGIN:VTODO",
+            "UID:TODO-plants",
+            &stamp,
+            "DTSTART:20240501T090000",
+            "RRULE:FREQ=WEEKLY;INTERVAL=1",
+            "SUMMARY:Water plants",
+            "CATEGORIES:home",
+            "STATUS:NEEDS-ACTION",
+            "PRIORITY:1",
+            "END:VTODO",
+        ];
+        assert_eq!(lines.get(5..5 + event.len()), Some(&event[..]));
+    }
+
+    #[test]
+    fn test_export_is_deterministic() {
+        let text = "* Meeting <2024-05-02 Thu>\n** DONE Report\nCLOSED: [2024-05-03 Fri 17:00] DEADLINE: <2024-05-03 Fri>";
+        let output = export_text(text);
+        assert_eq!(output, export_text(text));
+        assert!(output.contains("DTSTART;VALUE=DATE:20240502\r\nDTEND;VALUE=DATE:20240503\r\n"));
+        let closed = Timestamp::parse("[2024-05-03 Fri 17:00]").unwrap().0;
+        let completed = format!("STATUS:COMPLETED\r\nCOMPLETED:{}\r\n", dtstamp(&closed));
+        assert!(output.contains(&completed));
+        assert!(output.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn test_fold_and_escape() {
+        assert_eq!(escape("a, b; c\\d"), "a\\, b\\; c\\\\d");
+        let folded = fold(&"x".repeat(80));
+        assert_eq!(folded, format!("{}\r\n {}", "x".repeat(75), "x".repeat(5)));
+    }
+}
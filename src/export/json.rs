@@ -0,0 +1,317 @@
+use crate::org::{
+    Element, ElementKind, Headline, Inline, ListItem, OrgDocument, SourceText, TableRow,
+    TodoKeywords, parse_inline,
+};
+use crate::timestamp::Timestamp;
+use serde_json::{Map, Value, json};
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Bumped whenever the shape of the output changes incompatibly, so scripts
+// can refuse documents they do not understand.
+const SCHEMA_VERSION: u32 = 1;
+
+// Ranges use zero-based lines and grapheme columns, with exclusive ends.
+// Elements always span whole lines.
+pub fn export(document: &OrgDocument) -> String {
+    let mut output = serde_json::to_string_pretty(&document_value(document)).unwrap_or_default();
+    output.push('\n');
+    output
+}
+
+fn document_value(document: &OrgDocument) -> Value {
+    let keywords: Vec<Value> = document
+        .keywords
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": value }))
+        .collect();
+    let headlines: Vec<Value> = document
+        .headlines
+        .iter()
+        .map(|headline| headline_value(headline, &document.todo_keywords))
+        .collect();
+    json!({
+        "type": "document",
+        "version": SCHEMA_VERSION,
+        "keywords": keywords,
+        "todo_keywords": {
+            "active": document.todo_keywords.active,
+            "done": document.todo_keywords.done,
+        },
+        "section": elements_value(&document.section),
+        "headlines": headlines,
+    })
+}
+
+fn headline_value(headline: &Headline, todo_keywords: &TodoKeywords) -> Value {
+    let title = SourceText {
+        line: headline.range.start,
+        column: headline.title_column,
+        text: headline.title.clone(),
+    };
+    let properties: Map<String, Value> = headline
+        .properties
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect();
+    let children: Vec<Value> = headline
+        .children
+        .iter()
+        .map(|child| headline_value(child, todo_keywords))
+        .collect();
+    let planning = &headline.planning;
+    json!({
+        "type": "headline",
+        "range": line_range(&headline.range),
+        "level": headline.level,
+        "keyword": headline.keyword,
+        "done": headline.keyword.as_deref().is_some_and(|keyword| todo_keywords.is_done(keyword)),
+        "priority": headline.priority.map(String::from),
+        "title": inline_value(&[title]),
+        "raw_title": headline.title,
+        "tags": headline.tags,
+        "planning": {
+            "scheduled": planning.scheduled.as_ref().map(timestamp_value),
+            "deadline": planning.deadline.as_ref().map(timestamp_value),
+            "closed": planning.closed.as_ref().map(timestamp_value),
+        },
+        "properties": properties,
+        "section": elements_value(&headline.section),
+        "children": children,
+    })
+}
+
+fn elements_value(elements: &[Element]) -> Vec<Value> {
+    elements.iter().map(element_value).collect()
+}
+
+fn element_value(element: &Element) -> Value {
+    let mut value = match &element.kind {
+        ElementKind::Paragraph(paragraph) => json!({
+            "type": "paragraph",
+            "contents": inline_value(&paragraph.lines),
+        }),
+        ElementKind::List(list) => json!({
+            "type": "list",
+            "items": list.items.iter().map(item_value).collect::<Vec<Value>>(),
+        }),
+        ElementKind::Table(table) => {
+            let rows: Vec<Value> = table
+                .rows
+                .iter()
+                .map(|row| match row {
+                    TableRow::Rule => json!({ "type": "rule" }),
+                    TableRow::Cells(cells) => {
+                        let cells: Vec<Value> = cells
+                            .iter()
+                            .map(|cell| {
+                                let end = cell.column.saturating_add(length_of(&cell.text));
+                                json!({
+                                    "type": "cell",
+                                    "range": range(cell.line, cell.column, cell.line, end),
+                                    "contents": inline_value(std::slice::from_ref(cell)),
+                                })
+                            })
+                            .collect();
+                        json!({ "type": "row", "cells": cells })
+                    }
+                })
+                .collect();
+            json!({ "type": "table", "rows": rows })
+        }
+        ElementKind::Block(block) => json!({
+            "type": "block",
+            "name": block.kind,
+            "parameters": block.parameters,
+            "value": block.contents.join("\n"),
+        }),
+        ElementKind::Drawer(drawer) => json!({
+            "type": "drawer",
+            "name": drawer.name,
+            "value": drawer.contents.join("\n"),
+        }),
+        ElementKind::Keyword(keyword) => json!({
+            "type": "keyword",
+            "key": keyword.key,
+            "value": keyword.value,
+        }),
+        ElementKind::FootnoteDefinition(definition) => json!({
+            "type": "footnote-definition",
+            "label": definition.label,
+            "contents": inline_value(&definition.lines),
+        }),
+        ElementKind::Comment(lines) => json!({
+            "type": "comment",
+            "value": lines.join("\n"),
+        }),
+        ElementKind::HorizontalRule => json!({ "type": "horizontal-rule" }),
+    };
+    if let Some(object) = value.as_object_mut() {
+        object.insert("range".to_string(), line_range(&element.range));
+    }
+    value
+}
+
+fn item_value(item: &ListItem) -> Value {
+    json!({
+        "type": "item",
+        "range": line_range(&item.range),
+        "bullet": item.bullet,
+        "checkbox": item.checkbox.map(String::from),
+        "contents": inline_value(&item.lines),
+        "children": item.children.iter().map(item_value).collect::<Vec<Value>>(),
+    })
+}
+
+fn timestamp_value(timestamp: &Timestamp) -> Value {
+    json!({
+        "type": "timestamp",
+        "active": timestamp.active,
+        "date": timestamp.date.to_string(),
+        "start": timestamp.start.map(|time| time.to_string()),
+        "end_date": timestamp.end_date.map(|date| date.to_string()),
+        "end": timestamp.end.map(|time| time.to_string()),
+        "repeater": timestamp.repeater.map(|repeater| repeater.to_string()),
+        "raw": timestamp.raw,
+    })
+}
+
+// Inline objects of all `lines`, separated by line breaks.
+fn inline_value(lines: &[SourceText]) -> Vec<Value> {
+    let mut values = Vec::new();
+    let mut previous: Option<(usize, usize)> = None;
+    for line in lines {
+        if let Some((end_line, end_column)) = previous {
+            values.push(json!({
+                "type": "line-break",
+                "range": range(end_line, end_column, line.line, line.column),
+            }));
+        }
+        let (objects, length) = objects_value(&parse_inline(&line.text), line.line, line.column);
+        values.extend(objects);
+        previous = Some((line.line, line.column.saturating_add(length)));
+    }
+    values
+}
+
+// Converts `objects` starting at `column`, returning them together with the
+// number of graphemes they span in the source.
+fn objects_value(objects: &[Inline], line: usize, column: usize) -> (Vec<Value>, usize) {
+    let mut values = Vec::new();
+    let mut offset = column;
+    for object in objects {
+        let (mut value, length) = object_value(object, line, offset);
+        if let Some(object) = value.as_object_mut() {
+            let end = offset.saturating_add(length);
+            object.insert("range".to_string(), range(line, offset, line, end));
+        }
+        values.push(value);
+        offset = offset.saturating_add(length);
+    }
+    (values, offset.saturating_sub(column))
+}
+
+fn object_value(object: &Inline, line: usize, column: usize) -> (Value, usize) {
+    let nested = |name: &str, inner: &[Inline]| {
+        let (contents, length) = objects_value(inner, line, column.saturating_add(1));
+        (
+            json!({ "type": name, "contents": contents }),
+            length.saturating_add(2),
+        )
+    };
+    match object {
+        Inline::Text(text) => (json!({ "type": "text", "value": text }), length_of(text)),
+        Inline::Bold(inner) => nested("bold", inner),
+        Inline::Italic(inner) => nested("italic", inner),
+        Inline::Underline(inner) => nested("underline", inner),
+        Inline::StrikeThrough(inner) => nested("strike-through", inner),
+        Inline::Verbatim(text) => (
+            json!({ "type": "verbatim", "value": text }),
+            length_of(text).saturating_add(2),
+        ),
+        Inline::Code(text) => (
+            json!({ "type": "code", "value": text }),
+            length_of(text).saturating_add(2),
+        ),
+        Inline::Link {
+            target,
+            description,
+        } => {
+            let description_length = description
+                .as_deref()
+                .map_or(0, |description| length_of(description).saturating_add(2));
+            (
+                json!({ "type": "link", "target": target, "description": description }),
+                length_of(target)
+                    .saturating_add(description_length)
+                    .saturating_add(4),
+            )
+        }
+        Inline::FootnoteReference(label) => (
+            json!({ "type": "footnote-reference", "label": label }),
+            length_of(label).saturating_add(5),
+        ),
+        Inline::Timestamp(timestamp) => (timestamp_value(timestamp), length_of(&timestamp.raw)),
+    }
+}
+
+fn length_of(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+fn range(start_line: usize, start_column: usize, end_line: usize, end_column: usize) -> Value {
+    json!({
+        "start": { "line": start_line, "column": start_column },
+        "end": { "line": end_line, "column": end_column },
+    })
+}
+
+fn line_range(lines: &Range<usize>) -> Value {
+    range(lines.start, 0, lines.end, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headline_value() {
+        let document = OrgDocument::parse("* DONE [#B] Ship *it* :work:\nCLOSED: [2024-05-03 Fri]");
+        let value = document_value(&document);
+        let headline = &value["headlines"][0];
+        assert_eq!(headline["keyword"], "DONE");
+        assert_eq!(headline["done"], true);
+        assert_eq!(headline["priority"], "B");
+        assert_eq!(headline["tags"], json!(["work"]));
+        assert_eq!(headline["planning"]["closed"]["date"], "2024-05-03");
+        assert_eq!(headline["range"], range(0, 0, 2, 0));
+        assert_eq!(headline["title"][1]["type"], "bold");
+        assert_eq!(headline["title"][1]["range"], range(0, 17, 0, 21));
+        assert_eq!(
+            headline["title"][1]["contents"][0]["range"],
+            range(0, 18, 0, 20)
+        );
+    }
+
+    #[test]
+    fn test_inline_ranges_across_lines() {
+        let document = OrgDocument::parse("  See [[https://orgmode.org][Org]]\n  and =this=.");
+        let value = document_value(&document);
+        let contents = &value["section"][0]["contents"];
+        assert_eq!(contents[1]["type"], "link");
+        assert_eq!(contents[1]["range"], range(0, 6, 0, 34));
+        assert_eq!(contents[2]["type"], "line-break");
+        assert_eq!(contents[2]["range"], range(0, 34, 1, 2));
+        assert_eq!(contents[4]["type"], "verbatim");
+        assert_eq!(contents[4]["range"], range(1, 6, 1, 12));
+    }
+
+    #[test]
+    fn test_table_cells() {
+        let document = OrgDocument::parse("| a | *b* |");
+        let value = document_value(&document);
+        let cells = &value["section"][0]["rows"][0]["cells"];
+        assert_eq!(cells[1]["range"], range(0, 6, 0, 9));
+        assert_eq!(cells[1]["contents"][0]["type"], "bold");
+    }
+}
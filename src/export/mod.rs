@@ -0,0 +1,79 @@
+pub mod ascii;
+pub mod ical;
+pub mod json;
+
+use crate::org::OrgDocument;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Format {
+    Ascii,
+    Utf8,
+    Icalendar,
+    Json,
+}
+
+pub struct Settings {
+    pub width: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { width: 72 }
+    }
+}
+
+impl Format {
+    pub fn from(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "ascii" | "txt" | "text" => Some(Self::Ascii),
+            "utf8" | "utf-8" => Some(Self::Utf8),
+            "ics" | "ical" | "icalendar" => Some(Self::Icalendar),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Ascii | Self::Utf8 => "txt",
+            Self::Icalendar => "ics",
+            Self::Json => "json",
+        }
+    }
+}
+
+pub fn export(
+    file_name: &str,
+    document: &OrgDocument,
+    format: Format,
+    settings: &Settings,
+) -> String {
+    match format {
+        Format::Ascii | Format::Utf8 => ascii::export(
+            document,
+            &ascii::Options {
+                width: settings.width,
+                charset: if format == Format::Utf8 {
+                    ascii::Charset::Utf8
+                } else {
+                    ascii::Charset::Ascii
+                },
+            },
+        ),
+        Format::Icalendar => ical::export(&[ical::Calendar {
+            file_name,
+            document,
+        }]),
+        Format::Json => json::export(document),
+    }
+}
+
+// Replaces the extension of `file_name` with the one used by `format`.
+pub fn output_file_name(file_name: &str, format: Format) -> String {
+    let stem = match file_name.rfind('.') {
+        Some(index) if index > file_name.rfind('/').map_or(0, |i| i.saturating_add(1)) => {
+            file_name.get(..index).unwrap_or(file_name)
+        }
+        _ => file_name,
+    };
+    format!("{}.{}", stem, format.extension())
+}
@@ -1,4 +1,14 @@
-use termion::color;
+use crate::screen::Rgb;
+
+// What a row leaves open for the next one. Rows only need to be highlighted
+// again when the state they start in changes.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct State {
+    // Language of the source block the row is in.
+    pub block: Option<String>,
+    pub in_comment: bool,
+}
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Type {
     None,
@@ -13,16 +23,16 @@ pub enum Type {
 }
 
 impl Type {
-    pub fn to_color(self) -> impl color::Color {
+    pub fn to_color(self) -> Rgb {
         match self {
-            Type::Number => color::Rgb(220, 163, 163),
-            Type::Match => color::Rgb(38, 139, 210),
-            Type::String => color::Rgb(211, 54, 130),
-            Type::Character => color::Rgb(108, 113, 196),
-            Type::Comment | Type::MultilineComment => color::Rgb(133, 153, 0),
-            Type::PrimaryKeywords => color::Rgb(181, 137, 0),
-            Type::SecondaryKeywords => color::Rgb(42, 161, 152),
-            _ => color::Rgb(255, 255, 255),
+            Type::Number => Rgb(220, 163, 163),
+            Type::Match => Rgb(38, 139, 210),
+            Type::String => Rgb(211, 54, 130),
+            Type::Character => Rgb(108, 113, 196),
+            Type::Comment | Type::MultilineComment => Rgb(133, 153, 0),
+            Type::PrimaryKeywords => Rgb(181, 137, 0),
+            Type::SecondaryKeywords => Rgb(42, 161, 152),
+            _ => Rgb(255, 255, 255),
         }
     }
 }
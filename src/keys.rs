@@ -0,0 +1,132 @@
+use termion::event::Key;
+
+// Keys written as text in Vim's notation, so a macro can be put into the
+// buffer, edited and yanked back: `<Esc>`, `<CR>`, `<C-f>` and `<lt>` for a
+// literal `<`.
+const NAMES: [(&str, Key); 14] = [
+    ("Esc", Key::Esc),
+    ("CR", Key::Char('\n')),
+    ("Tab", Key::Char('\t')),
+    ("S-Tab", Key::BackTab),
+    ("BS", Key::Backspace),
+    ("Del", Key::Delete),
+    ("Insert", Key::Insert),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("lt", Key::Char('<')),
+];
+
+fn name(key: &Key) -> Option<String> {
+    if let Some((name, _)) = NAMES.iter().find(|(_, named)| named == key) {
+        return Some(format!("<{}>", name));
+    }
+    match key {
+        Key::PageUp => Some("<PageUp>".to_string()),
+        Key::PageDown => Some("<PageDown>".to_string()),
+        Key::F(n) => Some(format!("<F{}>", n)),
+        Key::Ctrl(c) => Some(format!("<C-{}>", c)),
+        Key::Alt(c) => Some(format!("<M-{}>", c)),
+        _ => None,
+    }
+}
+
+// Keys with no name, like `Null`, are left out.
+pub fn to_text(keys: &[Key]) -> String {
+    keys.iter()
+        .filter_map(|key| match key {
+            Key::Char(c) if *c != '<' && *c != '\n' && *c != '\t' => Some(c.to_string()),
+            _ => name(key),
+        })
+        .collect()
+}
+
+fn named(name: &str) -> Option<Key> {
+    if let Some((_, key)) = NAMES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+    {
+        return Some(key.clone());
+    }
+    let single = |rest: &str| {
+        let mut chars = rest.chars();
+        let c = chars.next()?;
+        chars.next().is_none().then_some(c)
+    };
+    match name {
+        _ if name.eq_ignore_ascii_case("PageUp") => Some(Key::PageUp),
+        _ if name.eq_ignore_ascii_case("PageDown") => Some(Key::PageDown),
+        _ if name.starts_with("C-") || name.starts_with("c-") => {
+            single(name.get(2..)?).map(|c| Key::Ctrl(c.to_ascii_lowercase()))
+        }
+        _ if name.starts_with("M-") || name.starts_with("m-") => {
+            single(name.get(2..)?).map(Key::Alt)
+        }
+        _ if name.starts_with('F') || name.starts_with('f') => {
+            name.get(1..)?.parse().ok().map(Key::F)
+        }
+        _ => None,
+    }
+}
+
+// A `<` that does not start a known name stands for itself.
+pub fn from_text(text: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let after = rest.get(c.len_utf8()..).unwrap_or_default();
+        if c == '<' {
+            if let Some((key, tail)) = after
+                .split_once('>')
+                .and_then(|(name, tail)| named(name).map(|key| (key, tail)))
+            {
+                keys.push(key);
+                rest = tail;
+                continue;
+            }
+        }
+        keys.push(Key::Char(c));
+        rest = after;
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_notation() {
+        let keys = [
+            Key::Char('c'),
+            Key::Char('w'),
+            Key::Char('<'),
+            Key::Char('b'),
+            Key::Char('>'),
+            Key::Char('\n'),
+            Key::Esc,
+            Key::Ctrl('f'),
+            Key::Alt('y'),
+            Key::F(5),
+            Key::Up,
+        ];
+        let text = to_text(&keys);
+        assert_eq!(text, "cw<lt>b><CR><Esc><C-f><M-y><F5><Up>");
+        assert_eq!(from_text(&text), keys);
+        assert_eq!(
+            from_text("a<esc><C-V><x>"),
+            [
+                Key::Char('a'),
+                Key::Esc,
+                Key::Ctrl('v'),
+                Key::Char('<'),
+                Key::Char('x'),
+                Key::Char('>')
+            ]
+        );
+        assert_eq!(from_text("<"), [Key::Char('<')]);
+    }
+}
@@ -7,12 +7,28 @@
     clippy::wildcard_enum_match_arm,
     clippy::else_if_without_else
 )]
+mod babel;
+mod backend;
+mod cli;
+mod clipboard;
 mod document;
 mod editor;
+mod ex;
+mod export;
 mod filetype;
 mod highlighting;
+mod keys;
+mod markdown;
+mod org;
+mod register;
 mod row;
+mod screen;
+mod search;
+mod syntax;
+mod tangle;
 mod terminal;
+mod timestamp;
+mod vim;
 pub use document::Document;
 use editor::Editor;
 pub use editor::Position;
@@ -23,5 +39,9 @@ pub use row::Row;
 pub use terminal::Terminal;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
     Editor::default().run();
 }
@@ -0,0 +1,498 @@
+// Converts Markdown (CommonMark with the common GitHub extensions) into Org
+// syntax. Constructs without an Org equivalent are kept as they are.
+pub fn to_org(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut output: Vec<String> = Vec::new();
+    let mut index = 0;
+    if let Some(end) = front_matter_end(&lines) {
+        for line in lines.get(1..end).unwrap_or(&[]) {
+            if let Some((key, value)) = line.split_once(':') {
+                let key = key.trim().to_uppercase();
+                if key == "TITLE" || key == "AUTHOR" || key == "DATE" {
+                    let value = value.trim().trim_matches(['"', '\'']);
+                    output.push(format!("#+{}: {}", key, value));
+                }
+            }
+        }
+        index = end.saturating_add(1);
+    }
+    while let Some(line) = lines.get(index) {
+        let trimmed = line.trim();
+        if let Some((fence, info)) = code_fence(line) {
+            index = convert_code_block(&lines, index, fence, info, &mut output);
+            continue;
+        }
+        if let Some(heading) = atx_heading(trimmed) {
+            output.push(heading);
+        } else if let Some(level) = lines
+            .get(index.saturating_add(1))
+            .and_then(|next| setext_level(line, next))
+        {
+            output.push(format!("{} {}", "*".repeat(level), convert_inline(trimmed)));
+            index = index.saturating_add(1);
+        } else if is_thematic_break(trimmed) {
+            output.push("-----".to_string());
+        } else if trimmed.starts_with('>') {
+            index = convert_quote(&lines, index, &mut output);
+            continue;
+        } else if is_table_row(trimmed)
+            && lines
+                .get(index.saturating_add(1))
+                .is_some_and(|next| is_table_delimiter(next.trim()))
+        {
+            index = convert_table(&lines, index, &mut output);
+            continue;
+        } else if let Some(item) = list_item(line) {
+            output.push(item);
+        } else {
+            output.push(convert_inline(line));
+        }
+        index = index.saturating_add(1);
+    }
+    let mut result = output.join("\n");
+    result.push('\n');
+    result
+}
+
+fn front_matter_end(lines: &[&str]) -> Option<usize> {
+    if lines.first().map(|line| line.trim()) != Some("---") {
+        return None;
+    }
+    lines
+        .iter()
+        .skip(1)
+        .position(|line| line.trim() == "---")
+        .map(|position| position.saturating_add(1))
+}
+
+fn code_fence(line: &str) -> Option<(String, &str)> {
+    let trimmed = line.trim_start();
+    let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = trimmed.chars().take_while(|c| *c == marker).count();
+    if length < 3 {
+        return None;
+    }
+    let info = trimmed.get(length..)?.trim();
+    if marker == '`' && info.contains('`') {
+        return None;
+    }
+    Some((marker.to_string().repeat(length), info))
+}
+
+fn convert_code_block(
+    lines: &[&str],
+    start: usize,
+    fence: String,
+    info: &str,
+    output: &mut Vec<String>,
+) -> usize {
+    let language = info.split_whitespace().next().unwrap_or("");
+    let (begin, end) = if language.is_empty() {
+        ("#+BEGIN_EXAMPLE".to_string(), "#+END_EXAMPLE")
+    } else {
+        (format!("#+BEGIN_SRC {}", language), "#+END_SRC")
+    };
+    output.push(begin);
+    let mut index = start.saturating_add(1);
+    while let Some(line) = lines.get(index) {
+        index = index.saturating_add(1);
+        if line.trim().starts_with(&fence)
+            && line.trim().chars().all(|c| Some(c) == fence.chars().next())
+        {
+            break;
+        }
+        // Org requires lines that could be read as headlines or keywords
+        // to be escaped with a comma inside blocks.
+        let content = line.trim_start();
+        if content.starts_with('*')
+            || content.starts_with("#+")
+            || content.starts_with(",*")
+            || content.starts_with(",#+")
+        {
+            let indent = line.len().saturating_sub(content.len());
+            output.push(format!("{},{}", line.get(..indent).unwrap_or(""), content));
+        } else {
+            output.push((*line).to_string());
+        }
+    }
+    output.push(end.to_string());
+    index
+}
+
+fn atx_heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = line.get(level..)?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let text = rest.trim();
+    // An optional closing sequence of `#`s is not part of the title.
+    let text = match text.trim_end_matches('#') {
+        stripped if stripped.is_empty() || stripped.ends_with(' ') => stripped.trim_end(),
+        _ => text,
+    };
+    Some(
+        format!("{} {}", "*".repeat(level), convert_inline(text))
+            .trim_end()
+            .to_string(),
+    )
+}
+
+fn setext_level(line: &str, next: &str) -> Option<usize> {
+    let next = next.trim();
+    if line.trim().is_empty()
+        || line.starts_with("    ")
+        || next.is_empty()
+        || list_item(line).is_some()
+    {
+        return None;
+    }
+    if next.chars().all(|c| c == '=') {
+        Some(1)
+    } else if next.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn is_thematic_break(line: &str) -> bool {
+    let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|marker| compact.chars().all(|c| c.to_string() == *marker))
+}
+
+fn convert_quote(lines: &[&str], start: usize, output: &mut Vec<String>) -> usize {
+    let mut quoted = Vec::new();
+    let mut index = start;
+    while let Some(line) = lines.get(index) {
+        let Some(rest) = line.trim_start().strip_prefix('>') else {
+            break;
+        };
+        quoted.push(rest.strip_prefix(' ').unwrap_or(rest).to_string());
+        index = index.saturating_add(1);
+    }
+    output.push("#+BEGIN_QUOTE".to_string());
+    let inner = to_org(&quoted.join("\n"));
+    output.extend(inner.lines().map(ToString::to_string));
+    output.push("#+END_QUOTE".to_string());
+    index
+}
+
+fn is_table_row(line: &str) -> bool {
+    line.contains('|')
+}
+
+fn is_table_delimiter(line: &str) -> bool {
+    let cells = split_table_row(line);
+    !cells.is_empty()
+        && cells.iter().all(|cell| {
+            let cell = cell.trim();
+            let inner = cell.trim_start_matches(':').trim_end_matches(':');
+            !inner.is_empty() && inner.chars().all(|c| c == '-')
+        })
+}
+
+fn split_table_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                // A literal pipe cannot appear in an Org table cell.
+                chars.next();
+                cell.push('¦');
+            }
+            '|' => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+}
+
+fn convert_table(lines: &[&str], start: usize, output: &mut Vec<String>) -> usize {
+    let format_row = |line: &str| {
+        let cells: Vec<String> = split_table_row(line)
+            .iter()
+            .map(|cell| convert_inline(cell.trim()))
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let header = lines.get(start).copied().unwrap_or("");
+    output.push(format_row(header));
+    let columns = split_table_row(header).len();
+    output.push(format!("|{}|", vec!["---"; columns].join("+")));
+    let mut index = start.saturating_add(2);
+    while let Some(line) = lines.get(index) {
+        if line.trim().is_empty() || !is_table_row(line) {
+            break;
+        }
+        output.push(format_row(line));
+        index = index.saturating_add(1);
+    }
+    index
+}
+
+fn list_item(line: &str) -> Option<String> {
+    let content = line.trim_start();
+    let indent = line.get(..line.len().saturating_sub(content.len()))?;
+    let (bullet, rest) = content.split_once(' ').unwrap_or((content, ""));
+    let bullet = match bullet {
+        "*" | "-" | "+" => "-".to_string(),
+        _ => {
+            let digits = bullet.trim_end_matches(['.', ')']);
+            if digits.is_empty()
+                || digits.len().saturating_add(1) != bullet.len()
+                || !digits.chars().all(|c| c.is_ascii_digit())
+            {
+                return None;
+            }
+            bullet.to_string()
+        }
+    };
+    if rest.is_empty() && content.len() != bullet.len() {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let (checkbox, text) = match rest.get(..3) {
+        Some("[ ]") => ("[ ] ", rest.get(3..).unwrap_or("")),
+        Some("[x]" | "[X]") => ("[X] ", rest.get(3..).unwrap_or("")),
+        _ => ("", rest),
+    };
+    Some(
+        format!(
+            "{}{} {}{}",
+            indent,
+            bullet,
+            checkbox,
+            convert_inline(text.trim_start())
+        )
+        .trim_end()
+        .to_string(),
+    )
+}
+
+pub fn convert_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::new();
+    let mut index = 0;
+    while let Some(c) = chars.get(index) {
+        let converted = match c {
+            '\\' => chars.get(index.saturating_add(1)).and_then(|next| {
+                next.is_ascii_punctuation()
+                    .then(|| (next.to_string(), index.saturating_add(2)))
+            }),
+            '`' => convert_code_span(&chars, index),
+            '!' => convert_link(&chars, index.saturating_add(1))
+                .map(|(target, _, next)| (format!("[[{}]]", target), next)),
+            '[' => convert_link(&chars, index).map(|(target, description, next)| {
+                (
+                    format!("[[{}][{}]]", target, convert_inline(&description)),
+                    next,
+                )
+            }),
+            '<' => convert_autolink(&chars, index),
+            '*' | '_' | '~' => convert_emphasis(&chars, index),
+            _ => None,
+        };
+        if let Some((converted, next)) = converted {
+            result.push_str(&converted);
+            index = next;
+        } else {
+            result.push(*c);
+            index = index.saturating_add(1);
+        }
+    }
+    result
+}
+
+fn find_sequence(chars: &[char], from: usize, sequence: &[char]) -> Option<usize> {
+    (from..chars.len())
+        .find(|start| chars.get(*start..start.saturating_add(sequence.len())) == Some(sequence))
+}
+
+fn convert_code_span(chars: &[char], index: usize) -> Option<(String, usize)> {
+    let length = chars
+        .get(index..)?
+        .iter()
+        .take_while(|c| **c == '`')
+        .count();
+    let fence = vec!['`'; length];
+    let start = index.saturating_add(length);
+    let end = find_sequence(chars, start, &fence)?;
+    let code: String = chars.get(start..end)?.iter().collect();
+    let code = code.trim();
+    let marker = if code.contains('~') { '=' } else { '~' };
+    Some((
+        format!("{}{}{}", marker, code, marker),
+        end.saturating_add(length),
+    ))
+}
+
+// Parses `[description](target)` starting at the opening bracket.
+fn convert_link(chars: &[char], index: usize) -> Option<(String, String, usize)> {
+    if chars.get(index) != Some(&'[') {
+        return None;
+    }
+    let mut depth = 0_usize;
+    let mut close = None;
+    for (position, c) in chars.iter().enumerate().skip(index) {
+        match c {
+            '[' => depth = depth.saturating_add(1),
+            ']' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    close = Some(position);
+                    break;
+                }
+            }
+            _ => (),
+        }
+    }
+    let close = close?;
+    if chars.get(close.saturating_add(1)) != Some(&'(') {
+        return None;
+    }
+    let target_start = close.saturating_add(2);
+    let target_end =
+        (target_start..chars.len()).find(|position| chars.get(*position) == Some(&')'))?;
+    let target: String = chars.get(target_start..target_end)?.iter().collect();
+    // Drop an optional link title: [text](url "title").
+    let target = target
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .trim_matches(['<', '>']);
+    let description: String = chars.get(index.saturating_add(1)..close)?.iter().collect();
+    Some((
+        target.to_string(),
+        description,
+        target_end.saturating_add(1),
+    ))
+}
+
+fn convert_autolink(chars: &[char], index: usize) -> Option<(String, usize)> {
+    let end = (index..chars.len()).find(|position| chars.get(*position) == Some(&'>'))?;
+    let target: String = chars.get(index.saturating_add(1)..end)?.iter().collect();
+    let is_link = target.contains("://") || (target.contains('@') && !target.contains(' '));
+    if !is_link || target.contains(char::is_whitespace) {
+        return None;
+    }
+    let target = if target.contains("://") || target.starts_with("mailto:") {
+        target
+    } else {
+        format!("mailto:{}", target)
+    };
+    Some((format!("[[{}]]", target), end.saturating_add(1)))
+}
+
+fn convert_emphasis(chars: &[char], index: usize) -> Option<(String, usize)> {
+    let marker = *chars.get(index)?;
+    let length = chars
+        .get(index..)?
+        .iter()
+        .take_while(|c| **c == marker)
+        .count();
+    let previous = index.checked_sub(1).and_then(|i| chars.get(i));
+    // Intraword underscores (snake_case) are not emphasis.
+    if marker == '_' && previous.is_some_and(|c| c.is_alphanumeric()) {
+        return None;
+    }
+    let (length, org_marker) = match (marker, length) {
+        ('~', 2) => (2, '+'),
+        ('~', _) => return None,
+        (_, 1) => (1, '/'),
+        (_, 2) => (2, '*'),
+        _ => return None,
+    };
+    let fence = vec![marker; length];
+    let start = index.saturating_add(length);
+    if chars.get(start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+    let mut search = start;
+    loop {
+        let end = find_sequence(chars, search, &fence)?;
+        let before = chars.get(end.saturating_sub(1))?;
+        let after = chars.get(end.saturating_add(length));
+        let closes = !before.is_whitespace()
+            && after != Some(&marker)
+            && !(marker == '_' && after.is_some_and(|c| c.is_alphanumeric()));
+        if closes && end > start {
+            let inner: String = chars.get(start..end)?.iter().collect();
+            return Some((
+                format!("{}{}{}", org_marker, convert_inline(&inner), org_marker),
+                end.saturating_add(length),
+            ));
+        }
+        search = end.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headings() {
+        assert_eq!(
+            to_org("# Title\n## Sub ##\nSetext\n------\nText\n\n---"),
+            "* Title\n** Sub\n** Setext\nText\n\n-----\n"
+        );
+    }
+
+    #[test]
+    fn test_code_block() {
+        assert_eq!(
+            to_org("```rust\nfn main() {}\n* not a headline\n```\n~~~\nplain\n~~~"),
+            "#+BEGIN_SRC rust\nfn main() {}\n,* not a headline\n#+END_SRC\n#+BEGIN_EXAMPLE\nplain\n#+END_EXAMPLE\n"
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(
+            to_org("* one\n  - [x] done\n  - [ ] todo\n1. first"),
+            "- one\n  - [X] done\n  - [ ] todo\n1. first\n"
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            to_org("| Name | Qty |\n|:-----|----:|\n| `milk` | 2 |\nafter"),
+            "| Name | Qty |\n|---+---|\n| ~milk~ | 2 |\nafter\n"
+        );
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(
+            convert_inline("**bold** *it* _it_ ~~gone~~ snake_case_name"),
+            "*bold* /it/ /it/ +gone+ snake_case_name"
+        );
+        assert_eq!(
+            convert_inline("[Org](https://orgmode.org \"Org\") ![logo](logo.png) <https://x.org>"),
+            "[[https://orgmode.org][Org]] [[logo.png]] [[https://x.org]]"
+        );
+        assert_eq!(convert_inline("2 * 3 * 4"), "2 * 3 * 4");
+    }
+
+    #[test]
+    fn test_front_matter_and_quote() {
+        assert_eq!(
+            to_org("---\ntitle: \"Notes\"\ntags: x\n---\n> quoted *text*\n> more"),
+            "#+TITLE: Notes\n#+BEGIN_QUOTE\nquoted /text/\nmore\n#+END_QUOTE\n"
+        );
+    }
+}
@@ -0,0 +1,991 @@
+use crate::timestamp::Timestamp;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TodoKeywords {
+    pub active: Vec<String>,
+    pub done: Vec<String>,
+}
+
+impl Default for TodoKeywords {
+    fn default() -> Self {
+        Self {
+            active: vec!["TODO".to_string()],
+            done: vec!["DONE".to_string()],
+        }
+    }
+}
+
+impl TodoKeywords {
+    pub fn contains(&self, word: &str) -> bool {
+        self.active
+            .iter()
+            .chain(self.done.iter())
+            .any(|k| k == word)
+    }
+    pub fn is_done(&self, word: &str) -> bool {
+        self.done.iter().any(|k| k == word)
+    }
+    // Parses the value of a `#+TODO:` line, e.g. "TODO NEXT | DONE CANCELLED".
+    // Fast-access keys such as "TODO(t)" are stripped.
+    fn from(value: &str) -> Self {
+        let strip = |word: &str| word.split('(').next().unwrap_or(word).to_string();
+        let (active, done) = match value.split_once('|') {
+            Some((active, done)) => (active, done),
+            None => {
+                let mut words: Vec<String> = value.split_whitespace().map(strip).collect();
+                let last = words.pop();
+                return Self {
+                    active: words,
+                    done: last.into_iter().collect(),
+                };
+            }
+        };
+        Self {
+            active: active.split_whitespace().map(strip).collect(),
+            done: done.split_whitespace().map(strip).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OrgDocument {
+    pub keywords: Vec<(String, String)>,
+    pub todo_keywords: TodoKeywords,
+    pub section: Vec<Element>,
+    pub headlines: Vec<Headline>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Headline {
+    pub range: Range<usize>,
+    pub level: usize,
+    pub keyword: Option<String>,
+    pub priority: Option<char>,
+    pub title: String,
+    pub title_column: usize,
+    pub tags: Vec<String>,
+    pub planning: Planning,
+    pub properties: Vec<(String, String)>,
+    pub section: Vec<Element>,
+    pub children: Vec<Headline>,
+}
+
+impl Headline {
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Planning {
+    pub scheduled: Option<Timestamp>,
+    pub deadline: Option<Timestamp>,
+    pub closed: Option<Timestamp>,
+}
+
+// A piece of text together with where it starts in the source: a zero-based
+// line number and grapheme column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceText {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl SourceText {
+    // `suffix` has to be a suffix of `source`, the full line it came from.
+    fn from(line: usize, source: &str, suffix: &str) -> Self {
+        let prefix = source
+            .get(..source.len().saturating_sub(suffix.len()))
+            .unwrap_or("");
+        Self {
+            line,
+            column: prefix.graphemes(true).count(),
+            text: suffix.trim_end().to_string(),
+        }
+    }
+    pub fn join(texts: &[Self]) -> String {
+        texts
+            .iter()
+            .map(|text| text.text.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+// An element of a section, covering the lines in `range` (end exclusive).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub range: Range<usize>,
+    pub kind: ElementKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementKind {
+    Paragraph(Paragraph),
+    List(List),
+    Table(Table),
+    Block(Block),
+    Drawer(Drawer),
+    Keyword(Keyword),
+    FootnoteDefinition(FootnoteDefinition),
+    Comment(Vec<String>),
+    HorizontalRule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paragraph {
+    pub lines: Vec<SourceText>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub range: Range<usize>,
+    pub bullet: String,
+    pub checkbox: Option<char>,
+    pub lines: Vec<SourceText>,
+    pub children: Vec<ListItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub rows: Vec<TableRow>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableRow {
+    Rule,
+    Cells(Vec<SourceText>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub kind: String,
+    pub parameters: String,
+    pub contents: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drawer {
+    pub name: String,
+    pub contents: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyword {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FootnoteDefinition {
+    pub label: String,
+    pub lines: Vec<SourceText>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Bold(Vec<Inline>),
+    Italic(Vec<Inline>),
+    Underline(Vec<Inline>),
+    StrikeThrough(Vec<Inline>),
+    Verbatim(String),
+    Code(String),
+    Link {
+        target: String,
+        description: Option<String>,
+    },
+    FootnoteReference(String),
+    Timestamp(Timestamp),
+}
+
+impl OrgDocument {
+    pub fn parse(text: &str) -> Self {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut document = Self::default();
+        // TODO keywords have to be known before any headline is parsed.
+        for line in &lines {
+            if let Some((key, value)) = parse_keyword(line) {
+                if key == "TODO" || key == "SEQ_TODO" || key == "TYP_TODO" {
+                    document.todo_keywords = TodoKeywords::from(&value);
+                }
+            }
+        }
+        let mut parser = Parser {
+            lines: &lines,
+            index: 0,
+            todo_keywords: &document.todo_keywords,
+        };
+        let section = parser.parse_section();
+        let mut headlines = Vec::new();
+        while parser.index < lines.len() {
+            if let Some(headline) = parser.parse_headline() {
+                headlines.push(headline);
+            }
+        }
+        document.keywords = section
+            .iter()
+            .filter_map(|element| match &element.kind {
+                ElementKind::Keyword(keyword) => Some((keyword.key.clone(), keyword.value.clone())),
+                _ => None,
+            })
+            .collect();
+        document.section = section;
+        document.headlines = headlines;
+        document
+    }
+    pub fn keyword(&self, key: &str) -> Option<&str> {
+        self.keywords
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    pub fn title(&self) -> Option<&str> {
+        self.keyword("TITLE")
+    }
+}
+
+struct Parser<'a> {
+    lines: &'a [&'a str],
+    index: usize,
+    todo_keywords: &'a TodoKeywords,
+}
+
+impl<'a> Parser<'a> {
+    fn current(&self) -> Option<&'a str> {
+        self.lines.get(self.index).copied()
+    }
+    fn advance(&mut self) {
+        self.index = self.index.saturating_add(1);
+    }
+    fn parse_headline(&mut self) -> Option<Headline> {
+        let line = self.current()?;
+        let level = headline_level(line)?;
+        let start = self.index;
+        let mut headline = Headline {
+            level,
+            ..Headline::default()
+        };
+        self.parse_headline_text(
+            &mut headline,
+            line,
+            line.get(level.saturating_add(1)..).unwrap_or(""),
+        );
+        self.advance();
+        if let Some(planning) = self.current().and_then(parse_planning) {
+            headline.planning = planning;
+            self.advance();
+        }
+        headline.section = self.parse_section();
+        if let Some(ElementKind::Drawer(drawer)) = headline.section.first().map(|e| &e.kind) {
+            if drawer.name.eq_ignore_ascii_case("PROPERTIES") {
+                headline.properties = drawer
+                    .contents
+                    .iter()
+                    .filter_map(|line| parse_property(line))
+                    .collect();
+            }
+        }
+        while let Some(next) = self.current() {
+            match headline_level(next) {
+                Some(next_level) if next_level > level => {
+                    if let Some(child) = self.parse_headline() {
+                        headline.children.push(child);
+                    }
+                }
+                _ => break,
+            }
+        }
+        headline.range = start..self.index;
+        Some(headline)
+    }
+    fn parse_headline_text(&self, headline: &mut Headline, line: &str, text: &str) {
+        let mut rest = text.trim_start();
+        if let Some((word, tail)) = split_first_word(rest) {
+            if self.todo_keywords.contains(word) {
+                headline.keyword = Some(word.to_string());
+                rest = tail;
+            }
+        }
+        if let Some(tail) = rest.strip_prefix("[#") {
+            let mut chars = tail.chars();
+            if let (Some(priority), Some(']')) = (chars.next(), chars.next()) {
+                headline.priority = Some(priority);
+                rest = chars.as_str().trim_start();
+            }
+        }
+        headline.title_column = SourceText::from(0, line, rest).column;
+        let rest = rest.trim_end();
+        if let Some((title, tags)) = split_tags(rest) {
+            headline.title = title.to_string();
+            headline.tags = tags;
+        } else {
+            headline.title = rest.to_string();
+        }
+    }
+    fn parse_section(&mut self) -> Vec<Element> {
+        let mut elements = Vec::new();
+        while let Some(line) = self.current() {
+            if headline_level(line).is_some() {
+                break;
+            }
+            if line.trim().is_empty() {
+                self.advance();
+                continue;
+            }
+            let start = self.index;
+            let kind = self.parse_element();
+            elements.push(Element {
+                range: start..self.index,
+                kind,
+            });
+        }
+        elements
+    }
+    fn parse_element(&mut self) -> ElementKind {
+        let start = self.index;
+        let line = self.lines.get(start).copied().unwrap_or("");
+        let trimmed = line.trim();
+        if let Some(block) = self.parse_block() {
+            return ElementKind::Block(block);
+        }
+        if let Some(drawer) = self.parse_drawer() {
+            return ElementKind::Drawer(drawer);
+        }
+        if let Some((key, value)) = parse_keyword(line) {
+            self.advance();
+            return ElementKind::Keyword(Keyword { key, value });
+        }
+        if trimmed == "#" || trimmed.starts_with("# ") {
+            let mut lines = Vec::new();
+            while let Some(line) = self.current() {
+                let trimmed = line.trim();
+                if trimmed != "#" && !trimmed.starts_with("# ") {
+                    break;
+                }
+                lines.push(trimmed.trim_start_matches('#').trim_start().to_string());
+                self.advance();
+            }
+            return ElementKind::Comment(lines);
+        }
+        if trimmed.starts_with('|') {
+            return ElementKind::Table(self.parse_table());
+        }
+        if trimmed.len() >= 5 && trimmed.chars().all(|c| c == '-') {
+            self.advance();
+            return ElementKind::HorizontalRule;
+        }
+        if list_bullet(line).is_some() {
+            let indent = indentation(line);
+            return ElementKind::List(List {
+                items: self.parse_list_items(indent),
+            });
+        }
+        if let Some((label, text)) = parse_footnote_definition(line) {
+            self.advance();
+            let mut lines = vec![SourceText::from(start, line, text)];
+            self.parse_paragraph_lines(&mut lines);
+            return ElementKind::FootnoteDefinition(FootnoteDefinition { label, lines });
+        }
+        let mut lines = vec![SourceText::from(start, line, line.trim_start())];
+        self.advance();
+        self.parse_paragraph_lines(&mut lines);
+        ElementKind::Paragraph(Paragraph { lines })
+    }
+    fn parse_paragraph_lines(&mut self, lines: &mut Vec<SourceText>) {
+        while let Some(line) = self.current() {
+            if line.trim().is_empty() || self.starts_element(line) {
+                break;
+            }
+            lines.push(SourceText::from(self.index, line, line.trim_start()));
+            self.advance();
+        }
+    }
+    // Whether `line` interrupts a running paragraph.
+    fn starts_element(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        headline_level(line).is_some()
+            || trimmed.starts_with("#+")
+            || trimmed.starts_with('|')
+            || trimmed == "#"
+            || trimmed.starts_with("# ")
+            || list_bullet(line).is_some()
+            || parse_footnote_definition(line).is_some()
+            || drawer_name(line).is_some()
+    }
+    fn parse_block(&mut self) -> Option<Block> {
+        let start = self.index;
+        let line = self.current()?.trim();
+        let (kind, parameters) = block_begin(line)?;
+        let end_marker = format!("#+end_{}", kind.to_lowercase());
+        let end = self
+            .lines
+            .iter()
+            .skip(start.saturating_add(1))
+            .position(|l| l.trim().to_lowercase() == end_marker)?
+            .saturating_add(start)
+            .saturating_add(1);
+        let contents = self
+            .lines
+            .get(start.saturating_add(1)..end)
+            .unwrap_or(&[])
+            .iter()
+            .map(|l| (*l).to_string())
+            .collect();
+        self.index = end.saturating_add(1);
+        Some(Block {
+            kind,
+            parameters,
+            contents,
+        })
+    }
+    fn parse_drawer(&mut self) -> Option<Drawer> {
+        let start = self.index;
+        let name = drawer_name(self.current()?)?;
+        let end = self
+            .lines
+            .iter()
+            .skip(start.saturating_add(1))
+            .take_while(|l| headline_level(l).is_none())
+            .position(|l| l.trim().eq_ignore_ascii_case(":END:"))?
+            .saturating_add(start)
+            .saturating_add(1);
+        let contents = self
+            .lines
+            .get(start.saturating_add(1)..end)
+            .unwrap_or(&[])
+            .iter()
+            .map(|l| l.trim().to_string())
+            .collect();
+        self.index = end.saturating_add(1);
+        Some(Drawer { name, contents })
+    }
+    fn parse_table(&mut self) -> Table {
+        let mut table = Table { rows: Vec::new() };
+        while let Some(line) = self.current() {
+            let trimmed = line.trim();
+            if !trimmed.starts_with('|') {
+                break;
+            }
+            if trimmed.starts_with("|-") {
+                table.rows.push(TableRow::Rule);
+            } else {
+                table
+                    .rows
+                    .push(TableRow::Cells(table_cells(self.index, line)));
+            }
+            self.advance();
+        }
+        table
+    }
+    fn parse_list_items(&mut self, indent: usize) -> Vec<ListItem> {
+        let mut items = Vec::new();
+        while let Some(line) = self.current() {
+            if indentation(line) != indent {
+                break;
+            }
+            let Some((bullet, text)) = list_bullet(line) else {
+                break;
+            };
+            let start = self.index;
+            let (checkbox, text) = split_checkbox(text);
+            let mut item = ListItem {
+                range: start..start,
+                bullet,
+                checkbox,
+                lines: Vec::new(),
+                children: Vec::new(),
+            };
+            if !text.is_empty() {
+                item.lines.push(SourceText::from(start, line, text));
+            }
+            self.advance();
+            while let Some(line) = self.current() {
+                if line.trim().is_empty() {
+                    // A single blank line only ends the item when the list
+                    // does not continue below it.
+                    match self.lines.get(self.index.saturating_add(1)) {
+                        Some(next) if !next.trim().is_empty() && indentation(next) > indent => {
+                            self.advance();
+                            continue;
+                        }
+                        Some(next)
+                            if list_bullet(next).is_some() && indentation(next) == indent =>
+                        {
+                            self.advance();
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+                if indentation(line) <= indent || headline_level(line).is_some() {
+                    break;
+                }
+                if list_bullet(line).is_some() {
+                    let child_indent = indentation(line);
+                    item.children.extend(self.parse_list_items(child_indent));
+                    continue;
+                }
+                item.lines
+                    .push(SourceText::from(self.index, line, line.trim_start()));
+                self.advance();
+            }
+            item.range = start..self.index;
+            items.push(item);
+        }
+        items
+    }
+}
+
+fn table_cells(number: usize, line: &str) -> Vec<SourceText> {
+    let mut separators: Vec<usize> = line.match_indices('|').map(|(index, _)| index).collect();
+    // The closing `|` of a row is optional.
+    if line.trim_end().ends_with('|') {
+        separators.pop();
+    }
+    let mut cells = Vec::new();
+    for (position, start) in separators.iter().enumerate() {
+        let start = start.saturating_add(1);
+        let end = separators
+            .get(position.saturating_add(1))
+            .copied()
+            .unwrap_or_else(|| line.trim_end().trim_end_matches('|').len());
+        let cell = line.get(start..end).unwrap_or("");
+        let padding = cell.len().saturating_sub(cell.trim_start().len());
+        let prefix = line.get(..start.saturating_add(padding)).unwrap_or("");
+        cells.push(SourceText {
+            line: number,
+            column: prefix.graphemes(true).count(),
+            text: cell.trim().to_string(),
+        });
+    }
+    cells
+}
+
+fn parse_planning(line: &str) -> Option<Planning> {
+    let mut planning = Planning::default();
+    let mut rest = line.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let (key, tail) = rest.split_once(':')?;
+        let (timestamp, length) = Timestamp::parse(tail.trim_start())?;
+        let slot = match key.trim() {
+            "SCHEDULED" => &mut planning.scheduled,
+            "DEADLINE" => &mut planning.deadline,
+            "CLOSED" => &mut planning.closed,
+            _ => return None,
+        };
+        *slot = Some(timestamp);
+        rest = tail.trim_start().get(length..)?.trim_start();
+    }
+    Some(planning)
+}
+
+fn parse_property(line: &str) -> Option<(String, String)> {
+    let rest = line.trim().strip_prefix(':')?;
+    let (key, value) = rest.split_once(':')?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((key.to_string(), value.trim().to_string()))
+}
+
+pub fn headline_level(line: &str) -> Option<usize> {
+    let level = line.chars().take_while(|c| *c == '*').count();
+    if level == 0 {
+        return None;
+    }
+    match line.get(level..) {
+        Some(rest) if rest.is_empty() || rest.starts_with(' ') => Some(level),
+        _ => None,
+    }
+}
+
+// The column soft-wrapped continuation lines of `line` are indented to: the
+// text of a list item, or the indentation of other lines.
+pub fn wrap_indent(line: &str) -> usize {
+    match list_bullet(line) {
+        Some((_, text)) => line.len().saturating_sub(text.len()),
+        None => indentation(line),
+    }
+}
+
+fn indentation(line: &str) -> usize {
+    line.chars().take_while(|c| *c == ' ' || *c == '\t').count()
+}
+
+fn split_first_word(text: &str) -> Option<(&str, &str)> {
+    let end = text.find(' ').unwrap_or(text.len());
+    let word = text.get(..end)?;
+    if word.is_empty() {
+        return None;
+    }
+    Some((word, text.get(end..).unwrap_or("").trim_start()))
+}
+
+fn split_tags(text: &str) -> Option<(&str, Vec<String>)> {
+    let (title, tags) = match text.rfind(char::is_whitespace) {
+        Some(index) => (text.get(..index)?, text.get(index..)?.trim()),
+        None => ("", text),
+    };
+    if tags.len() < 3 || !tags.starts_with(':') || !tags.ends_with(':') {
+        return None;
+    }
+    let tags: Vec<String> = tags
+        .trim_matches(':')
+        .split(':')
+        .map(ToString::to_string)
+        .collect();
+    let valid = tags.iter().all(|tag| {
+        !tag.is_empty()
+            && tag
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '@' || c == '#' || c == '%')
+    });
+    if valid {
+        Some((title.trim_end(), tags))
+    } else {
+        None
+    }
+}
+
+fn parse_keyword(line: &str) -> Option<(String, String)> {
+    let rest = line.trim().strip_prefix("#+")?;
+    let (key, value) = rest.split_once(':')?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    let key = key.to_uppercase();
+    if key.starts_with("BEGIN_") || key.starts_with("END_") {
+        return None;
+    }
+    Some((key, value.trim().to_string()))
+}
+
+fn block_begin(line: &str) -> Option<(String, String)> {
+    let rest = line.get(..8)?;
+    if !rest.eq_ignore_ascii_case("#+begin_") {
+        return None;
+    }
+    let rest = line.get(8..)?;
+    let (kind, parameters) = match rest.split_once(char::is_whitespace) {
+        Some((kind, parameters)) => (kind, parameters.trim()),
+        None => (rest, ""),
+    };
+    if kind.is_empty() {
+        return None;
+    }
+    Some((kind.to_lowercase(), parameters.to_string()))
+}
+
+fn drawer_name(line: &str) -> Option<String> {
+    let name = line.trim().strip_prefix(':')?.strip_suffix(':')?;
+    if name.is_empty()
+        || name.eq_ignore_ascii_case("END")
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+fn list_bullet(line: &str) -> Option<(String, &str)> {
+    let trimmed = line.trim_start();
+    let indent = line.len().saturating_sub(trimmed.len());
+    let (bullet, rest) = split_first_word(trimmed)?;
+    let is_bullet = match bullet {
+        "-" | "+" => true,
+        "*" => indent > 0,
+        _ => {
+            let digits = bullet.trim_end_matches(['.', ')']);
+            bullet.len() == digits.len().saturating_add(1)
+                && !digits.is_empty()
+                && digits.chars().all(|c| c.is_ascii_digit())
+        }
+    };
+    if is_bullet && (rest.len() < trimmed.len().saturating_sub(bullet.len()) || rest.is_empty()) {
+        Some((bullet.to_string(), rest))
+    } else {
+        None
+    }
+}
+
+fn split_checkbox(text: &str) -> (Option<char>, &str) {
+    let mut chars = text.chars();
+    if let (Some('['), Some(state), Some(']')) = (chars.next(), chars.next(), chars.next()) {
+        if state == ' ' || state == 'X' || state == 'x' || state == '-' {
+            let rest = chars.as_str();
+            if rest.is_empty() || rest.starts_with(' ') {
+                return (Some(state.to_ascii_uppercase()), rest.trim_start());
+            }
+        }
+    }
+    (None, text)
+}
+
+fn parse_footnote_definition(line: &str) -> Option<(String, &str)> {
+    let rest = line.strip_prefix("[fn:")?;
+    let (label, text) = rest.split_once(']')?;
+    if label.is_empty() || label.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((label.to_string(), text.trim_start()))
+}
+
+pub fn parse_inline(text: &str) -> Vec<Inline> {
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+    let mut objects = Vec::new();
+    let mut plain = String::new();
+    let mut index = 0;
+    while let Some(grapheme) = graphemes.get(index) {
+        if let Some((object, next)) = parse_object(&graphemes, index) {
+            if !plain.is_empty() {
+                objects.push(Inline::Text(std::mem::take(&mut plain)));
+            }
+            objects.push(object);
+            index = next;
+            continue;
+        }
+        plain.push_str(grapheme);
+        index = index.saturating_add(1);
+    }
+    if !plain.is_empty() {
+        objects.push(Inline::Text(plain));
+    }
+    objects
+}
+
+fn parse_object(graphemes: &[&str], index: usize) -> Option<(Inline, usize)> {
+    let current = *graphemes.get(index)?;
+    match current {
+        "[" => parse_link(graphemes, index)
+            .or_else(|| parse_footnote_reference(graphemes, index))
+            .or_else(|| parse_timestamp(graphemes, index)),
+        "<" => parse_timestamp(graphemes, index),
+        "*" | "/" | "_" | "+" | "=" | "~" => parse_emphasis(graphemes, index),
+        _ => None,
+    }
+}
+
+fn parse_link(graphemes: &[&str], index: usize) -> Option<(Inline, usize)> {
+    if graphemes.get(index.saturating_add(1)) != Some(&"[") {
+        return None;
+    }
+    let rest: String = graphemes.get(index.saturating_add(2)..)?.concat();
+    let end = rest.find("]]")?;
+    let inner = rest.get(..end)?;
+    let (target, description) = match inner.split_once("][") {
+        Some((target, description)) => (target, Some(description.to_string())),
+        None => (inner, None),
+    };
+    if target.is_empty() || target.contains('[') {
+        return None;
+    }
+    let length = inner.graphemes(true).count().saturating_add(4);
+    Some((
+        Inline::Link {
+            target: target.to_string(),
+            description,
+        },
+        index.saturating_add(length),
+    ))
+}
+
+fn parse_footnote_reference(graphemes: &[&str], index: usize) -> Option<(Inline, usize)> {
+    let rest: String = graphemes.get(index..)?.concat();
+    let label = rest.strip_prefix("[fn:")?;
+    let end = label.find(']')?;
+    let label = label.get(..end)?;
+    if label.is_empty() || label.contains(char::is_whitespace) {
+        return None;
+    }
+    let length = label.graphemes(true).count().saturating_add(5);
+    Some((
+        Inline::FootnoteReference(label.to_string()),
+        index.saturating_add(length),
+    ))
+}
+
+fn parse_timestamp(graphemes: &[&str], index: usize) -> Option<(Inline, usize)> {
+    let rest: String = graphemes.get(index..)?.concat();
+    let (timestamp, length) = Timestamp::parse(&rest)?;
+    let length = rest.get(..length)?.graphemes(true).count();
+    Some((Inline::Timestamp(timestamp), index.saturating_add(length)))
+}
+
+fn parse_emphasis(graphemes: &[&str], index: usize) -> Option<(Inline, usize)> {
+    let marker = *graphemes.get(index)?;
+    if let Some(pre) = index.checked_sub(1).and_then(|i| graphemes.get(i)) {
+        if !(is_whitespace(pre) || "-({'\"".contains(pre)) {
+            return None;
+        }
+    }
+    let first = *graphemes.get(index.saturating_add(1))?;
+    if is_whitespace(first) || first == marker {
+        return None;
+    }
+    let mut end = index.saturating_add(2);
+    while let Some(grapheme) = graphemes.get(end) {
+        if *grapheme == marker {
+            let last = graphemes.get(end.saturating_sub(1)).copied().unwrap_or(" ");
+            let post = graphemes.get(end.saturating_add(1)).copied();
+            let post_ok = post.is_none_or(|p| is_whitespace(p) || "-.,:!?;'\")}[".contains(p));
+            if !is_whitespace(last) && post_ok {
+                break;
+            }
+        }
+        end = end.saturating_add(1);
+    }
+    graphemes.get(end)?;
+    let contents: String = graphemes.get(index.saturating_add(1)..end)?.concat();
+    let object = match marker {
+        "*" => Inline::Bold(parse_inline(&contents)),
+        "/" => Inline::Italic(parse_inline(&contents)),
+        "_" => Inline::Underline(parse_inline(&contents)),
+        "+" => Inline::StrikeThrough(parse_inline(&contents)),
+        "=" => Inline::Verbatim(contents),
+        _ => Inline::Code(contents),
+    };
+    Some((object, end.saturating_add(1)))
+}
+
+fn is_whitespace(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_headline() {
+        let document = OrgDocument::parse("** TODO [#A] Buy milk :errand:home:\nBody text");
+        let headline = &document.headlines[0];
+        assert_eq!(headline.level, 2);
+        assert_eq!(headline.keyword.as_deref(), Some("TODO"));
+        assert_eq!(headline.priority, Some('A'));
+        assert_eq!(headline.title, "Buy milk");
+        assert_eq!(headline.tags, vec!["errand", "home"]);
+        assert_eq!(headline.title_column, 13);
+        assert_eq!(headline.section.len(), 1);
+        assert_eq!(headline.range, 0..2);
+    }
+
+    #[test]
+    fn test_parse_planning_and_properties() {
+        let text = "* TODO Call\nSCHEDULED: <2024-05-01 Wed> DEADLINE: <2024-05-03 Fri>\n:PROPERTIES:\n:ID: abc-123\n:END:\nText";
+        let document = OrgDocument::parse(text);
+        let headline = &document.headlines[0];
+        let scheduled = headline.planning.scheduled.as_ref().unwrap();
+        assert_eq!(scheduled.date.to_string(), "2024-05-01");
+        assert!(headline.planning.deadline.is_some());
+        assert!(headline.planning.closed.is_none());
+        assert_eq!(headline.property("id"), Some("abc-123"));
+        assert_eq!(headline.section.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_nested_headlines() {
+        let document = OrgDocument::parse("* One\n** Two\n*** Three\n** Four\n* Five");
+        assert_eq!(document.headlines.len(), 2);
+        assert_eq!(document.headlines[0].children.len(), 2);
+        assert_eq!(document.headlines[0].children[0].children[0].title, "Three");
+    }
+
+    #[test]
+    fn test_custom_todo_keywords() {
+        let document = OrgDocument::parse("#+TODO: NEXT WAIT | DONE CANCELLED\n* WAIT Reply");
+        assert_eq!(document.headlines[0].keyword.as_deref(), Some("WAIT"));
+        assert_eq!(document.todo_keywords.done, vec!["DONE", "CANCELLED"]);
+    }
+
+    #[test]
+    fn test_parse_elements() {
+        let text = "#+TITLE: Notes\nA paragraph\nspanning lines.\n\n- [X] one\n  continued\n  - nested\n- two\n\n| a | b |\n|---+---|\n| 1 | 2 |\n#+BEGIN_SRC rust\nfn main() {}\n#+END_SRC\n[fn:1] A note.";
+        let document = OrgDocument::parse(text);
+        assert_eq!(document.title(), Some("Notes"));
+        let kinds: Vec<&str> = document
+            .section
+            .iter()
+            .map(|element| match element.kind {
+                ElementKind::Keyword(_) => "keyword",
+                ElementKind::Paragraph(_) => "paragraph",
+                ElementKind::List(_) => "list",
+                ElementKind::Table(_) => "table",
+                ElementKind::Block(_) => "block",
+                ElementKind::FootnoteDefinition(_) => "footnote",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec!["keyword", "paragraph", "list", "table", "block", "footnote"]
+        );
+        assert_eq!(document.section[2].range, 4..8);
+        if let ElementKind::List(list) = &document.section[2].kind {
+            assert_eq!(list.items.len(), 2);
+            assert_eq!(list.items[0].checkbox, Some('X'));
+            assert_eq!(SourceText::join(&list.items[0].lines), "one continued");
+            assert_eq!(list.items[0].lines[0].column, 6);
+            assert_eq!(list.items[0].children.len(), 1);
+            assert_eq!(list.items[0].range, 4..7);
+        }
+        if let ElementKind::Table(table) = &document.section[3].kind {
+            assert_eq!(table.rows[1], TableRow::Rule);
+            assert_eq!(
+                table.rows[2],
+                TableRow::Cells(vec![
+                    SourceText {
+                        line: 11,
+                        column: 2,
+                        text: "1".to_string()
+                    },
+                    SourceText {
+                        line: 11,
+                        column: 6,
+                        text: "2".to_string()
+                    },
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_inline() {
+        assert_eq!(
+            parse_inline("a *bold* [[https://x.org][site]] =v= [fn:2]"),
+            vec![
+                Inline::Text("a ".to_string()),
+                Inline::Bold(vec![Inline::Text("bold".to_string())]),
+                Inline::Text(" ".to_string()),
+                Inline::Link {
+                    target: "https://x.org".to_string(),
+                    description: Some("site".to_string()),
+                },
+                Inline::Text(" ".to_string()),
+                Inline::Verbatim("v".to_string()),
+                Inline::Text(" ".to_string()),
+                Inline::FootnoteReference("2".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_inline("2*3*4 and a/b/c"),
+            vec![Inline::Text("2*3*4 and a/b/c".to_string())]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::{HashMap, VecDeque};
+
+// Deletes kept in the numbered registers `1` to `9`.
+const DELETE_HISTORY: usize = 9;
+// Yanks and deletes kept for cycling through after a put.
+const KILL_RING_SIZE: usize = 30;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Kind {
+    Characters,
+    Lines,
+    // A visual block: each line goes in its own row, at the same column.
+    Block,
+}
+
+// Text in a register. Lines are joined by newlines, without one at the
+// end.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Register {
+    pub text: String,
+    pub kind: Kind,
+}
+
+impl Register {
+    pub fn new(text: String, kind: Kind) -> Self {
+        Self { text, kind }
+    }
+    pub fn lines(&self) -> Vec<&str> {
+        self.text.split('\n').collect()
+    }
+    // Adds `other` at the end, as Vim does for the uppercase registers:
+    // anything appended to or from lines becomes lines.
+    fn append(&mut self, other: &Self) {
+        if self.kind == Kind::Characters && other.kind == Kind::Characters {
+            self.text.push_str(&other.text);
+        } else {
+            self.text.push('\n');
+            self.text.push_str(&other.text);
+            self.kind = Kind::Lines;
+        }
+    }
+}
+
+// Whether `name` can follow `"` in a command.
+pub fn is_valid(name: char) -> bool {
+    matches!(name, '"' | '_' | '+' | '*') || name.is_ascii_digit() || name.is_ascii_alphabetic()
+}
+
+// Vim's registers: the unnamed one, `0` with the last yank, `1` to `9` with
+// the last deletes, the named `a` to `z` (appended to as `A` to `Z`) and
+// the black hole `_`. The clipboard registers `+` and `*` keep what was
+// copied last, for when the system clipboard cannot be read. Everything
+// stored also goes on an Emacs-style kill ring.
+#[derive(Default)]
+pub struct Registers {
+    unnamed: Option<Register>,
+    yanked: Option<Register>,
+    deleted: VecDeque<Register>,
+    named: HashMap<char, Register>,
+    kill_ring: VecDeque<Register>,
+}
+
+impl Registers {
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        self.store(name, register, false);
+    }
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        self.store(name, register, true);
+    }
+    fn store(&mut self, name: Option<char>, register: Register, deleted: bool) {
+        let register = match name {
+            Some('_') => return,
+            Some(name) if name.is_ascii_lowercase() || name == '+' || name == '*' => {
+                self.named.insert(name, register.clone());
+                register
+            }
+            Some(name) if name.is_ascii_uppercase() => self.append(name, register),
+            _ if deleted => {
+                self.deleted.push_front(register.clone());
+                self.deleted.truncate(DELETE_HISTORY);
+                register
+            }
+            _ => {
+                self.yanked = Some(register.clone());
+                register
+            }
+        };
+        self.kill_ring.push_front(register.clone());
+        self.kill_ring.truncate(KILL_RING_SIZE);
+        self.unnamed = Some(register);
+    }
+    fn append(&mut self, name: char, register: Register) -> Register {
+        let named = self
+            .named
+            .entry(name.to_ascii_lowercase())
+            .or_insert_with(|| Register::new(String::new(), register.kind));
+        if named.text.is_empty() {
+            *named = register;
+        } else {
+            named.append(&register);
+        }
+        named.clone()
+    }
+    // Stores a recorded macro in the named register `name`, or appends it
+    // for an uppercase name. The unnamed register is left alone.
+    pub fn record(&mut self, name: char, register: Register) {
+        if name.is_ascii_uppercase() {
+            self.append(name, register);
+        } else {
+            self.named.insert(name, register);
+        }
+    }
+    pub fn get(&self, name: Option<char>) -> Option<&Register> {
+        match name.unwrap_or('"') {
+            '"' => self.unnamed.as_ref(),
+            '0' => self.yanked.as_ref(),
+            name if name.is_ascii_digit() => {
+                let index = name.to_digit(10).map_or(0, |digit| digit as usize);
+                self.deleted.get(index.saturating_sub(1))
+            }
+            name if name.is_ascii_alphabetic() => self.named.get(&name.to_ascii_lowercase()),
+            name @ ('+' | '*') => self.named.get(&name),
+            _ => None,
+        }
+    }
+    // The kill `index` back from the newest, wrapping around.
+    pub fn kill(&self, index: usize) -> Option<&Register> {
+        self.kill_ring
+            .get(index.checked_rem(self.kill_ring.len()).unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn characters(text: &str) -> Register {
+        Register::new(text.to_string(), Kind::Characters)
+    }
+
+    fn lines(text: &str) -> Register {
+        Register::new(text.to_string(), Kind::Lines)
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut registers = Registers::default();
+        registers.yank(None, characters("yanked"));
+        registers.delete(None, lines("first"));
+        registers.delete(None, characters("second"));
+        assert_eq!(registers.get(None), Some(&characters("second")));
+        assert_eq!(registers.get(Some('0')), Some(&characters("yanked")));
+        assert_eq!(registers.get(Some('1')), Some(&characters("second")));
+        assert_eq!(registers.get(Some('2')), Some(&lines("first")));
+        assert_eq!(registers.get(Some('3')), None);
+        for index in 0..12 {
+            registers.delete(None, characters(&index.to_string()));
+        }
+        assert_eq!(registers.get(Some('9')), Some(&characters("3")));
+        // The black hole keeps the unnamed register.
+        registers.delete(Some('_'), characters("gone"));
+        assert_eq!(registers.get(None), Some(&characters("11")));
+    }
+
+    #[test]
+    fn test_named_registers() {
+        let mut registers = Registers::default();
+        registers.yank(Some('a'), characters("one"));
+        registers.yank(Some('A'), characters(" two"));
+        assert_eq!(registers.get(Some('a')), Some(&characters("one two")));
+        assert_eq!(registers.get(None), Some(&characters("one two")));
+        // A named yank leaves register 0 alone.
+        assert_eq!(registers.get(Some('0')), None);
+        registers.yank(Some('A'), lines("three"));
+        assert_eq!(registers.get(Some('a')), Some(&lines("one two\nthree")));
+        registers.yank(Some('B'), characters("new"));
+        assert_eq!(registers.get(Some('b')), Some(&characters("new")));
+        registers.yank(Some('+'), characters("copied"));
+        assert_eq!(registers.get(Some('+')), Some(&characters("copied")));
+        assert_eq!(registers.get(Some('*')), None);
+    }
+
+    #[test]
+    fn test_kill_ring() {
+        let mut registers = Registers::default();
+        assert_eq!(registers.kill(0), None);
+        registers.yank(None, characters("a"));
+        registers.delete(Some('x'), characters("b"));
+        registers.delete(None, characters("c"));
+        assert_eq!(registers.kill(0), Some(&characters("c")));
+        assert_eq!(registers.kill(1), Some(&characters("b")));
+        assert_eq!(registers.kill(2), Some(&characters("a")));
+        assert_eq!(registers.kill(3), Some(&characters("c")));
+    }
+}
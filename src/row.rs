@@ -1,15 +1,30 @@
 use crate::highlighting;
 use crate::HighlightingOptions;
 use crate::SearchDirection;
-use std::cmp;
-use termion::color;
+use regex::Regex;
+use std::borrow::Cow;
+use std::ops::Range;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// The number of columns `grapheme` takes up when it starts at `column`.
+fn grapheme_width(grapheme: &str, column: usize, tab_stop: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_stop = tab_stop.max(1);
+        tab_stop.saturating_sub(column % tab_stop)
+    } else {
+        grapheme.width()
+    }
+}
 
 #[derive(Default)]
 pub struct Row {
     string: String,
     highlighting: Vec<highlighting::Type>,
     pub is_highlighted: bool,
+    // The states the row was last highlighted with.
+    pub start_state: highlighting::State,
+    pub end_state: highlighting::State,
     len: usize,
 }
 
@@ -19,45 +34,186 @@ impl From<&str> for Row {
             string: String::from(slice),
             highlighting: Vec::new(),
             is_highlighted: false,
+            start_state: highlighting::State::default(),
+            end_state: highlighting::State::default(),
             len: slice.graphemes(true).count(),
         }
     }
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
-        let mut result = String::new();
-        let mut current_highlighting = &highlighting::Type::None;
-        #[allow(clippy::integer_arithmetic)]
+    #[cfg(test)]
+    pub fn highlighting(&self) -> &[highlighting::Type] {
+        &self.highlighting
+    }
+    // The graphemes between display columns `start` and `end` with their
+    // highlighting, and whether they are in the `selected` range. Tabs are
+    // expanded to the next tab stop, and wide graphemes cut off at either
+    // edge are replaced by spaces.
+    pub fn render(
+        &self,
+        start: usize,
+        end: usize,
+        tab_stop: usize,
+        selected: &Range<usize>,
+    ) -> Vec<(Cow<'_, str>, highlighting::Type, bool)> {
+        let mut result = Vec::new();
+        let mut column: usize = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            if column >= end {
+                break;
+            }
+            let width = grapheme_width(grapheme, column, tab_stop);
+            let next = column.saturating_add(width);
+            if next > start || (width == 0 && column > start) {
+                let highlighting_type = self
+                    .highlighting
+                    .get(index)
+                    .copied()
+                    .unwrap_or(highlighting::Type::None);
+                let visible = next.min(end).saturating_sub(column.max(start));
+                let grapheme = if grapheme == "\t" || visible < width {
+                    Cow::Owned(" ".repeat(visible))
+                } else {
+                    Cow::Borrowed(grapheme)
+                };
+                result.push((grapheme, highlighting_type, selected.contains(&index)));
+            }
+            column = next;
+        }
+        result
+    }
+    // The display column the grapheme at `index` starts at.
+    pub fn column(&self, index: usize, tab_stop: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .take(index)
+            .fold(0, |column, grapheme| {
+                column.saturating_add(grapheme_width(grapheme, column, tab_stop))
+            })
+    }
+    // The index of the grapheme covering display `column`, or the length of
+    // the row if it ends before.
+    pub fn index(&self, column: usize, tab_stop: usize) -> usize {
+        let mut start: usize = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            let end = start.saturating_add(grapheme_width(grapheme, start, tab_stop));
+            if column < end {
+                return index;
+            }
+            start = end;
+        }
+        self.len
+    }
+    // Splits the row into screen lines of at most `width` columns for soft
+    // wrapping, returning the index each line starts at. Continuation lines
+    // start at column `indent`. With `words`, lines are broken after
+    // whitespace where possible.
+    pub fn wrap(&self, width: usize, tab_stop: usize, words: bool, indent: usize) -> Vec<usize> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let mut starts = vec![0];
+        let mut start = 0;
+        let mut column: usize = 0;
+        // The index after the last whitespace on the current line.
+        let mut break_after: Option<usize> = None;
+        for (index, grapheme) in graphemes.iter().enumerate() {
+            let mut width_here = grapheme_width(grapheme, column, tab_stop);
+            if column.saturating_add(width_here) > width && index > start {
+                let mut line_start = if words {
+                    break_after.filter(|after| *after > start).unwrap_or(index)
+                } else {
+                    index
+                };
+                // The moved part of a word that does not fit after the
+                // indentation is broken anyway.
+                let mut moved = indent;
+                for moved_grapheme in graphemes.get(line_start..index).unwrap_or(&[]) {
+                    moved = moved.saturating_add(grapheme_width(moved_grapheme, moved, tab_stop));
+                }
+                if moved.saturating_add(width_here) > width {
+                    line_start = index;
+                    moved = indent;
+                }
+                starts.push(line_start);
+                start = line_start;
+                column = moved;
+                break_after = None;
+                width_here = grapheme_width(grapheme, column, tab_stop);
+            }
+            column = column.saturating_add(width_here);
+            if words && grapheme.chars().all(char::is_whitespace) {
+                break_after = Some(index.saturating_add(1));
+            }
+        }
+        starts
+    }
+    // The screen column of the grapheme at `index`, on a screen line that
+    // starts with the grapheme at `start` in `column`.
+    pub fn wrapped_column(&self, start: usize, index: usize, column: usize, tab_stop: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .take(index)
+            .skip(start)
+            .fold(column, |column, grapheme| {
+                column.saturating_add(grapheme_width(grapheme, column, tab_stop))
+            })
+    }
+    // The index of the grapheme covering screen column `target` on a screen
+    // line with the graphemes from `start` to `end`, starting in `column`.
+    pub fn wrapped_index(
+        &self,
+        start: usize,
+        end: usize,
+        column: usize,
+        target: usize,
+        tab_stop: usize,
+    ) -> usize {
+        let mut column = column;
         for (index, grapheme) in self.string[..]
             .graphemes(true)
             .enumerate()
+            .take(end)
             .skip(start)
-            .take(end - start)
         {
-            if let Some(c) = grapheme.chars().next() {
+            column = column.saturating_add(grapheme_width(grapheme, column, tab_stop));
+            if target < column {
+                return index;
+            }
+        }
+        end
+    }
+    // The graphemes from `start` to `end` as `render` returns them, for a
+    // screen line starting in `column`.
+    pub fn render_wrapped(
+        &self,
+        start: usize,
+        end: usize,
+        column: usize,
+        tab_stop: usize,
+        selected: &Range<usize>,
+    ) -> Vec<(Cow<'_, str>, highlighting::Type, bool)> {
+        let mut column = column;
+        self.string[..]
+            .graphemes(true)
+            .enumerate()
+            .take(end)
+            .skip(start)
+            .map(|(index, grapheme)| {
+                let width = grapheme_width(grapheme, column, tab_stop);
+                column = column.saturating_add(width);
                 let highlighting_type = self
                     .highlighting
                     .get(index)
-                    .unwrap_or(&highlighting::Type::None);
-                if highlighting_type != current_highlighting {
-                    current_highlighting = highlighting_type;
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()));
-                    result.push_str(&start_highlight[..]);
-                }
-                if c == '\t' {
-                    result.push_str(" ");
+                    .copied()
+                    .unwrap_or(highlighting::Type::None);
+                let grapheme = if grapheme == "\t" {
+                    Cow::Owned(" ".repeat(width))
                 } else {
-                    result.push(c);
-                }
-            }
-        }
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
-        result.push_str(&end_highlight[..]);
-        result
+                    Cow::Borrowed(grapheme)
+                };
+                (grapheme, highlighting_type, selected.contains(&index))
+            })
+            .collect()
     }
     pub fn len(&self) -> usize {
         self.len
@@ -102,6 +258,7 @@ impl Row {
     pub fn append(&mut self, new: &Self) {
         self.string = format!("{}{}", self.string, new.string);
         self.len += new.len;
+        self.is_highlighted = false;
     }
     pub fn split(&mut self, at: usize) -> Self {
         let mut row: String = String::new();
@@ -126,65 +283,75 @@ impl Row {
             len: splitted_length,
             is_highlighted: false,
             highlighting: Vec::new(),
+            start_state: highlighting::State::default(),
+            end_state: highlighting::State::default(),
         }
     }
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
     }
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
-        if at > self.len || query.is_empty() {
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+    // The text of the graphemes from `start` up to `end`, clamped to the row.
+    pub fn slice(&self, start: usize, end: usize) -> &str {
+        let byte = |index: usize| {
+            self.string
+                .grapheme_indices(true)
+                .nth(index)
+                .map_or(self.string.len(), |(byte, _)| byte)
+        };
+        let end = byte(end);
+        let start = byte(start).min(end);
+        self.string.get(start..end).unwrap_or_default()
+    }
+    // The index of the grapheme starting at `byte`, or of the next one when
+    // `byte` is inside a grapheme.
+    pub fn grapheme_at(&self, byte: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .take_while(|(start, _)| *start < byte)
+            .count()
+    }
+    // Going forward, the first match of `pattern` starting at or after
+    // grapheme `at`; going backward, the last one starting before it.
+    pub fn find(&self, pattern: &Regex, at: usize, direction: SearchDirection) -> Option<usize> {
+        if at > self.len {
             return None;
         }
-        let start = if direction == SearchDirection::Forward {
-            at
-        } else {
-            0
-        };
-        let end = if direction == SearchDirection::Forward {
-            self.len
+        let found = if direction == SearchDirection::Forward {
+            let byte = self
+                .string
+                .grapheme_indices(true)
+                .nth(at)
+                .map_or(self.string.len(), |(byte, _)| byte);
+            pattern.find_at(&self.string, byte).map(|found| found.start())
         } else {
-            at
+            pattern
+                .find_iter(&self.string)
+                .map(|found| found.start())
+                .take_while(|start| self.grapheme_at(*start) < at)
+                .last()
         };
-        #[allow(clippy::integer_arithmetic)]
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
-        let matching_byte_index = if direction == SearchDirection::Forward {
-            substring.find(query)
-        } else {
-            substring.rfind(query)
-        };
-        if let Some(matching_byte_index) = matching_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring[..].grapheme_indices(true).enumerate()
-            {
-                if matching_byte_index == byte_index {
-                    #[allow(clippy::integer_arithmetic)]
-                    return Some(start + grapheme_index);
-                }
-            }
-        }
-        None
+        found.map(|byte| self.grapheme_at(byte))
+    }
+    // The graphemes of each non-empty match of `pattern`.
+    pub fn matches(&self, pattern: &Regex) -> Vec<Range<usize>> {
+        pattern
+            .find_iter(&self.string)
+            .filter(|found| !found.is_empty())
+            .map(|found| self.grapheme_at(found.start())..self.grapheme_at(found.end()))
+            .collect()
     }
 
-    fn highlight_match(&mut self, word: &Option<String>) {
-        if let Some(word) = word {
-            if word.is_empty() {
-                return;
-            }
-            let mut index = 0;
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                if let Some(next_index) = search_match.checked_add(word[..].graphemes(true).count())
-                {
-                    #[allow(clippy::indexing_slicing)]
-                    for i in search_match..next_index {
-                        self.highlighting[i] = highlighting::Type::Match;
-                    }
-                    index = next_index;
-                } else {
-                    break;
+    fn highlight_match(&mut self, word: &Option<Regex>) {
+        let Some(word) = word else {
+            return;
+        };
+        for found in self.matches(word) {
+            for index in found {
+                if let Some(highlighting) = self.highlighting.get_mut(index) {
+                    *highlighting = highlighting::Type::Match;
                 }
             }
         }
@@ -196,13 +363,19 @@ impl Row {
         substring: &str,
         chars: &[char],
         hl_type: highlighting::Type,
+        ignore_case: bool,
     ) -> bool {
         if substring.is_empty() {
             return false;
         }
         for (substring_index, c) in substring.chars().enumerate() {
             if let Some(next_char) = chars.get(index.saturating_add(substring_index)) {
-                if *next_char != c {
+                let matches = if ignore_case {
+                    next_char.eq_ignore_ascii_case(&c)
+                } else {
+                    *next_char == c
+                };
+                if !matches {
                     return false;
                 }
             } else {
@@ -221,6 +394,7 @@ impl Row {
         chars: &[char],
         keywords: &[String],
         hl_type: highlighting::Type,
+        ignore_case: bool,
     ) -> bool {
         if *index > 0 {
             #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
@@ -238,7 +412,7 @@ impl Row {
                 }
             }
 
-            if self.highlight_str(index, &word, chars, hl_type) {
+            if self.highlight_str(index, &word, chars, hl_type, ignore_case) {
                 return true;
             }
         }
@@ -256,6 +430,7 @@ impl Row {
             chars,
             opts.primary_keywords(),
             highlighting::Type::PrimaryKeywords,
+            opts.ignore_case(),
         )
     }
     fn highlight_secondary_keywords(
@@ -269,6 +444,7 @@ impl Row {
             chars,
             opts.secondary_keywords(),
             highlighting::Type::SecondaryKeywords,
+            opts.ignore_case(),
         )
     }
 
@@ -304,46 +480,36 @@ impl Row {
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '/' {
-                    for _ in *index..chars.len() {
-                        self.highlighting.push(highlighting::Type::Comment);
-                        *index += 1;
-                    }
-                    return true;
+        if let Some(start) = opts.line_comment() {
+            if starts_with_at(chars, *index, start) {
+                for _ in *index..chars.len() {
+                    self.highlighting.push(highlighting::Type::Comment);
+                    *index += 1;
                 }
-            };
+                return true;
+            }
         }
         false
     }
-    #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
     fn highlight_multiline_comment(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '*' {
-                    let closing_index =
-                        if let Some(closing_index) = self.string[*index + 2..].find("*/") {
-                            *index + closing_index + 4
-                        } else {
-                            chars.len()
-                        };
-                    for _ in *index..closing_index {
-                        self.highlighting.push(highlighting::Type::MultilineComment);
-                        *index += 1;
-                    }
-                    return true;
+        if let Some((open, close)) = opts.block_comment() {
+            if starts_with_at(chars, *index, open) {
+                let after_open = index.saturating_add(open.chars().count());
+                let closing_index = find_from(chars, after_open, close)
+                    .map_or(chars.len(), |found| found.saturating_add(close.chars().count()));
+                for _ in *index..closing_index {
+                    self.highlighting.push(highlighting::Type::MultilineComment);
+                    *index += 1;
                 }
-            };
+                return true;
+            }
         }
         false
     }
@@ -355,20 +521,25 @@ impl Row {
         c: char,
         chars: &[char],
     ) -> bool {
-        if opts.strings() && c == '"' {
+        if opts.strings().contains(&c) {
             loop {
                 self.highlighting.push(highlighting::Type::String);
                 *index += 1;
                 if let Some(next_char) = chars.get(*index) {
-                    if *next_char == '"' {
+                    if *next_char == '\\' && chars.get(index.saturating_add(1)).is_some() {
+                        self.highlighting.push(highlighting::Type::String);
+                        *index += 1;
+                    } else if *next_char == c {
                         break;
                     }
                 } else {
                     break;
                 }
             }
-            self.highlighting.push(highlighting::Type::String);
-            *index += 1;
+            if *index < chars.len() {
+                self.highlighting.push(highlighting::Type::String);
+                *index += 1;
+            }
             return true;
         }
         false
@@ -407,43 +578,31 @@ impl Row {
     pub fn highlight(
         &mut self,
         opts: &HighlightingOptions,
-        word: &Option<String>,
+        word: &Option<Regex>,
         start_with_comment: bool,
     ) -> bool {
         let chars: Vec<char> = self.string.chars().collect();
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment
-                    && self.string.len() > 1
-                    && self.string[self.string.len() - 2..] == *"*/"
-                {
-                    return true;
-                }
-            }
-            return false;
-        }
+        let closing = opts.block_comment().map_or("", |(_, close)| close);
         self.highlighting = Vec::new();
         let mut index = 0;
         let mut in_ml_comment = start_with_comment;
         if in_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
-                closing_index + 2
-            } else {
-                chars.len()
-            };
+            let closing_index = find_from(&chars, 0, closing)
+                .filter(|_| !closing.is_empty())
+                .map_or(chars.len(), |found| found.saturating_add(closing.chars().count()));
             for _ in 0..closing_index {
                 self.highlighting.push(highlighting::Type::MultilineComment);
             }
             index = closing_index;
         }
         while let Some(c) = chars.get(index) {
-            if self.highlight_multiline_comment(&mut index, &opts, *c, &chars) {
+            if self.highlight_multiline_comment(&mut index, &opts, &chars) {
                 in_ml_comment = true;
                 continue;
             }
             in_ml_comment = false;
             if self.highlight_char(&mut index, opts, *c, &chars)
-                || self.highlight_comment(&mut index, opts, *c, &chars)
+                || self.highlight_comment(&mut index, opts, &chars)
                 || self.highlight_primary_keywords(&mut index, &opts, &chars)
                 || self.highlight_secondary_keywords(&mut index, &opts, &chars)
                 || self.highlight_string(&mut index, opts, *c, &chars)
@@ -454,15 +613,32 @@ impl Row {
             self.highlighting.push(highlighting::Type::None);
             index += 1;
         }
-        self.highlight_match(word);
-        if in_ml_comment && &self.string[self.string.len().saturating_sub(2)..] != "*/" {
-            return true;
+        // The rules above go by chars, but `render` goes by graphemes: each
+        // grapheme takes the type of its first char.
+        let by_char = std::mem::take(&mut self.highlighting);
+        let mut char_index = 0;
+        for grapheme in self.string.graphemes(true) {
+            self.highlighting
+                .push(by_char.get(char_index).copied().unwrap_or(highlighting::Type::None));
+            char_index += grapheme.chars().count();
         }
-        self.is_highlighted = true;
-        false
+        self.highlight_match(word);
+        in_ml_comment && !self.string.ends_with(closing)
     }
 }
 
+fn starts_with_at(chars: &[char], index: usize, pattern: &str) -> bool {
+    !pattern.is_empty()
+        && pattern
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| chars.get(index.saturating_add(offset)) == Some(&c))
+}
+
+fn find_from(chars: &[char], from: usize, pattern: &str) -> Option<usize> {
+    (from..chars.len()).find(|index| starts_with_at(chars, *index, pattern))
+}
+
 fn is_separator(c: char) -> bool {
     c.is_ascii_punctuation() || c.is_ascii_whitespace()
 }
@@ -485,7 +661,7 @@ mod test_super {
             highlighting::Type::None,
             highlighting::Type::None,
         ];
-        row.highlight_match(&Some("t".to_string()));
+        row.highlight_match(&Regex::new("t").ok());
         assert_eq!(
             vec![
                 highlighting::Type::Number,
@@ -502,11 +678,110 @@ mod test_super {
         )
     }
 
+    #[test]
+    fn test_highlight_with_definition() {
+        let opts = HighlightingOptions {
+            strings: vec!['\''],
+            line_comment: Some("--".to_string()),
+            ignore_case: true,
+            primary_keywords: vec!["select".to_string()],
+            ..HighlightingOptions::default()
+        };
+        let mut row = Row::from("SELECT 'a\\'b' -- x");
+        row.highlight(&opts, &None, false);
+        let mut expected = vec![highlighting::Type::PrimaryKeywords; 6];
+        expected.push(highlighting::Type::None);
+        expected.extend(vec![highlighting::Type::String; 6]);
+        expected.push(highlighting::Type::None);
+        expected.extend(vec![highlighting::Type::Comment; 4]);
+        assert_eq!(row.highlighting, expected);
+    }
+
+    #[test]
+    fn test_highlight_combining_marks() {
+        let opts = HighlightingOptions {
+            primary_keywords: vec!["select".to_string()],
+            ..HighlightingOptions::default()
+        };
+        // `é` is `e` and a combining accent: two chars, one grapheme.
+        let mut row = Row::from("e\u{301} select");
+        row.highlight(&opts, &Regex::new("le").ok(), false);
+        let mut expected = vec![highlighting::Type::None; 2];
+        expected.extend(vec![highlighting::Type::PrimaryKeywords; 6]);
+        expected[4] = highlighting::Type::Match;
+        expected[5] = highlighting::Type::Match;
+        assert_eq!(row.highlighting, expected);
+        let rendered: Vec<_> = row
+            .render(0, 8, 4, &(0..0))
+            .into_iter()
+            .map(|(grapheme, hl_type, _)| (grapheme.into_owned(), hl_type))
+            .collect();
+        assert_eq!(rendered[0], ("e\u{301}".to_string(), highlighting::Type::None));
+        assert_eq!(rendered[2], ("s".to_string(), highlighting::Type::PrimaryKeywords));
+        assert_eq!(rendered[4], ("l".to_string(), highlighting::Type::Match));
+    }
+
     #[test]
     fn test_find() {
         let row = Row::from("1testtest");
-        assert_eq!(row.find("t", 0, SearchDirection::Forward), Some(1));
-        assert_eq!(row.find("t", 2, SearchDirection::Forward), Some(4));
-        assert_eq!(row.find("t", 5, SearchDirection::Forward), Some(5));
+        let t = Regex::new("t").unwrap();
+        assert_eq!(row.find(&t, 0, SearchDirection::Forward), Some(1));
+        assert_eq!(row.find(&t, 2, SearchDirection::Forward), Some(4));
+        assert_eq!(row.find(&t, 5, SearchDirection::Forward), Some(5));
+        assert_eq!(row.find(&t, 5, SearchDirection::Backward), Some(4));
+        assert_eq!(row.find(&t, 1, SearchDirection::Backward), None);
+        // Anchors and word boundaries see the whole row.
+        let word = Regex::new(r"\btest").unwrap();
+        assert_eq!(row.find(&word, 2, SearchDirection::Forward), None);
+        let row = Row::from("中文 e\u{301}x");
+        let accent = Regex::new("e\u{301}x").unwrap();
+        assert_eq!(row.find(&accent, 0, SearchDirection::Forward), Some(3));
+        assert_eq!(row.matches(&accent), [3..5]);
+    }
+
+    #[test]
+    fn test_display_columns() {
+        // A wide character, a combining accent and a tab.
+        let row = Row::from("a中e\u{301}\tb");
+        assert_eq!(row.len(), 5);
+        let columns: Vec<usize> = (0..=5).map(|index| row.column(index, 8)).collect();
+        assert_eq!(columns, vec![0, 1, 3, 4, 8, 9]);
+        assert_eq!(row.column(4, 3), 6);
+        assert_eq!(row.index(2, 8), 1);
+        assert_eq!(row.index(6, 8), 3);
+        assert_eq!(row.index(20, 8), 5);
+        let text = |start, end| {
+            row.render(start, end, 8, &(0..0))
+                .iter()
+                .map(|(grapheme, _, _)| grapheme.to_string())
+                .collect::<String>()
+        };
+        assert_eq!(text(0, 20), "a中e\u{301}    b");
+        // Wide characters cut off by an edge become spaces.
+        assert_eq!(text(2, 5), " e\u{301} ");
+        assert_eq!(text(0, 2), "a ");
+        let selected: Vec<bool> = row
+            .render(2, 20, 8, &(1..3))
+            .iter()
+            .map(|(_, _, selected)| *selected)
+            .collect();
+        assert_eq!(selected, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let row = Row::from("- one two three");
+        assert_eq!(row.wrap(8, 8, false, 0), vec![0, 8]);
+        // Words move to the next line, indented past the bullet.
+        assert_eq!(row.wrap(8, 8, true, 2), vec![0, 6, 10]);
+        assert_eq!(row.wrapped_column(6, 8, 2, 8), 4);
+        assert_eq!(row.wrapped_index(6, 10, 2, 4, 8), 8);
+        assert_eq!(row.wrapped_index(6, 10, 2, 7, 8), 10);
+        // Words longer than a line are broken anywhere.
+        let row = Row::from("abcdefghij");
+        assert_eq!(row.wrap(4, 8, true, 2), vec![0, 4, 6, 8]);
+        // Wide characters are not split across lines.
+        let row = Row::from("ab中");
+        assert_eq!(row.wrap(3, 8, false, 0), vec![0, 2]);
     }
 }
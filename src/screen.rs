@@ -0,0 +1,369 @@
+use crate::Position;
+use crate::backend::Backend;
+use std::io;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct Style {
+    pub fg: Option<Rgb>,
+    pub bg: Option<Rgb>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct Cell {
+    grapheme: String,
+    style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            grapheme: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+// The contents of the terminal as a grid of cells. Each frame is drawn into
+// the grid, and `render` only emits what changed since the last frame.
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    // The frame currently on the terminal; `None` when it is unknown.
+    previous: Option<Vec<Cell>>,
+    cursor: Position,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width.saturating_mul(height)],
+            previous: None,
+            cursor: Position::default(),
+        }
+    }
+    // The next frame is drawn from scratch, since the terminal's contents
+    // are unknown after a resize.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.cells = vec![Cell::default(); width.saturating_mul(height)];
+        self.previous = None;
+    }
+    // Starts a new frame.
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::default());
+    }
+    // Writes `text` from column `x` of line `y`, clipped to the width of the
+    // screen. Returns the column after the text. Wide graphemes take up two
+    // cells, and zero-width ones are joined to the cell before them.
+    pub fn put_str(&mut self, x: usize, y: usize, text: &str, style: Style) -> usize {
+        let mut x = x;
+        if y >= self.height {
+            return x;
+        }
+        for grapheme in text.graphemes(true) {
+            let width = grapheme.width();
+            if width == 0 {
+                if let Some(cell) = x.checked_sub(1).and_then(|x| self.cell_mut(x, y)) {
+                    cell.grapheme.push_str(grapheme);
+                }
+                continue;
+            }
+            if x.saturating_add(width) > self.width {
+                // Half a wide grapheme cannot be shown.
+                while x < self.width {
+                    self.set(x, y, " ", style);
+                    x = x.saturating_add(1);
+                }
+                break;
+            }
+            self.set(x, y, grapheme, style);
+            for continuation in 1..width {
+                self.set(x.saturating_add(continuation), y, "", style);
+            }
+            x = x.saturating_add(width);
+        }
+        x
+    }
+    #[cfg(test)]
+    pub fn style(&self, x: usize, y: usize) -> Option<Style> {
+        if x >= self.width {
+            return None;
+        }
+        self.cells
+            .get(y.saturating_mul(self.width).saturating_add(x))
+            .map(|cell| cell.style)
+    }
+    fn cell_mut(&mut self, x: usize, y: usize) -> Option<&mut Cell> {
+        if x >= self.width {
+            return None;
+        }
+        self.cells
+            .get_mut(y.saturating_mul(self.width).saturating_add(x))
+    }
+    // Sets one cell. Overwriting either half of a wide grapheme blanks the
+    // other half.
+    fn set(&mut self, x: usize, y: usize, grapheme: &str, style: Style) {
+        let Some(cell) = self.cell_mut(x, y) else {
+            return;
+        };
+        let was_continuation = cell.grapheme.is_empty();
+        cell.grapheme = grapheme.to_string();
+        cell.style = style;
+        if was_continuation && !grapheme.is_empty() {
+            let mut before = x;
+            while let Some(previous) = before.checked_sub(1) {
+                before = previous;
+                let Some(cell) = self.cell_mut(previous, y) else {
+                    break;
+                };
+                let is_lead = !cell.grapheme.is_empty();
+                cell.grapheme = " ".to_string();
+                if is_lead {
+                    break;
+                }
+            }
+        }
+        let mut after = x.saturating_add(1);
+        while let Some(cell) = self.cell_mut(after, y) {
+            if !cell.grapheme.is_empty() {
+                break;
+            }
+            cell.grapheme = " ".to_string();
+            after = after.saturating_add(1);
+        }
+    }
+    pub fn set_cursor(&mut self, position: Position) {
+        self.cursor = position;
+    }
+    // Sends what changed since the previous frame to `backend`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn render(&mut self, backend: &mut dyn Backend) -> io::Result<()> {
+        backend.hide_cursor()?;
+        if self.previous.is_none() {
+            backend.clear_all()?;
+        }
+        let blank = Cell::default();
+        let mut style = Style::default();
+        for y in 0..self.height {
+            let start = y.saturating_mul(self.width);
+            let line = self
+                .cells
+                .get(start..start.saturating_add(self.width))
+                .unwrap_or(&[]);
+            let old_line = self
+                .previous
+                .as_ref()
+                .and_then(|previous| previous.get(start..start.saturating_add(self.width)));
+            // Where the terminal cursor is after the last write, if on this
+            // line.
+            let mut position: Option<usize> = None;
+            let mut x = 0;
+            while x < line.len() {
+                let Some(cell) = line.get(x) else {
+                    break;
+                };
+                // The rest of a wide grapheme is drawn together with it.
+                if cell.grapheme.is_empty() {
+                    x = x.saturating_add(1);
+                    continue;
+                }
+                let width = line
+                    .get(x.saturating_add(1)..)
+                    .map_or(0, |rest| {
+                        rest.iter()
+                            .take_while(|cell| cell.grapheme.is_empty())
+                            .count()
+                    })
+                    .saturating_add(1);
+                let cells = line.get(x..x.saturating_add(width));
+                let unchanged = match old_line {
+                    Some(old_line) => old_line.get(x..x.saturating_add(width)) == cells,
+                    None => cells.is_some_and(|cells| cells.iter().all(|cell| *cell == blank)),
+                };
+                if unchanged {
+                    x = x.saturating_add(width);
+                    continue;
+                }
+                if position != Some(x) {
+                    backend.move_cursor(x as u16, y as u16)?;
+                }
+                // Clearing is shorter than writing a run of blanks up to the
+                // end of the line.
+                if line
+                    .get(x..)
+                    .is_some_and(|rest| rest.iter().all(|cell| *cell == blank))
+                {
+                    if style != Style::default() {
+                        backend.set_style(Style::default())?;
+                        style = Style::default();
+                    }
+                    backend.clear_until_newline()?;
+                    break;
+                }
+                if cell.style != style {
+                    backend.set_style(cell.style)?;
+                    style = cell.style;
+                }
+                backend.print(&cell.grapheme)?;
+                x = x.saturating_add(width);
+                position = Some(x);
+            }
+        }
+        if style != Style::default() {
+            backend.set_style(Style::default())?;
+        }
+        backend.move_cursor(self.cursor.x as u16, self.cursor.y as u16)?;
+        backend.show_cursor()?;
+        self.previous = Some(self.cells.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    fn frame(
+        screen: &mut Screen,
+        backend: &mut MemoryBackend,
+        lines: &[&str],
+        cursor: Position,
+    ) -> Vec<String> {
+        screen.clear();
+        for (y, line) in lines.iter().enumerate() {
+            screen.put_str(0, y, line, Style::default());
+        }
+        screen.set_cursor(cursor);
+        screen.render(backend).unwrap();
+        backend.take_log()
+    }
+
+    #[test]
+    fn test_first_frame_is_drawn_in_full() {
+        let mut screen = Screen::new(10, 2);
+        let mut backend = MemoryBackend::new(10, 2);
+        let output = frame(&mut screen, &mut backend, &["ab", ""], Position::default());
+        assert_eq!(
+            output,
+            [
+                "hide",
+                "clear all",
+                "move 0,0",
+                "print ab",
+                "move 0,0",
+                "show"
+            ]
+        );
+        assert_eq!(backend.line(0), "ab");
+    }
+
+    #[test]
+    fn test_typing_a_character() {
+        let mut screen = Screen::new(10, 3);
+        let mut backend = MemoryBackend::new(10, 3);
+        frame(
+            &mut screen,
+            &mut backend,
+            &["fn main", "x", "~"],
+            Position { x: 7, y: 0 },
+        );
+        let output = frame(
+            &mut screen,
+            &mut backend,
+            &["fn mains", "x", "~"],
+            Position { x: 8, y: 0 },
+        );
+        assert_eq!(output, ["hide", "move 7,0", "print s", "move 8,0", "show"]);
+    }
+
+    #[test]
+    fn test_deleting_clears_to_end_of_line() {
+        let mut screen = Screen::new(10, 1);
+        let mut backend = MemoryBackend::new(10, 1);
+        frame(&mut screen, &mut backend, &["hello"], Position::default());
+        let output = frame(&mut screen, &mut backend, &["help"], Position::default());
+        assert_eq!(
+            output,
+            [
+                "hide",
+                "move 3,0",
+                "print p",
+                "clear line",
+                "move 0,0",
+                "show"
+            ]
+        );
+        assert_eq!(backend.line(0), "help");
+    }
+
+    #[test]
+    fn test_unchanged_frame_only_moves_cursor() {
+        let mut screen = Screen::new(5, 2);
+        let mut backend = MemoryBackend::new(5, 2);
+        frame(&mut screen, &mut backend, &["a", "b"], Position::default());
+        let output = frame(
+            &mut screen,
+            &mut backend,
+            &["a", "b"],
+            Position { x: 1, y: 1 },
+        );
+        assert_eq!(output, ["hide", "move 1,1", "show"]);
+    }
+
+    #[test]
+    fn test_style_changes() {
+        let mut screen = Screen::new(5, 1);
+        let mut backend = MemoryBackend::new(5, 1);
+        frame(&mut screen, &mut backend, &["ab"], Position::default());
+        screen.clear();
+        let red = Style {
+            fg: Some(Rgb(255, 0, 0)),
+            bg: None,
+        };
+        screen.put_str(0, 0, "a", Style::default());
+        screen.put_str(1, 0, "b", red);
+        screen.render(&mut backend).unwrap();
+        assert_eq!(
+            backend.take_log(),
+            [
+                "hide",
+                "move 1,0",
+                "style Some(Rgb(255, 0, 0)) None",
+                "print b",
+                "style None None",
+                "move 0,0",
+                "show"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wide_graphemes() {
+        let mut screen = Screen::new(6, 1);
+        let mut backend = MemoryBackend::new(6, 1);
+        frame(&mut screen, &mut backend, &["a中b"], Position::default());
+        assert_eq!(backend.line(0), "a中b");
+        // Replacing the wide character redraws both of its cells.
+        let output = frame(&mut screen, &mut backend, &["axyb"], Position::default());
+        assert_eq!(output, ["hide", "move 1,0", "print xy", "move 0,0", "show"]);
+        // Half of a wide character does not fit at the right edge.
+        frame(&mut screen, &mut backend, &["abcde中"], Position::default());
+        assert_eq!(backend.line(0), "abcde");
+        // Overwriting half of one blanks the other half.
+        screen.clear();
+        screen.put_str(0, 0, "中文", Style::default());
+        screen.put_str(1, 0, "x", Style::default());
+        screen.render(&mut backend).unwrap();
+        assert_eq!(backend.line(0), " x文");
+    }
+}
@@ -0,0 +1,89 @@
+use regex::Regex;
+
+// How search patterns are matched, set with `:set`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Options {
+    // Patterns are regular expressions unless `literal`.
+    pub literal: bool,
+    pub ignore_case: bool,
+    // With `ignore_case`, a pattern with an uppercase letter still matches
+    // case.
+    pub smart_case: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            literal: false,
+            ignore_case: true,
+            smart_case: true,
+        }
+    }
+}
+
+impl Options {
+    pub fn ignores_case(&self, pattern: &str) -> bool {
+        self.ignore_case && !(self.smart_case && has_uppercase(pattern, self.literal))
+    }
+    pub fn regex(&self, pattern: &str) -> Result<Regex, String> {
+        regex(pattern, self.literal, self.ignores_case(pattern))
+    }
+}
+
+// Escapes like `\S` and `\W` are not uppercase letters of the pattern.
+fn has_uppercase(pattern: &str, literal: bool) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' && !literal {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+// Case is ignored with a flag in the pattern itself, so two regexes match
+// the same exactly when their `as_str()` is the same.
+pub fn regex(pattern: &str, literal: bool, ignore_case: bool) -> Result<Regex, String> {
+    let mut text = if literal {
+        regex::escape(pattern)
+    } else {
+        pattern.to_string()
+    };
+    if ignore_case {
+        text.insert_str(0, "(?i)");
+    }
+    Regex::new(&text).map_err(|_| format!("Invalid pattern: {}", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smart_case() {
+        let options = Options::default();
+        assert!(options.ignores_case("todo"));
+        assert!(!options.ignores_case("TODO"));
+        assert!(options.ignores_case("\\Sdone\\W"));
+        let regex = options.regex("to+d").unwrap();
+        assert!(regex.is_match("* TOOD"));
+        let literal = Options {
+            literal: true,
+            ..options
+        };
+        assert!(literal.regex("a.b").unwrap().is_match("A.B"));
+        assert!(!literal.regex("a.b").unwrap().is_match("axb"));
+        assert!(!literal.ignores_case("\\S"));
+        let sensitive = Options {
+            ignore_case: false,
+            ..options
+        };
+        assert!(!sensitive.regex("todo").unwrap().is_match("TODO"));
+        assert_eq!(
+            options.regex("(").err(),
+            Some("Invalid pattern: (".to_string())
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::HighlightingOptions;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Definitions shipped with the binary. Files in the user directory with the
+// same `name` replace them.
+const BUILTIN: [&str; 7] = [
+    include_str!("../syntax/rust.syntax"),
+    include_str!("../syntax/python.syntax"),
+    include_str!("../syntax/shell.syntax"),
+    include_str!("../syntax/yaml.syntax"),
+    include_str!("../syntax/json.syntax"),
+    include_str!("../syntax/toml.syntax"),
+    include_str!("../syntax/sql.syntax"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Syntax {
+    pub name: String,
+    pub extensions: Vec<String>,
+    // Names used after `#+BEGIN_SRC`.
+    pub languages: Vec<String>,
+    pub options: HighlightingOptions,
+}
+
+fn words(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split_whitespace().map(ToString::to_string)
+}
+
+fn flag(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" => Ok(true),
+        "false" | "no" => Ok(false),
+        _ => Err(format!("expected true or false, found `{}`", value)),
+    }
+}
+
+impl Syntax {
+    // Parses `key = value` lines. List keys may be repeated to continue the
+    // list; lines starting with `#` are comments.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut name = None;
+        let mut extensions = Vec::new();
+        let mut languages = Vec::new();
+        let mut options = HighlightingOptions::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number.saturating_add(1), message);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `key = value`".to_string()))?;
+            let value = value.trim();
+            match key.trim() {
+                "name" => name = Some(value.to_string()),
+                "extensions" => extensions.extend(words(value)),
+                "languages" => languages.extend(words(value)),
+                "numbers" => options.numbers = flag(value).map_err(error)?,
+                "characters" => options.characters = flag(value).map_err(error)?,
+                "ignore_case" => options.ignore_case = flag(value).map_err(error)?,
+                "strings" => options.strings.extend(
+                    value
+                        .split_whitespace()
+                        .filter_map(|word| word.chars().next()),
+                ),
+                "line_comment" => options.line_comment = Some(value.to_string()),
+                "block_comment" => {
+                    let (open, close) = value.split_once(char::is_whitespace).ok_or_else(|| {
+                        error("expected an opening and a closing delimiter".to_string())
+                    })?;
+                    options.block_comment = Some((open.to_string(), close.trim().to_string()));
+                }
+                "primary_keywords" => options.primary_keywords.extend(words(value)),
+                "secondary_keywords" => options.secondary_keywords.extend(words(value)),
+                key => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+        Ok(Self {
+            name: name.ok_or_else(|| "missing `name`".to_string())?,
+            extensions,
+            languages,
+            options,
+        })
+    }
+}
+
+// `$XDG_CONFIG_HOME/orgonaut/syntax`, or `~/.config/orgonaut/syntax`.
+pub fn user_directory() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("orgonaut").join("syntax"))
+}
+
+fn load(directory: Option<&Path>) -> Vec<Syntax> {
+    let mut syntaxes: Vec<Syntax> = BUILTIN
+        .iter()
+        .filter_map(|text| Syntax::parse(text).ok())
+        .collect();
+    let mut files: Vec<PathBuf> = directory
+        .and_then(|directory| fs::read_dir(directory).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "syntax")
+        })
+        .collect();
+    files.sort();
+    // Broken user definitions are skipped rather than keeping the editor
+    // from starting.
+    for syntax in files
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|text| Syntax::parse(&text).ok())
+    {
+        match syntaxes
+            .iter_mut()
+            .find(|existing| existing.name == syntax.name)
+        {
+            Some(existing) => *existing = syntax,
+            None => syntaxes.push(syntax),
+        }
+    }
+    syntaxes
+}
+
+pub fn all() -> &'static [Syntax] {
+    static SYNTAXES: OnceLock<Vec<Syntax>> = OnceLock::new();
+    SYNTAXES.get_or_init(|| load(user_directory().as_deref()))
+}
+
+// Later (user) definitions take precedence over built-in ones.
+fn find(syntaxes: &[Syntax], predicate: impl Fn(&Syntax) -> bool) -> Option<&Syntax> {
+    syntaxes.iter().rev().find(|syntax| predicate(syntax))
+}
+
+pub fn for_file(file_name: &str) -> Option<&'static Syntax> {
+    let extension = Path::new(file_name).extension()?.to_str()?;
+    find(all(), |syntax| {
+        syntax
+            .extensions
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(extension))
+    })
+}
+
+pub fn for_language(language: &str) -> Option<&'static Syntax> {
+    find(all(), |syntax| {
+        syntax.name.eq_ignore_ascii_case(language)
+            || syntax
+                .languages
+                .iter()
+                .any(|candidate| candidate.eq_ignore_ascii_case(language))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let syntax = Syntax::parse(
+            "# comment\nname = SQL\nextensions = sql\nstrings = ' \"\nline_comment = --\n\
+             block_comment = /* */\nignore_case = true\nprimary_keywords = select from\n\
+             primary_keywords = where\n",
+        )
+        .unwrap();
+        assert_eq!(syntax.name, "SQL");
+        assert_eq!(syntax.options.strings, vec!['\'', '"']);
+        assert_eq!(syntax.options.line_comment.as_deref(), Some("--"));
+        assert_eq!(
+            syntax.options.block_comment,
+            Some(("/*".to_string(), "*/".to_string()))
+        );
+        assert!(syntax.options.ignore_case);
+        assert_eq!(
+            syntax.options.primary_keywords,
+            vec!["select", "from", "where"]
+        );
+        assert!(Syntax::parse("name = X\ncolour = red").is_err());
+        assert!(Syntax::parse("numbers = true").is_err());
+    }
+
+    #[test]
+    fn test_builtin_definitions() {
+        let syntaxes = load(None);
+        assert_eq!(syntaxes.len(), BUILTIN.len());
+        let rust = find(&syntaxes, |syntax| {
+            syntax.extensions.contains(&"rs".to_string())
+        })
+        .unwrap();
+        assert_eq!(rust.name, "Rust");
+        assert!(rust.options.primary_keywords.contains(&"fn".to_string()));
+        assert!(
+            rust.options
+                .secondary_keywords
+                .contains(&"usize".to_string())
+        );
+    }
+
+    #[test]
+    fn test_user_definitions() {
+        let directory =
+            std::env::temp_dir().join(format!("orgonaut-syntax-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("rust.syntax"),
+            "name = Rust\nextensions = rs\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("lua.syntax"),
+            "name = Lua\nextensions = lua\nline_comment = --\n",
+        )
+        .unwrap();
+        fs::write(directory.join("broken.syntax"), "no equals sign").unwrap();
+        let syntaxes = load(Some(&directory));
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(syntaxes.len(), BUILTIN.len().saturating_add(1));
+        let rust = syntaxes
+            .iter()
+            .find(|syntax| syntax.name == "Rust")
+            .unwrap();
+        assert!(rust.options.primary_keywords.is_empty());
+        assert!(syntaxes.iter().any(|syntax| syntax.name == "Lua"));
+    }
+}
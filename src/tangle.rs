@@ -0,0 +1,274 @@
+use crate::babel::{self, SourceBlock};
+use crate::org::OrgDocument;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub struct TangledFile {
+    pub path: PathBuf,
+    pub contents: String,
+    pub blocks: usize,
+    pub executable: bool,
+    pub mkdirp: bool,
+}
+
+// Noweb references are only expanded when a block asks for it.
+const NOWEB_VALUES: [&str; 4] = ["yes", "tangle", "no-export", "strip-export"];
+
+// Guards against blocks that (indirectly) reference themselves.
+const MAX_NOWEB_DEPTH: usize = 16;
+
+fn extension(language: &str) -> &str {
+    match language {
+        "sh" | "shell" | "bash" | "zsh" => "sh",
+        "python" | "python3" => "py",
+        "ruby" => "rb",
+        "perl" => "pl",
+        "js" | "javascript" | "node" => "js",
+        "emacs-lisp" | "elisp" => "el",
+        "rust" => "rs",
+        "yaml" => "yml",
+        language => language,
+    }
+}
+
+fn comment_prefix(language: &str) -> &'static str {
+    match language {
+        "emacs-lisp" | "elisp" | "lisp" | "scheme" | "clojure" => ";;",
+        "js" | "javascript" | "node" | "rust" | "c" | "cpp" | "java" | "go" | "css" => "//",
+        "sql" | "lua" | "haskell" => "--",
+        _ => "#",
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn is_yes(block: &SourceBlock, key: &str) -> bool {
+    block.arguments.get(key).is_some_and(|value| value == "yes")
+}
+
+// Expands `<<name>>` references to the bodies of blocks named `name` (via
+// `#+NAME:` or `:noweb-ref`). Text around a reference is repeated on every
+// line of the expansion, like Org does.
+fn expand(body: &str, blocks: &[SourceBlock], depth: usize) -> String {
+    let lines: Vec<String> = body
+        .lines()
+        .map(|line| {
+            let Some((prefix, rest)) = line.split_once("<<") else {
+                return line.to_string();
+            };
+            let Some((name, suffix)) = rest.split_once(">>") else {
+                return line.to_string();
+            };
+            let referenced: Vec<&SourceBlock> = blocks
+                .iter()
+                .filter(|block| {
+                    block.name.as_deref() == Some(name)
+                        || block.arguments.get("noweb-ref") == Some(name)
+                })
+                .collect();
+            if referenced.is_empty() || depth >= MAX_NOWEB_DEPTH {
+                return line.to_string();
+            }
+            let expansion: Vec<String> = referenced
+                .iter()
+                .map(|block| expand(&block.body, blocks, depth.saturating_add(1)))
+                .collect();
+            let expansion = expansion.join("\n");
+            let suffix = expand(suffix, blocks, depth.saturating_add(1));
+            expansion
+                .lines()
+                .map(|line| format!("{}{}{}", prefix, line, suffix))
+                .collect::<Vec<String>>()
+                .join("\n")
+        })
+        .collect();
+    lines.join("\n")
+}
+
+// Groups the blocks of `document` by their `:tangle` target. Relative targets
+// are resolved against the directory of `file_name`.
+pub fn files(document: &OrgDocument, file_name: &str) -> Vec<TangledFile> {
+    let source = Path::new(file_name);
+    let directory = source.parent().unwrap_or_else(|| Path::new(""));
+    let blocks = SourceBlock::all(document);
+    let mut files: Vec<TangledFile> = Vec::new();
+    let mut counters: Vec<(Option<String>, usize)> = Vec::new();
+    for block in &blocks {
+        let target = match block.arguments.get("tangle").map(unquote) {
+            None | Some("no" | "") => continue,
+            Some("yes") => source
+                .with_extension(extension(&block.language))
+                .to_string_lossy()
+                .into_owned(),
+            Some(target) => directory
+                .join(babel::expand_home(target))
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let path = PathBuf::from(target);
+        let mut body = if block
+            .arguments
+            .get("noweb")
+            .is_some_and(|value| NOWEB_VALUES.contains(&value))
+        {
+            expand(&block.body, &blocks, 0)
+        } else {
+            block.body.clone()
+        };
+        if block.arguments.get("comments") == Some("link") {
+            // Org numbers the blocks below each headline.
+            let number = match counters
+                .iter_mut()
+                .find(|(headline, _)| *headline == block.headline)
+            {
+                Some((_, count)) => {
+                    *count = count.saturating_add(1);
+                    *count
+                }
+                None => {
+                    counters.push((block.headline.clone(), 1));
+                    1
+                }
+            };
+            let prefix = comment_prefix(&block.language);
+            let headline = block.headline.as_deref().unwrap_or("No heading");
+            let link_file = source
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+            let link = if block.headline.is_some() {
+                format!("file:{}::*{}", link_file, headline)
+            } else {
+                format!("file:{}", link_file)
+            };
+            body = format!(
+                "{} [[{}][{}:{}]]\n{}\n{} {}:{} ends here",
+                prefix, link, headline, number, body, prefix, headline, number
+            );
+        }
+        let file = match files.iter_mut().position(|file| file.path == path) {
+            Some(index) => files.get_mut(index),
+            None => {
+                files.push(TangledFile {
+                    path,
+                    contents: String::new(),
+                    blocks: 0,
+                    executable: false,
+                    mkdirp: false,
+                });
+                files.last_mut()
+            }
+        };
+        let Some(file) = file else {
+            continue;
+        };
+        if let Some(shebang) = block.arguments.get("shebang") {
+            if file.contents.is_empty() {
+                file.contents = format!("{}\n", unquote(shebang));
+                file.executable = true;
+            }
+        }
+        if file.blocks > 0 {
+            file.contents.push('\n');
+        }
+        file.contents.push_str(&body);
+        file.contents.push('\n');
+        file.blocks = file.blocks.saturating_add(1);
+        file.mkdirp |= is_yes(block, "mkdirp");
+    }
+    files
+}
+
+pub fn write(file: &TangledFile) -> Result<(), String> {
+    let describe = |error: std::io::Error| format!("{}: {}", file.path.display(), error);
+    if file.mkdirp {
+        if let Some(parent) = file
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).map_err(describe)?;
+        }
+    }
+    fs::write(&file.path, &file.contents).map_err(describe)?;
+    #[cfg(unix)]
+    if file.executable {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(&file.path).map_err(describe)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        fs::set_permissions(&file.path, permissions).map_err(describe)?;
+    }
+    Ok(())
+}
+
+// Tangles `document` and returns a summary of what was written.
+pub fn tangle(document: &OrgDocument, file_name: &str) -> Result<String, String> {
+    let files = files(document, file_name);
+    for file in &files {
+        write(file)?;
+    }
+    let blocks: usize = files.iter().map(|file| file.blocks).sum();
+    Ok(format!(
+        "Tangled {} blocks into {} files",
+        blocks,
+        files.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tangle_targets() {
+        let document = OrgDocument::parse(
+            "#+PROPERTY: header-args:sh :tangle yes\n\
+             #+BEGIN_SRC sh :shebang \"#!/bin/sh\"\necho one\n#+END_SRC\n\
+             #+BEGIN_SRC sh\necho two\n#+END_SRC\n\
+             #+BEGIN_SRC python :tangle lib/a.py :mkdirp yes\nprint(1)\n#+END_SRC\n\
+             #+BEGIN_SRC python\nskipped\n#+END_SRC\n",
+        );
+        let files = files(&document, "dir/notes.org");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, PathBuf::from("dir/notes.sh"));
+        assert_eq!(files[0].contents, "#!/bin/sh\necho one\n\necho two\n");
+        assert!(files[0].executable);
+        assert_eq!(files[1].path, PathBuf::from("dir/lib/a.py"));
+        assert!(files[1].mkdirp);
+    }
+
+    #[test]
+    fn test_home_target() {
+        let document = OrgDocument::parse(
+            "#+PROPERTY: header-args :tangle ~/out.sh\n\
+             #+BEGIN_SRC sh\necho one\n#+END_SRC\n",
+        );
+        let files = files(&document, "dir/notes.org");
+        let expected = match std::env::var("HOME") {
+            Ok(home) => Path::new(&home).join("out.sh"),
+            Err(_) => PathBuf::from("dir/~/out.sh"),
+        };
+        assert_eq!(files[0].path, expected);
+    }
+
+    #[test]
+    fn test_noweb_and_comments() {
+        let document = OrgDocument::parse(
+            "* Config\n\
+             #+NAME: paths\n#+BEGIN_SRC sh\n/usr/bin\n/bin\n#+END_SRC\n\
+             #+BEGIN_SRC sh :tangle out.sh :noweb yes :comments link\nfor p in <<paths>>; do\n#+END_SRC\n",
+        );
+        let files = files(&document, "init.org");
+        assert_eq!(
+            files[0].contents,
+            "# [[file:init.org::*Config][Config:1]]\n\
+             for p in /usr/bin; do\nfor p in /bin; do\n\
+             # Config:1 ends here\n"
+        );
+    }
+}
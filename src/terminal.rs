@@ -1,9 +1,7 @@
-use crate::Position;
-use std::io::{self, stdout, Write};
-use termion::color;
-use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::{IntoRawMode, RawTerminal};
+use crate::backend::{self, Backend, Event, Waker};
+use crate::clipboard::Selection;
+use crate::screen::Screen;
+use std::time::Duration;
 
 pub struct Size {
     pub width: u16,
@@ -11,65 +9,79 @@ pub struct Size {
 }
 pub struct Terminal {
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+    backend: Box<dyn Backend>,
 }
 
 impl Terminal {
     pub fn default() -> Result<Self, std::io::Error> {
-        let size = termion::terminal_size()?;
+        Self::new(backend::from_env())
+    }
+    // Puts `backend` into raw mode until the terminal is dropped.
+    pub fn new(mut backend: Box<dyn Backend>) -> Result<Self, std::io::Error> {
+        let size = backend.size()?;
+        backend.enable_raw_mode()?;
         Ok(Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(2),
             },
-            _stdout: stdout().into_raw_mode()?,
+            backend,
         })
     }
     pub fn size(&self) -> &Size {
         &self.size
     }
-    pub fn clear_screen() {
-        print!("{}", termion::clear::All);
-    }
-
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn cursor_position(position: &Position) {
-        let Position { mut x, mut y } = position;
-        x = x.saturating_add(1);
-        y = y.saturating_add(1);
-        let x = x as u16;
-        let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
-    }
-    pub fn flush() -> Result<(), std::io::Error> {
-        io::stdout().flush()
+    // Clears the screen and leaves the cursor at the top left.
+    pub fn clear_screen(&mut self) -> Result<(), std::io::Error> {
+        self.backend.clear_all()?;
+        self.backend.move_cursor(0, 0)
     }
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
-            }
-        }
+    pub fn print(&mut self, text: &str) -> Result<(), std::io::Error> {
+        self.backend.print(text)
     }
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.backend.flush()
     }
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+    // Sends the changes to `screen` in one write.
+    pub fn draw(&mut self, screen: &mut Screen) -> Result<(), std::io::Error> {
+        screen.render(self.backend.as_mut())?;
+        self.backend.flush()
     }
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    // The next key press or resize, or `None` if there was none within
+    // `timeout`. The size is updated before a resize is returned.
+    pub fn poll_event(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Event>, std::io::Error> {
+        let event = self.backend.poll_event(timeout)?;
+        if let Some(Event::Resize(width, height)) = event {
+            self.size = Size {
+                width,
+                height: height.saturating_sub(2),
+            };
+        }
+        Ok(event)
     }
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    // For other threads to end a wait in `poll_event`.
+    pub fn waker(&self) -> Waker {
+        self.backend.waker()
     }
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    // Sent with the next flush.
+    pub fn copy_to_clipboard(
+        &mut self,
+        selection: Selection,
+        text: &str,
+    ) -> Result<(), std::io::Error> {
+        self.backend.copy_to_clipboard(selection, text)
     }
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
+    #[cfg(test)]
+    pub fn backend(&self) -> &dyn Backend {
+        self.backend.as_ref()
     }
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.backend.disable_raw_mode();
     }
 }
@@ -0,0 +1,386 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Time {
+    pub hour: u32,
+    pub minute: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeaterKind {
+    // `+1w`: shift by one interval.
+    Cumulative,
+    // `++1w`: shift until the date is in the future.
+    CatchUp,
+    // `.+1w`: shift from today.
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Repeater {
+    pub kind: RepeaterKind,
+    pub value: u32,
+    pub unit: Unit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timestamp {
+    pub active: bool,
+    pub date: Date,
+    pub start: Option<Time>,
+    pub end_date: Option<Date>,
+    pub end: Option<Time>,
+    pub repeater: Option<Repeater>,
+    pub raw: String,
+}
+
+impl Date {
+    fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.split('-');
+        let year = parts.next()?;
+        let month = parts.next()?;
+        let day = parts.next()?;
+        if parts.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return None;
+        }
+        let date = Self {
+            year: year.parse().ok()?,
+            month: month.parse().ok()?,
+            day: day.parse().ok()?,
+        };
+        if date.month == 0 || date.month > 12 || date.day == 0 || date.day > date.days_in_month() {
+            return None;
+        }
+        Some(date)
+    }
+    fn is_leap_year(self) -> bool {
+        (self.year % 4 == 0 && self.year % 100 != 0) || self.year % 400 == 0
+    }
+    pub fn days_in_month(self) -> u32 {
+        match self.month {
+            2 if self.is_leap_year() => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+    pub fn succ(self) -> Self {
+        if self.day < self.days_in_month() {
+            Self {
+                day: self.day.saturating_add(1),
+                ..self
+            }
+        } else if self.month < 12 {
+            Self {
+                month: self.month.saturating_add(1),
+                day: 1,
+                ..self
+            }
+        } else {
+            Self {
+                year: self.year.saturating_add(1),
+                month: 1,
+                day: 1,
+            }
+        }
+    }
+}
+
+// The UTC date and time of `date` at `time` in the local time zone.
+#[allow(unsafe_code)]
+pub fn local_to_utc(date: Date, time: Time) -> Option<(Date, Time)> {
+    // SAFETY: `mktime` only reads and normalizes the `tm` it is given, and
+    // an all-zero `tm` is a valid value to start from.
+    let seconds = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tm.tm_year = date.year.checked_sub(1900)?;
+        tm.tm_mon = libc::c_int::try_from(date.month).ok()?.checked_sub(1)?;
+        tm.tm_mday = libc::c_int::try_from(date.day).ok()?;
+        tm.tm_hour = libc::c_int::try_from(time.hour).ok()?;
+        tm.tm_min = libc::c_int::try_from(time.minute).ok()?;
+        // Whether daylight saving time applies is up to the time zone.
+        tm.tm_isdst = -1;
+        libc::mktime(&mut tm)
+    };
+    if seconds == -1 {
+        return None;
+    }
+    utc_from_epoch(i64::from(seconds))
+}
+
+// The UTC date and time `seconds` after the epoch, using Howard Hinnant's
+// days-to-civil algorithm.
+#[allow(clippy::integer_arithmetic)]
+fn utc_from_epoch(seconds: i64) -> Option<(Date, Time)> {
+    let days = seconds.div_euclid(86_400);
+    let second_of_day = seconds.rem_euclid(86_400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let date = Date {
+        year: i32::try_from(year).ok()?,
+        month: u32::try_from(month).ok()?,
+        day: u32::try_from(day).ok()?,
+    };
+    let time = Time {
+        hour: u32::try_from(second_of_day / 3600).ok()?,
+        minute: u32::try_from(second_of_day % 3600 / 60).ok()?,
+    };
+    Some((date, time))
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl Time {
+    // The current local time of day.
+    #[allow(unsafe_code)]
+    pub fn now() -> Option<Self> {
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        let seconds = libc::time_t::try_from(seconds).ok()?;
+        // SAFETY: `localtime_r` only writes to the `tm` it is given, and an
+        // all-zero `tm` is a valid value to start from.
+        let tm = unsafe {
+            let mut tm: libc::tm = std::mem::zeroed();
+            if libc::localtime_r(&seconds, &mut tm).is_null() {
+                return None;
+            }
+            tm
+        };
+        Some(Self {
+            hour: u32::try_from(tm.tm_hour).ok()?,
+            minute: u32::try_from(tm.tm_min).ok()?,
+        })
+    }
+    fn parse(text: &str) -> Option<Self> {
+        let (hour, minute) = text.split_once(':')?;
+        if hour.is_empty() || hour.len() > 2 || minute.len() != 2 {
+            return None;
+        }
+        let time = Self {
+            hour: hour.parse().ok()?,
+            minute: minute.parse().ok()?,
+        };
+        if time.hour > 23 || time.minute > 59 {
+            return None;
+        }
+        Some(time)
+    }
+}
+
+impl Repeater {
+    fn parse(text: &str) -> Option<Self> {
+        let (kind, rest) = if let Some(rest) = text.strip_prefix(".+") {
+            (RepeaterKind::Restart, rest)
+        } else if let Some(rest) = text.strip_prefix("++") {
+            (RepeaterKind::CatchUp, rest)
+        } else {
+            (RepeaterKind::Cumulative, text.strip_prefix('+')?)
+        };
+        let unit = match rest.chars().last()? {
+            'h' => Unit::Hour,
+            'd' => Unit::Day,
+            'w' => Unit::Week,
+            'm' => Unit::Month,
+            'y' => Unit::Year,
+            _ => return None,
+        };
+        let value = rest.get(..rest.len().saturating_sub(1))?.parse().ok()?;
+        Some(Self { kind, value, unit })
+    }
+}
+
+impl fmt::Display for Repeater {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            RepeaterKind::Cumulative => "+",
+            RepeaterKind::CatchUp => "++",
+            RepeaterKind::Restart => ".+",
+        };
+        let unit = match self.unit {
+            Unit::Hour => 'h',
+            Unit::Day => 'd',
+            Unit::Week => 'w',
+            Unit::Month => 'm',
+            Unit::Year => 'y',
+        };
+        write!(f, "{}{}{}", kind, self.value, unit)
+    }
+}
+
+impl Timestamp {
+    // Parses a timestamp (or timestamp range) at the start of `text`,
+    // returning it together with the number of bytes consumed.
+    pub fn parse(text: &str) -> Option<(Self, usize)> {
+        let (mut timestamp, mut length) = Self::parse_single(text)?;
+        if let Some(rest) = text.get(length..).and_then(|rest| rest.strip_prefix("--")) {
+            if let Some((end, end_length)) = Self::parse_single(rest) {
+                if end.active == timestamp.active {
+                    timestamp.end_date = Some(end.date);
+                    timestamp.end = end.start;
+                    length = length.saturating_add(2).saturating_add(end_length);
+                }
+            }
+        }
+        timestamp.raw = text.get(..length)?.to_string();
+        Some((timestamp, length))
+    }
+    fn parse_single(text: &str) -> Option<(Self, usize)> {
+        let (active, closing) = match text.chars().next()? {
+            '<' => (true, '>'),
+            '[' => (false, ']'),
+            _ => return None,
+        };
+        let end = text.find(closing)?;
+        let inner = text.get(1..end)?;
+        let mut words = inner.split_whitespace();
+        let mut timestamp = Self {
+            active,
+            date: Date::parse(words.next()?)?,
+            start: None,
+            end_date: None,
+            end: None,
+            repeater: None,
+            raw: String::new(),
+        };
+        for word in words {
+            if word.chars().all(char::is_alphabetic) {
+                continue;
+            }
+            if let Some(repeater) = Repeater::parse(word) {
+                timestamp.repeater = Some(repeater);
+            } else if word.starts_with('-') {
+                // Warning periods only matter for the agenda.
+                continue;
+            } else if let Some((start, end)) = word.split_once('-') {
+                timestamp.start = Some(Time::parse(start)?);
+                timestamp.end = Some(Time::parse(end)?);
+            } else {
+                timestamp.start = Some(Time::parse(word)?);
+            }
+        }
+        Some((timestamp, end.saturating_add(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let (timestamp, length) =
+            Timestamp::parse("<2024-05-01 Wed 10:00-11:30 +1w> rest").unwrap();
+        assert_eq!(length, 32);
+        assert!(timestamp.active);
+        assert_eq!(timestamp.date.to_string(), "2024-05-01");
+        assert_eq!(
+            timestamp.start,
+            Some(Time {
+                hour: 10,
+                minute: 0
+            })
+        );
+        assert_eq!(
+            timestamp.end,
+            Some(Time {
+                hour: 11,
+                minute: 30
+            })
+        );
+        assert_eq!(
+            timestamp.repeater,
+            Some(Repeater {
+                kind: RepeaterKind::Cumulative,
+                value: 1,
+                unit: Unit::Week
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let (timestamp, _) = Timestamp::parse("[2024-02-28 Wed]--[2024-03-01 Fri]").unwrap();
+        assert!(!timestamp.active);
+        assert_eq!(
+            timestamp.end_date.map(|d| d.to_string()).as_deref(),
+            Some("2024-03-01")
+        );
+        assert_eq!(timestamp.raw, "[2024-02-28 Wed]--[2024-03-01 Fri]");
+    }
+
+    #[test]
+    fn test_invalid_timestamp() {
+        assert!(Timestamp::parse("<2024-13-01>").is_none());
+        assert!(Timestamp::parse("<not a date>").is_none());
+        assert!(Timestamp::parse("<2024-05-01 Wed 24:00>").is_none());
+        assert!(Timestamp::parse("<2024-05-01 Wed 22:00-24:00>").is_none());
+    }
+
+    #[test]
+    fn test_utc_from_epoch() {
+        let utc = |seconds| {
+            let (date, time) = utc_from_epoch(seconds).unwrap();
+            format!("{} {}", date, time)
+        };
+        assert_eq!(utc(0), "1970-01-01 00:00");
+        assert_eq!(utc(1_714_554_000), "2024-05-01 09:00");
+        assert_eq!(utc(951_782_400), "2000-02-29 00:00");
+        assert_eq!(utc(-60), "1969-12-31 23:59");
+    }
+
+    #[test]
+    fn test_date_succ() {
+        let date = Date {
+            year: 2024,
+            month: 2,
+            day: 29,
+        };
+        assert_eq!(date.succ().to_string(), "2024-03-01");
+        let date = Date {
+            year: 2023,
+            month: 12,
+            day: 31,
+        };
+        assert_eq!(date.succ().to_string(), "2024-01-01");
+    }
+}
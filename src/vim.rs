@@ -0,0 +1,1795 @@
+use crate::Document;
+use crate::Position;
+use crate::Row;
+use crate::org::{ElementKind, Headline, ListItem, OrgDocument};
+use crate::register;
+use std::ops::Range;
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Motion {
+    Left,
+    Down,
+    Up,
+    Right,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    // `gg` and `G`; with a count both go to that line.
+    FirstLine,
+    LastLine,
+    FindForward(char),
+    TillForward(char),
+    FindBackward(char),
+    TillBackward(char),
+    MatchingBracket,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+    // `gu`, `gU` and `g~`, or `u`, `U` and `~` on a selection.
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Target {
+    Motion(Motion),
+    // The operator typed twice, as in `dd`: whole lines from the cursor's.
+    Lines,
+    // Text objects, like `iw` and `a(`.
+    Inner(Object),
+    Around(Object),
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Object {
+    Word,
+    Sentence,
+    Paragraph,
+    Quote(char),
+    Bracket(char, char),
+    // The Org subtree and element around the cursor, `r` and `e`.
+    Subtree,
+    Element,
+}
+
+// What `v`, `V` and `Ctrl-v` select.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Visual {
+    Characters,
+    Lines,
+    Block,
+}
+
+// Where insert mode starts for `i a A I o O`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Insert {
+    Before,
+    After,
+    LineEnd,
+    LineStart,
+    Below,
+    Above,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    Insert(Insert),
+    // `p` and `P`.
+    Put { before: bool },
+    // Starts visual mode, or switches or leaves it.
+    Visual(Visual),
+    // In visual mode: `o` moves the cursor to the other end of the
+    // selection, and operators act on the selection.
+    OtherEnd,
+    OperateOnSelection(Operator),
+    // `.` repeats the last change.
+    Repeat,
+    // `q{register}` starts recording a macro, and `@{register}` runs one;
+    // `@@` runs the last one again.
+    Record(char),
+    Replay(char),
+    // `:` opens the command line.
+    CommandLine,
+    // `/` and `?` search forward and backward; `n` searches again the same
+    // way and `N` the other way.
+    Search { backward: bool },
+    SearchNext { reverse: bool },
+    // `u`; `Ctrl-r` redoes.
+    Undo,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Command {
+    // `None` when no count was typed, which `G` tells apart from 1.
+    pub count: Option<usize>,
+    // The register named with `"`, before or after the count.
+    pub register: Option<char>,
+    pub action: Action,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Parse {
+    Complete(Command),
+    // The keys so far start a command.
+    Pending,
+    Invalid,
+}
+
+struct Input<'a> {
+    keys: std::slice::Iter<'a, Key>,
+}
+
+impl Input<'_> {
+    fn next(&mut self) -> Result<char, Parse> {
+        match self.keys.next() {
+            Some(Key::Char(c)) => Ok(*c),
+            Some(_) => Err(Parse::Invalid),
+            None => Err(Parse::Pending),
+        }
+    }
+    // An optional count and the key after it. A leading `0` is a motion.
+    fn counted(&mut self) -> Result<(Option<usize>, char), Parse> {
+        let mut count: Option<usize> = None;
+        let mut c = self.next()?;
+        while let Some(digit) = c.to_digit(10).filter(|digit| *digit > 0 || count.is_some()) {
+            count = Some(
+                count
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit as usize),
+            );
+            c = self.next()?;
+        }
+        Ok((count, c))
+    }
+    fn motion(&mut self, c: char) -> Result<Option<Motion>, Parse> {
+        let motion = match c {
+            'h' => Motion::Left,
+            'j' => Motion::Down,
+            'k' => Motion::Up,
+            'l' => Motion::Right,
+            'w' => Motion::WordForward,
+            'b' => Motion::WordBackward,
+            'e' => Motion::WordEnd,
+            '0' => Motion::LineStart,
+            '^' => Motion::FirstNonBlank,
+            '$' => Motion::LineEnd,
+            'G' => Motion::LastLine,
+            '%' => Motion::MatchingBracket,
+            'g' => match self.next()? {
+                'g' => Motion::FirstLine,
+                _ => return Err(Parse::Invalid),
+            },
+            'f' => Motion::FindForward(self.next()?),
+            't' => Motion::TillForward(self.next()?),
+            'F' => Motion::FindBackward(self.next()?),
+            'T' => Motion::TillBackward(self.next()?),
+            _ => return Ok(None),
+        };
+        Ok(Some(motion))
+    }
+}
+
+fn operator(c: char) -> Option<Operator> {
+    match c {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        '>' => Some(Operator::Indent),
+        '<' => Some(Operator::Outdent),
+        _ => None,
+    }
+}
+
+fn object(c: char) -> Option<Object> {
+    match c {
+        'w' => Some(Object::Word),
+        's' => Some(Object::Sentence),
+        'p' => Some(Object::Paragraph),
+        '"' | '\'' | '`' => Some(Object::Quote(c)),
+        '(' | ')' | 'b' => Some(Object::Bracket('(', ')')),
+        '[' | ']' => Some(Object::Bracket('[', ']')),
+        '{' | '}' | 'B' => Some(Object::Bracket('{', '}')),
+        '<' | '>' => Some(Object::Bracket('<', '>')),
+        'r' => Some(Object::Subtree),
+        'e' => Some(Object::Element),
+        _ => None,
+    }
+}
+
+// `2d3w` deletes six words.
+fn multiply(first: Option<usize>, second: Option<usize>) -> Option<usize> {
+    match (first, second) {
+        (None, None) => None,
+        _ => Some(first.unwrap_or(1).saturating_mul(second.unwrap_or(1))),
+    }
+}
+
+// The count and register before a command, and the key after them.
+fn prefix(input: &mut Input) -> Result<(Option<usize>, Option<char>, char), Parse> {
+    let (mut count, mut c) = input.counted()?;
+    let mut register = None;
+    if c == '"' {
+        let name = input.next()?;
+        if !register::is_valid(name) {
+            return Err(Parse::Invalid);
+        }
+        register = Some(name);
+        let (second, d) = input.counted()?;
+        count = multiply(count, second);
+        c = d;
+    }
+    Ok((count, register, c))
+}
+
+// Macros are recorded into the named registers, and can be run from any.
+fn macro_register(name: char, replay: bool) -> Result<char, Parse> {
+    if name.is_ascii_alphabetic() || (replay && (name == '@' || register::is_valid(name))) {
+        Ok(name)
+    } else {
+        Err(Parse::Invalid)
+    }
+}
+
+fn command(input: &mut Input) -> Result<Command, Parse> {
+    let (count, register, c) = prefix(input)?;
+    // The last key of an operator doubles it: `dd`, `guu`.
+    let (operator, double) = match c {
+        'g' => match input.next()? {
+            'u' => (Some(Operator::Lowercase), 'u'),
+            'U' => (Some(Operator::Uppercase), 'U'),
+            '~' => (Some(Operator::ToggleCase), '~'),
+            'g' => {
+                return Ok(Command {
+                    count,
+                    register,
+                    action: Action::Move(Motion::FirstLine),
+                });
+            }
+            _ => return Err(Parse::Invalid),
+        },
+        _ => (operator(c), c),
+    };
+    if let Some(operator) = operator {
+        let (second, d) = input.counted()?;
+        let target = match d {
+            _ if d == double => Target::Lines,
+            'i' => Target::Inner(object(input.next()?).ok_or(Parse::Invalid)?),
+            'a' => Target::Around(object(input.next()?).ok_or(Parse::Invalid)?),
+            _ => Target::Motion(input.motion(d)?.ok_or(Parse::Invalid)?),
+        };
+        return Ok(Command {
+            count: multiply(count, second),
+            register,
+            action: Action::Operate(operator, target),
+        });
+    }
+    let action = match input.motion(c)? {
+        Some(motion) => Action::Move(motion),
+        None => match c {
+            'i' => Action::Insert(Insert::Before),
+            'a' => Action::Insert(Insert::After),
+            'A' => Action::Insert(Insert::LineEnd),
+            'I' => Action::Insert(Insert::LineStart),
+            'o' => Action::Insert(Insert::Below),
+            'O' => Action::Insert(Insert::Above),
+            // `x` is `dl`.
+            'x' => Action::Operate(Operator::Delete, Target::Motion(Motion::Right)),
+            'p' => Action::Put { before: false },
+            'P' => Action::Put { before: true },
+            'v' => Action::Visual(Visual::Characters),
+            'V' => Action::Visual(Visual::Lines),
+            '.' => Action::Repeat,
+            'q' => Action::Record(macro_register(input.next()?, false)?),
+            '@' => Action::Replay(macro_register(input.next()?, true)?),
+            ':' => Action::CommandLine,
+            '/' => Action::Search { backward: false },
+            '?' => Action::Search { backward: true },
+            'n' => Action::SearchNext { reverse: false },
+            'N' => Action::SearchNext { reverse: true },
+            'u' => Action::Undo,
+            _ => return Err(Parse::Invalid),
+        },
+    };
+    Ok(Command {
+        count,
+        register,
+        action,
+    })
+}
+
+fn visual_command(input: &mut Input) -> Result<Command, Parse> {
+    let (count, register, c) = prefix(input)?;
+    let action = match input.motion(c)? {
+        Some(motion) => Action::Move(motion),
+        None => match c {
+            'v' => Action::Visual(Visual::Characters),
+            'V' => Action::Visual(Visual::Lines),
+            'o' => Action::OtherEnd,
+            'I' => Action::Insert(Insert::LineStart),
+            'A' => Action::Insert(Insert::LineEnd),
+            'd' | 'x' => Action::OperateOnSelection(Operator::Delete),
+            'c' | 's' => Action::OperateOnSelection(Operator::Change),
+            'y' => Action::OperateOnSelection(Operator::Yank),
+            '>' => Action::OperateOnSelection(Operator::Indent),
+            '<' => Action::OperateOnSelection(Operator::Outdent),
+            'u' => Action::OperateOnSelection(Operator::Lowercase),
+            'U' => Action::OperateOnSelection(Operator::Uppercase),
+            '~' => Action::OperateOnSelection(Operator::ToggleCase),
+            '@' => Action::Replay(macro_register(input.next()?, true)?),
+            ':' => Action::CommandLine,
+            '/' => Action::Search { backward: false },
+            '?' => Action::Search { backward: true },
+            'n' => Action::SearchNext { reverse: false },
+            'N' => Action::SearchNext { reverse: true },
+            _ => return Err(Parse::Invalid),
+        },
+    };
+    Ok(Command {
+        count,
+        register,
+        action,
+    })
+}
+
+// Parses the keys typed in command mode since the last complete command.
+pub fn parse(keys: &[Key]) -> Parse {
+    let mut input = Input { keys: keys.iter() };
+    match command(&mut input) {
+        Ok(command) => Parse::Complete(command),
+        Err(parse) => parse,
+    }
+}
+
+// Parses the keys typed in visual mode, where operators act on the
+// selection right away.
+pub fn parse_visual(keys: &[Key]) -> Parse {
+    let mut input = Input { keys: keys.iter() };
+    match visual_command(&mut input) {
+        Ok(command) => Parse::Complete(command),
+        Err(parse) => parse,
+    }
+}
+
+// How much of the text between the cursor and the end of a motion an
+// operator acts on.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Extent {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Destination {
+    pub position: Position,
+    pub extent: Extent,
+}
+
+// The text an operator acts on.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Span {
+    // From the first position up to the second.
+    Text(Position, Position),
+    Lines(Range<usize>),
+    // The display columns of a visual block, on each of the rows. Up to
+    // `usize::MAX` the block reaches the end of every row, as after `$`.
+    Block(Range<usize>, Range<usize>),
+}
+
+impl Span {
+    // The rows the span touches.
+    pub fn rows(&self) -> Range<usize> {
+        match self {
+            Self::Text(start, end) => start.y..end.y.saturating_add(1),
+            Self::Lines(rows) | Self::Block(rows, _) => rows.clone(),
+        }
+    }
+    // The graphemes of `row`, the row at `y`, within the span.
+    pub fn graphemes(&self, y: usize, row: &Row, tab_stop: usize) -> Range<usize> {
+        match self {
+            Self::Text(start, end) if (start.y..=end.y).contains(&y) => {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { row.len() };
+                from..to.max(from)
+            }
+            Self::Lines(rows) if rows.contains(&y) => 0..row.len(),
+            Self::Block(rows, columns) if rows.contains(&y) => {
+                let from = row.index(columns.start, tab_stop);
+                let to = if columns.end == usize::MAX {
+                    row.len()
+                } else {
+                    row.index(columns.end, tab_stop)
+                };
+                from..to.max(from)
+            }
+            _ => 0..0,
+        }
+    }
+}
+
+// The display columns the grapheme at `at` covers; a position past the end
+// of its row covers one column.
+fn columns(document: &Document, at: &Position, tab_stop: usize) -> Range<usize> {
+    document.row(at.y).map_or(0..1, |row| {
+        let start = row.column(at.x, tab_stop);
+        let end = row.column(at.x.saturating_add(1), tab_stop);
+        start..end.max(start.saturating_add(1))
+    })
+}
+
+// What is selected between `anchor`, where visual mode started, and the
+// cursor. Both ends are included. `to_end` stretches a block to the end of
+// every row.
+pub fn selection(
+    document: &Document,
+    visual: Visual,
+    anchor: &Position,
+    cursor: &Position,
+    tab_stop: usize,
+    to_end: bool,
+) -> Span {
+    let (start, end) = if before(cursor, anchor) {
+        (cursor, anchor)
+    } else {
+        (anchor, cursor)
+    };
+    let rows = start.y..end.y.saturating_add(1).min(document.len().max(1));
+    match visual {
+        Visual::Characters => {
+            // A selection reaching past the end of a row takes the line
+            // break.
+            let after = next(document, end).unwrap_or_else(|| end.clone());
+            Span::Text(start.clone(), after)
+        }
+        Visual::Lines => Span::Lines(rows),
+        Visual::Block => {
+            let first = columns(document, anchor, tab_stop);
+            let second = columns(document, cursor, tab_stop);
+            let right = if to_end {
+                usize::MAX
+            } else {
+                first.end.max(second.end)
+            };
+            Span::Block(rows, first.start.min(second.start)..right)
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Class {
+    Blank,
+    Word,
+    Punctuation,
+    // An empty row counts as a word of its own.
+    EmptyRow,
+}
+
+fn row_len(document: &Document, y: usize) -> usize {
+    document.row(y).map_or(0, crate::Row::len)
+}
+
+fn grapheme<'a>(document: &'a Document, at: &Position) -> &'a str {
+    document
+        .row(at.y)
+        .map_or("", |row| row.slice(at.x, at.x.saturating_add(1)))
+}
+
+// The end of each row is read as a line break.
+fn class(document: &Document, at: &Position) -> Class {
+    let len = row_len(document, at.y);
+    if len == 0 {
+        return Class::EmptyRow;
+    }
+    match grapheme(document, at).chars().next() {
+        None => Class::Blank,
+        Some(c) if c.is_whitespace() => Class::Blank,
+        Some(c) if c.is_alphanumeric() || c == '_' => Class::Word,
+        Some(_) => Class::Punctuation,
+    }
+}
+
+fn next(document: &Document, at: &Position) -> Option<Position> {
+    if at.x < row_len(document, at.y) {
+        Some(Position {
+            x: at.x.saturating_add(1),
+            y: at.y,
+        })
+    } else if at.y.saturating_add(1) < document.len() {
+        Some(Position {
+            x: 0,
+            y: at.y.saturating_add(1),
+        })
+    } else {
+        None
+    }
+}
+
+fn previous(document: &Document, at: &Position) -> Option<Position> {
+    if at.x > 0 {
+        Some(Position {
+            x: at.x.saturating_sub(1).min(row_len(document, at.y)),
+            y: at.y,
+        })
+    } else {
+        let y = at.y.checked_sub(1)?;
+        Some(Position {
+            x: row_len(document, y),
+            y,
+        })
+    }
+}
+
+// The index of the first non-blank grapheme of row `y`.
+pub fn first_non_blank(document: &Document, y: usize) -> usize {
+    document.row(y).map_or(0, |row| {
+        row.as_str()
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .count()
+    })
+}
+
+fn word_forward(document: &Document, from: &Position) -> Position {
+    let start = class(document, from);
+    let mut at = from.clone();
+    loop {
+        let Some(following) = next(document, &at) else {
+            return at;
+        };
+        at = following;
+        if start == Class::EmptyRow || class(document, &at) != start {
+            break;
+        }
+    }
+    while class(document, &at) == Class::Blank {
+        let Some(following) = next(document, &at) else {
+            break;
+        };
+        at = following;
+    }
+    at
+}
+
+fn word_end(document: &Document, from: &Position) -> Position {
+    let Some(mut at) = next(document, from) else {
+        return from.clone();
+    };
+    while matches!(class(document, &at), Class::Blank | Class::EmptyRow) {
+        let Some(following) = next(document, &at) else {
+            return at;
+        };
+        at = following;
+    }
+    let word = class(document, &at);
+    while let Some(following) = next(document, &at) {
+        if class(document, &following) != word {
+            break;
+        }
+        at = following;
+    }
+    at
+}
+
+fn word_backward(document: &Document, from: &Position) -> Position {
+    let Some(mut at) = previous(document, from) else {
+        return from.clone();
+    };
+    while class(document, &at) == Class::Blank {
+        let Some(preceding) = previous(document, &at) else {
+            return at;
+        };
+        at = preceding;
+    }
+    let word = class(document, &at);
+    if word == Class::EmptyRow {
+        return at;
+    }
+    while let Some(preceding) = previous(document, &at) {
+        if preceding.y != at.y || class(document, &preceding) != word {
+            break;
+        }
+        at = preceding;
+    }
+    at
+}
+
+// The `count`th occurrence of `target` after (or before) `x` in row `y`.
+fn find_in_row(
+    document: &Document,
+    y: usize,
+    x: usize,
+    target: char,
+    forward: bool,
+    count: usize,
+) -> Option<usize> {
+    let row = document.row(y)?;
+    let target = target.to_string();
+    let matches = |index: &usize| row.slice(*index, index.saturating_add(1)) == target;
+    if forward {
+        (x.saturating_add(1)..row.len())
+            .filter(matches)
+            .nth(count.saturating_sub(1))
+    } else {
+        (0..x.min(row.len()))
+            .rev()
+            .filter(matches)
+            .nth(count.saturating_sub(1))
+    }
+}
+
+fn matching_bracket(document: &Document, from: &Position) -> Option<Position> {
+    const PAIRS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+    let row = document.row(from.y)?;
+    let (x, open, close, forward) = (from.x..row.len()).find_map(|x| {
+        let grapheme = row.slice(x, x.saturating_add(1));
+        PAIRS.iter().find_map(|(open, close)| {
+            if grapheme == *open {
+                Some((x, *open, *close, true))
+            } else if grapheme == *close {
+                Some((x, *open, *close, false))
+            } else {
+                None
+            }
+        })
+    })?;
+    match_bracket(document, Position { x, y: from.y }, open, close, forward)
+}
+
+// The bracket matching the `open` or `close` bracket at `at`.
+fn match_bracket(
+    document: &Document,
+    at: Position,
+    open: &str,
+    close: &str,
+    forward: bool,
+) -> Option<Position> {
+    let mut at = at;
+    let mut depth: usize = 0;
+    loop {
+        let grapheme = grapheme(document, &at);
+        if grapheme == open {
+            depth = if forward {
+                depth.saturating_add(1)
+            } else {
+                depth.saturating_sub(1)
+            };
+        } else if grapheme == close {
+            depth = if forward {
+                depth.saturating_sub(1)
+            } else {
+                depth.saturating_add(1)
+            };
+        }
+        if depth == 0 {
+            return Some(at);
+        }
+        at = if forward {
+            next(document, &at)?
+        } else {
+            previous(document, &at)?
+        };
+    }
+}
+
+// Where `motion` repeated `count` times goes from `from`, or `None` if it
+// cannot, like `f` without a match.
+pub fn destination(
+    document: &Document,
+    from: &Position,
+    motion: Motion,
+    count: Option<usize>,
+) -> Option<Destination> {
+    let times = count.unwrap_or(1).max(1);
+    let last_row = document.len().saturating_sub(1);
+    let y = from.y;
+    let at = |x: usize, y: usize| Position { x, y };
+    let (position, extent) = match motion {
+        Motion::Left => (at(from.x.saturating_sub(times), y), Extent::Exclusive),
+        Motion::Right => (
+            at(from.x.saturating_add(times).min(row_len(document, y)), y),
+            Extent::Exclusive,
+        ),
+        Motion::Down => (
+            at(from.x, y.saturating_add(times).min(last_row)),
+            Extent::Linewise,
+        ),
+        Motion::Up => (at(from.x, y.saturating_sub(times)), Extent::Linewise),
+        Motion::WordForward | Motion::WordBackward | Motion::WordEnd => {
+            let mut position = from.clone();
+            for _ in 0..times {
+                position = match motion {
+                    Motion::WordForward => word_forward(document, &position),
+                    Motion::WordBackward => word_backward(document, &position),
+                    _ => word_end(document, &position),
+                };
+            }
+            let extent = if motion == Motion::WordEnd {
+                Extent::Inclusive
+            } else {
+                Extent::Exclusive
+            };
+            (position, extent)
+        }
+        Motion::LineStart => (at(0, y), Extent::Exclusive),
+        Motion::FirstNonBlank => (at(first_non_blank(document, y), y), Extent::Exclusive),
+        Motion::LineEnd => {
+            let y = y.saturating_add(times.saturating_sub(1)).min(last_row);
+            (
+                at(row_len(document, y).saturating_sub(1), y),
+                Extent::Inclusive,
+            )
+        }
+        Motion::FirstLine | Motion::LastLine => {
+            let y = match (motion, count) {
+                (_, Some(line)) => line.saturating_sub(1).min(last_row),
+                (Motion::FirstLine, None) => 0,
+                _ => last_row,
+            };
+            (at(first_non_blank(document, y), y), Extent::Linewise)
+        }
+        Motion::FindForward(c) => (
+            at(find_in_row(document, y, from.x, c, true, times)?, y),
+            Extent::Inclusive,
+        ),
+        Motion::TillForward(c) => (
+            at(
+                find_in_row(document, y, from.x, c, true, times)?.saturating_sub(1),
+                y,
+            ),
+            Extent::Inclusive,
+        ),
+        Motion::FindBackward(c) => (
+            at(find_in_row(document, y, from.x, c, false, times)?, y),
+            Extent::Exclusive,
+        ),
+        Motion::TillBackward(c) => (
+            at(
+                find_in_row(document, y, from.x, c, false, times)?.saturating_add(1),
+                y,
+            ),
+            Extent::Exclusive,
+        ),
+        Motion::MatchingBracket => (matching_bracket(document, from)?, Extent::Inclusive),
+    };
+    Some(Destination { position, extent })
+}
+
+fn before(first: &Position, second: &Position) -> bool {
+    (first.y, first.x) < (second.y, second.x)
+}
+
+// What `operator` applied to `target` from the cursor at `from` acts on.
+pub fn span(
+    document: &Document,
+    from: &Position,
+    operator: Operator,
+    target: Target,
+    count: Option<usize>,
+) -> Option<Span> {
+    if document.is_empty() {
+        return None;
+    }
+    let motion = match target {
+        Target::Lines => {
+            let end = from
+                .y
+                .saturating_add(count.unwrap_or(1).max(1))
+                .min(document.len());
+            return Some(Span::Lines(from.y..end));
+        }
+        Target::Inner(object) => return object_span(document, from, object, false, count),
+        Target::Around(object) => return object_span(document, from, object, true, count),
+        // `cw` on a word changes up to its end, like `ce`.
+        Target::Motion(Motion::WordForward)
+            if operator == Operator::Change && class(document, from) != Class::Blank =>
+        {
+            Motion::WordEnd
+        }
+        Target::Motion(motion) => motion,
+    };
+    let destination = destination(document, from, motion, count)?;
+    let (start, mut end) = if before(&destination.position, from) {
+        (destination.position, from.clone())
+    } else {
+        (from.clone(), destination.position)
+    };
+    match destination.extent {
+        Extent::Linewise => return Some(Span::Lines(start.y..end.y.saturating_add(1))),
+        Extent::Inclusive => end.x = end.x.saturating_add(1).min(row_len(document, end.y)),
+        Extent::Exclusive => {
+            // An exclusive motion to the start of a later row stops at the
+            // end of the row before, so `dw` on a row's last word keeps the
+            // line break. `w` also skips the indentation of the next row, and
+            // then stops at the end of the row of the last word moved over.
+            let word = motion == Motion::WordForward;
+            if end.y > start.y && (end.x == 0 || (word && blanks_before(document, &end))) {
+                end.y = if word {
+                    (start.y.saturating_add(1)..end.y)
+                        .rev()
+                        .find(|y| has_word(document, *y))
+                        .unwrap_or(start.y)
+                } else {
+                    end.y.saturating_sub(1)
+                };
+                end.x = row_len(document, end.y);
+            }
+        }
+    }
+    // Nothing to operate on, except that `c` still starts inserting.
+    if start == end && operator != Operator::Change {
+        return None;
+    }
+    Some(Span::Text(start, end))
+}
+
+// Whether `w` stops in row `y`: at a non-blank, or at an empty row.
+fn has_word(document: &Document, y: usize) -> bool {
+    document
+        .row(y)
+        .is_some_and(|row| row.is_empty() || !row.as_str().chars().all(char::is_whitespace))
+}
+
+// Whether only blanks come before `position` in its row.
+fn blanks_before(document: &Document, position: &Position) -> bool {
+    document
+        .row(position.y)
+        .is_some_and(|row| row.slice(0, position.x).chars().all(char::is_whitespace))
+}
+
+// The runs of equal items around `index` that a text object covers: `count`
+// runs for an inner object, and for an around object each with the blank
+// run after it, or before the first if none follows.
+fn runs<T: PartialEq + Copy>(
+    items: &[T],
+    index: usize,
+    blank: T,
+    around: bool,
+    count: usize,
+) -> Option<Range<usize>> {
+    let kind = *items.get(index)?;
+    let run_end = |start: usize| {
+        let kind = items.get(start).copied();
+        let mut end = start;
+        while end < items.len() && items.get(end).copied() == kind {
+            end = end.saturating_add(1);
+        }
+        end
+    };
+    let mut start = index;
+    while start > 0 && items.get(start.saturating_sub(1)) == Some(&kind) {
+        start = start.saturating_sub(1);
+    }
+    let mut end = start;
+    let mut trailing = false;
+    for _ in 0..count.max(1) {
+        let Some(&run) = items.get(end) else {
+            break;
+        };
+        end = run_end(end);
+        trailing = false;
+        if around {
+            match items.get(end) {
+                Some(next) if run != blank && *next == blank => {
+                    end = run_end(end);
+                    trailing = true;
+                }
+                Some(_) if run == blank => end = run_end(end),
+                _ => (),
+            }
+        }
+    }
+    if around && kind != blank && !trailing {
+        while start > 0 && items.get(start.saturating_sub(1)) == Some(&blank) {
+            start = start.saturating_sub(1);
+        }
+    }
+    Some(start..end)
+}
+
+fn word_object(document: &Document, from: &Position, around: bool, count: usize) -> Option<Span> {
+    let len = row_len(document, from.y);
+    let classes: Vec<Class> = (0..len)
+        .map(|x| class(document, &Position { x, y: from.y }))
+        .collect();
+    let x = from.x.min(len.checked_sub(1)?);
+    let range = runs(&classes, x, Class::Blank, around, count)?;
+    Some(Span::Text(
+        Position {
+            x: range.start,
+            y: from.y,
+        },
+        Position {
+            x: range.end,
+            y: from.y,
+        },
+    ))
+}
+
+fn is_blank_row(document: &Document, y: usize) -> bool {
+    document
+        .row(y)
+        .is_none_or(|row| row.as_str().trim().is_empty())
+}
+
+fn paragraph_object(
+    document: &Document,
+    from: &Position,
+    around: bool,
+    count: usize,
+) -> Option<Span> {
+    let blank: Vec<bool> = (0..document.len())
+        .map(|y| is_blank_row(document, y))
+        .collect();
+    runs(&blank, from.y, true, around, count).map(Span::Lines)
+}
+
+// A `.`, `!` or `?` followed by a blank or the end of the row.
+fn ends_sentence(document: &Document, at: &Position) -> bool {
+    matches!(grapheme(document, at), "." | "!" | "?")
+        && class(
+            document,
+            &Position {
+                x: at.x.saturating_add(1),
+                y: at.y,
+            },
+        ) == Class::Blank
+}
+
+// Skips blanks, including line breaks, within a paragraph.
+fn skip_blanks(document: &Document, from: Position) -> Position {
+    let mut at = from;
+    while class(document, &at) == Class::Blank {
+        match next(document, &at) {
+            Some(following) if class(document, &following) != Class::EmptyRow => at = following,
+            _ => break,
+        }
+    }
+    at
+}
+
+// Sentences end after `.`, `!` or `?` and at the end of a paragraph.
+fn sentence_object(
+    document: &Document,
+    from: &Position,
+    around: bool,
+    count: usize,
+) -> Option<Span> {
+    if class(document, from) == Class::EmptyRow {
+        return None;
+    }
+    let mut start = from.clone();
+    while let Some(preceding) = previous(document, &start) {
+        if class(document, &preceding) == Class::EmptyRow || ends_sentence(document, &preceding) {
+            break;
+        }
+        start = preceding;
+    }
+    let start = skip_blanks(document, start);
+    let mut end = start.clone();
+    for index in 0..count.max(1) {
+        if index > 0 {
+            end = skip_blanks(document, end);
+        }
+        loop {
+            if ends_sentence(document, &end) {
+                end.x = end.x.saturating_add(1);
+                break;
+            }
+            match next(document, &end) {
+                Some(following) if class(document, &following) != Class::EmptyRow => {
+                    end = following
+                }
+                _ => break,
+            }
+        }
+    }
+    let mut start = start;
+    if around {
+        let after = skip_blanks(document, end.clone());
+        if after != end && class(document, &after) != Class::Blank {
+            end = after;
+        } else {
+            while start.x > 0
+                && class(
+                    document,
+                    &Position {
+                        x: start.x.saturating_sub(1),
+                        y: start.y,
+                    },
+                ) == Class::Blank
+            {
+                start.x = start.x.saturating_sub(1);
+            }
+        }
+    }
+    Some(Span::Text(start, end))
+}
+
+// Quotes pair up from the start of the row; backslash-escaped ones are
+// skipped.
+fn quote_object(document: &Document, from: &Position, quote: char, around: bool) -> Option<Span> {
+    let row = document.row(from.y)?;
+    let graphemes: Vec<&str> = row.as_str().graphemes(true).collect();
+    let quote = quote.to_string();
+    let quotes: Vec<usize> = (0..graphemes.len())
+        .filter(|x| {
+            graphemes.get(*x) == Some(&quote.as_str())
+                && x.checked_sub(1).and_then(|x| graphemes.get(x)) != Some(&"\\")
+        })
+        .collect();
+    let pairs: Vec<(usize, usize)> = quotes
+        .chunks(2)
+        .filter_map(|pair| Some((*pair.first()?, *pair.get(1)?)))
+        .collect();
+    let (open, close) = pairs
+        .iter()
+        .find(|(open, close)| *open <= from.x && from.x <= *close)
+        .or_else(|| pairs.iter().find(|(open, _)| *open > from.x))
+        .copied()?;
+    let is_blank = |x: usize| graphemes.get(x).is_some_and(|g| g.trim().is_empty());
+    let (mut start, mut end) = if around {
+        (open, close.saturating_add(1))
+    } else {
+        (open.saturating_add(1), close)
+    };
+    if around {
+        let mut trailing = end;
+        while is_blank(trailing) {
+            trailing = trailing.saturating_add(1);
+        }
+        if trailing > end {
+            end = trailing;
+        } else {
+            while start > 0 && is_blank(start.saturating_sub(1)) {
+                start = start.saturating_sub(1);
+            }
+        }
+    }
+    Some(Span::Text(
+        Position {
+            x: start,
+            y: from.y,
+        },
+        Position { x: end, y: from.y },
+    ))
+}
+
+// The `open` bracket `count` levels out from `from`.
+fn enclosing_bracket(
+    document: &Document,
+    from: &Position,
+    open: &str,
+    close: &str,
+    count: usize,
+) -> Option<Position> {
+    let mut at = from.clone();
+    // A closing bracket under the cursor belongs to the pair it closes.
+    if grapheme(document, &at) == close {
+        at = previous(document, &at)?;
+    }
+    let mut depth: usize = 0;
+    let mut levels = count.max(1);
+    loop {
+        let grapheme = grapheme(document, &at);
+        if grapheme == close {
+            depth = depth.saturating_add(1);
+        } else if grapheme == open {
+            if depth == 0 {
+                levels = levels.saturating_sub(1);
+                if levels == 0 {
+                    return Some(at);
+                }
+            } else {
+                depth = depth.saturating_sub(1);
+            }
+        }
+        at = previous(document, &at)?;
+    }
+}
+
+fn bracket_object(
+    document: &Document,
+    from: &Position,
+    open: char,
+    close: char,
+    around: bool,
+    count: usize,
+) -> Option<Span> {
+    let (open, close) = (open.to_string(), close.to_string());
+    let start = enclosing_bracket(document, from, &open, &close, count)?;
+    let end = match_bracket(document, start.clone(), &open, &close, true)?;
+    if around {
+        return Some(Span::Text(
+            start,
+            Position {
+                x: end.x.saturating_add(1),
+                y: end.y,
+            },
+        ));
+    }
+    // Brackets on rows of their own, as around a block of code, enclose the
+    // rows between them.
+    if start.x.saturating_add(1) == row_len(document, start.y)
+        && end.y > start.y
+        && first_non_blank(document, end.y) == end.x
+    {
+        return Some(Span::Lines(start.y.saturating_add(1)..end.y));
+    }
+    Some(Span::Text(
+        Position {
+            x: start.x.saturating_add(1),
+            y: start.y,
+        },
+        end,
+    ))
+}
+
+// The headlines whose subtrees contain row `y`, outermost first.
+fn enclosing_headlines(headlines: &[Headline], y: usize) -> Vec<&Headline> {
+    let mut chain = Vec::new();
+    let mut level = headlines;
+    while let Some(headline) = level.iter().find(|headline| headline.range.contains(&y)) {
+        chain.push(headline);
+        level = &headline.children;
+    }
+    chain
+}
+
+// With a count, the subtree that many levels out. The inner subtree is
+// everything below the headline.
+fn subtree_object(
+    document: &Document,
+    from: &Position,
+    around: bool,
+    count: usize,
+) -> Option<Span> {
+    let org = OrgDocument::parse(&document.text());
+    let chain = enclosing_headlines(&org.headlines, from.y);
+    let range = chain
+        .iter()
+        .rev()
+        .nth(count.saturating_sub(1))?
+        .range
+        .clone();
+    if around {
+        Some(Span::Lines(range))
+    } else {
+        let body = range.start.saturating_add(1)..range.end;
+        (!body.is_empty()).then_some(Span::Lines(body))
+    }
+}
+
+// The innermost list item containing row `y`.
+fn list_item(items: &[ListItem], y: usize) -> Option<&ListItem> {
+    let item = items.iter().find(|item| item.range.contains(&y))?;
+    list_item(&item.children, y).or(Some(item))
+}
+
+// A table cell is the text between two `|`s, and without its padding when
+// inner.
+fn table_cell(document: &Document, from: &Position, around: bool) -> Option<Span> {
+    let row = document.row(from.y)?;
+    let graphemes: Vec<&str> = row.as_str().graphemes(true).collect();
+    let pipes: Vec<usize> = (0..graphemes.len())
+        .filter(|x| graphemes.get(*x) == Some(&"|"))
+        .collect();
+    let mut left = pipes.iter().rev().find(|pipe| **pipe <= from.x).copied()?;
+    let mut right = pipes
+        .iter()
+        .find(|pipe| **pipe > left)
+        .copied()
+        .unwrap_or(graphemes.len());
+    // On the closing `|`, the cell before it.
+    if right == graphemes.len() && left.saturating_add(1) >= graphemes.len() {
+        right = left;
+        left = pipes.iter().rev().find(|pipe| **pipe < right).copied()?;
+    }
+    let mut start = left.saturating_add(1);
+    let mut end = right;
+    if !around {
+        let is_blank = |x: usize| graphemes.get(x).is_some_and(|g| g.trim().is_empty());
+        while start < end && is_blank(start) {
+            start = start.saturating_add(1);
+        }
+        while end > start && is_blank(end.saturating_sub(1)) {
+            end = end.saturating_sub(1);
+        }
+    }
+    Some(Span::Text(
+        Position {
+            x: start,
+            y: from.y,
+        },
+        Position { x: end, y: from.y },
+    ))
+}
+
+// The Org element under the cursor: a table cell, the body of a block, a
+// list item or a whole paragraph or other element. Around paragraphs take
+// in the blank rows after them, like `ap`.
+fn element_object(document: &Document, from: &Position, around: bool) -> Option<Span> {
+    let org = OrgDocument::parse(&document.text());
+    let section = enclosing_headlines(&org.headlines, from.y)
+        .last()
+        .map_or(&org.section, |headline| &headline.section);
+    let element = section
+        .iter()
+        .find(|element| element.range.contains(&from.y))?;
+    let range = element.range.clone();
+    match &element.kind {
+        ElementKind::Table(_) => table_cell(document, from, around),
+        ElementKind::Block(_) if !around => {
+            let body = range.start.saturating_add(1)..range.end.saturating_sub(1);
+            (!body.is_empty()).then_some(Span::Lines(body))
+        }
+        ElementKind::List(list) => {
+            let item = list_item(&list.items, from.y)?;
+            if around {
+                let mut end = item.range.end;
+                while end > item.range.start && is_blank_row(document, end.saturating_sub(1)) {
+                    end = end.saturating_sub(1);
+                }
+                return Some(Span::Lines(item.range.start..end));
+            }
+            let first = item.lines.first()?;
+            let last = item.lines.last()?;
+            Some(Span::Text(
+                Position {
+                    x: first.column,
+                    y: first.line,
+                },
+                Position {
+                    x: row_len(document, last.line),
+                    y: last.line,
+                },
+            ))
+        }
+        _ if around => {
+            let mut end = range.end;
+            while end < document.len() && is_blank_row(document, end) {
+                end = end.saturating_add(1);
+            }
+            Some(Span::Lines(range.start..end))
+        }
+        _ => Some(Span::Lines(range)),
+    }
+}
+
+fn object_span(
+    document: &Document,
+    from: &Position,
+    object: Object,
+    around: bool,
+    count: Option<usize>,
+) -> Option<Span> {
+    let count = count.unwrap_or(1).max(1);
+    match object {
+        Object::Word => word_object(document, from, around, count),
+        Object::Sentence => sentence_object(document, from, around, count),
+        Object::Paragraph => paragraph_object(document, from, around, count),
+        Object::Quote(quote) => quote_object(document, from, quote, around),
+        Object::Bracket(open, close) => bracket_object(document, from, open, close, around, count),
+        Object::Subtree => subtree_object(document, from, around, count),
+        Object::Element => element_object(document, from, around),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileType;
+
+    fn keys(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    fn complete(text: &str) -> Command {
+        match parse(&keys(text)) {
+            Parse::Complete(command) => command,
+            other => panic!("{} parsed as {:?}", text, other),
+        }
+    }
+
+    fn document(lines: &[&str]) -> Document {
+        let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        Document::from_lines(&lines, FileType::default())
+    }
+
+    fn text(document: &Document, span: &Span) -> String {
+        match span {
+            Span::Text(start, end) => document.text_between(start, end),
+            Span::Lines(rows) => document.lines()[rows.clone()].join("\n"),
+            Span::Block(rows, _) => rows
+                .clone()
+                .filter_map(|y| {
+                    let row = document.row(y)?;
+                    let selected = span.graphemes(y, row, 8);
+                    Some(row.slice(selected.start, selected.end))
+                })
+                .collect::<Vec<&str>>()
+                .join("\n"),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            complete("3dw"),
+            Command {
+                count: Some(3),
+                register: None,
+                action: Action::Operate(Operator::Delete, Target::Motion(Motion::WordForward)),
+            }
+        );
+        assert_eq!(complete("2d3w").count, Some(6));
+        assert_eq!(
+            complete("5j"),
+            Command {
+                count: Some(5),
+                register: None,
+                action: Action::Move(Motion::Down),
+            }
+        );
+        assert_eq!(
+            complete("yy").action,
+            Action::Operate(Operator::Yank, Target::Lines)
+        );
+        assert_eq!(
+            complete(">>").action,
+            Action::Operate(Operator::Indent, Target::Lines)
+        );
+        assert_eq!(complete("10G").count, Some(10));
+        assert_eq!(complete("0").action, Action::Move(Motion::LineStart));
+        assert_eq!(
+            complete("ct)").action,
+            Action::Operate(Operator::Change, Target::Motion(Motion::TillForward(')')))
+        );
+        assert_eq!(complete("gg").action, Action::Move(Motion::FirstLine));
+        assert_eq!(complete("A").action, Action::Insert(Insert::LineEnd));
+        assert_eq!(
+            complete("2dar"),
+            Command {
+                count: Some(2),
+                register: None,
+                action: Action::Operate(Operator::Delete, Target::Around(Object::Subtree)),
+            }
+        );
+        assert_eq!(
+            complete("ciB").action,
+            Action::Operate(Operator::Change, Target::Inner(Object::Bracket('{', '}')))
+        );
+        assert_eq!(
+            complete("ya\"").action,
+            Action::Operate(Operator::Yank, Target::Around(Object::Quote('"')))
+        );
+        assert_eq!(
+            complete("\"a3yy"),
+            Command {
+                count: Some(3),
+                register: Some('a'),
+                action: Action::Operate(Operator::Yank, Target::Lines),
+            }
+        );
+        assert_eq!(complete("2\"A3dw").count, Some(6));
+        assert_eq!(complete("3\"_x").register, Some('_'));
+        assert_eq!(complete("P").action, Action::Put { before: true });
+        assert_eq!(
+            complete("guu").action,
+            Action::Operate(Operator::Lowercase, Target::Lines)
+        );
+        assert_eq!(
+            complete("gUiw").action,
+            Action::Operate(Operator::Uppercase, Target::Inner(Object::Word))
+        );
+        assert_eq!(
+            complete("g~~").action,
+            Action::Operate(Operator::ToggleCase, Target::Lines)
+        );
+        assert_eq!(complete("V").action, Action::Visual(Visual::Lines));
+        assert_eq!(complete("3.").count, Some(3));
+        assert_eq!(complete("qA").action, Action::Record('A'));
+        assert_eq!(complete("2@@").action, Action::Replay('@'));
+        assert_eq!(complete("3:").count, Some(3));
+        assert_eq!(complete("?").action, Action::Search { backward: true });
+        assert_eq!(complete("2N").action, Action::SearchNext { reverse: true });
+        assert_eq!(complete("u").action, Action::Undo);
+        assert_eq!(parse(&keys("q_")), Parse::Invalid);
+        assert_eq!(parse(&keys("@")), Parse::Pending);
+        for pending in ["", "3", "d", "d2", "g", "f", "dt", "di", "ca", "\"", "\"a2"] {
+            assert_eq!(parse(&keys(pending)), Parse::Pending, "{}", pending);
+        }
+        for invalid in ["Q", "dq", "gx", "3dz", "diz", "\"%p", "\"\"\"p"] {
+            assert_eq!(parse(&keys(invalid)), Parse::Invalid, "{}", invalid);
+        }
+        assert_eq!(parse(&[Key::Char('d'), Key::Esc]), Parse::Invalid);
+    }
+
+    #[test]
+    fn test_parse_visual() {
+        let visual = |text: &str| match parse_visual(&keys(text)) {
+            Parse::Complete(command) => command,
+            other => panic!("{} parsed as {:?}", text, other),
+        };
+        assert_eq!(visual("3j").action, Action::Move(Motion::Down));
+        assert_eq!(
+            visual("\"ay"),
+            Command {
+                count: None,
+                register: Some('a'),
+                action: Action::OperateOnSelection(Operator::Yank),
+            }
+        );
+        assert_eq!(visual("2>").count, Some(2));
+        assert_eq!(
+            visual("~").action,
+            Action::OperateOnSelection(Operator::ToggleCase)
+        );
+        assert_eq!(visual("I").action, Action::Insert(Insert::LineStart));
+        assert_eq!(visual("o").action, Action::OtherEnd);
+        assert_eq!(visual(":").action, Action::CommandLine);
+        assert_eq!(parse_visual(&keys("i")), Parse::Invalid);
+        assert_eq!(parse_visual(&keys("g")), Parse::Pending);
+    }
+
+    #[test]
+    fn test_selections() {
+        let document = document(&["one two", "", "th\tree", "x"]);
+        let at = |x, y| Position { x, y };
+        let select = |visual, anchor: Position, cursor: Position, to_end| {
+            let span = selection(&document, visual, &anchor, &cursor, 8, to_end);
+            text(&document, &span)
+        };
+        assert_eq!(
+            select(Visual::Characters, at(4, 0), at(1, 0), false),
+            "ne t"
+        );
+        // The cursor past the end of the empty row takes its line break.
+        assert_eq!(
+            select(Visual::Characters, at(4, 0), at(0, 1), false),
+            "two\n\n"
+        );
+        assert_eq!(
+            select(Visual::Lines, at(3, 2), at(0, 1), false),
+            "\nth\tree"
+        );
+        // Blocks go by display column: the tab covers columns 2 to 7.
+        assert_eq!(
+            select(Visual::Block, at(1, 0), at(2, 2), false),
+            "ne two\n\nh\t"
+        );
+        assert_eq!(select(Visual::Block, at(0, 2), at(3, 3), false), "th\nx");
+        assert_eq!(
+            select(Visual::Block, at(4, 0), at(4, 2), true),
+            "two\n\n\tree"
+        );
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let document = document(&["foo bar.baz  qux", "", "  last"]);
+        let go = |x, y, motion, count| {
+            destination(&document, &Position { x, y }, motion, count)
+                .unwrap()
+                .position
+        };
+        let at = |x, y| Position { x, y };
+        assert_eq!(go(0, 0, Motion::WordForward, None), at(4, 0));
+        assert_eq!(go(4, 0, Motion::WordForward, None), at(7, 0));
+        assert_eq!(go(4, 0, Motion::WordForward, Some(3)), at(13, 0));
+        // An empty row stops `w`, and leading blanks are skipped.
+        assert_eq!(go(13, 0, Motion::WordForward, None), at(0, 1));
+        assert_eq!(go(0, 1, Motion::WordForward, None), at(2, 2));
+        assert_eq!(go(0, 0, Motion::WordEnd, None), at(2, 0));
+        assert_eq!(go(2, 0, Motion::WordEnd, None), at(6, 0));
+        assert_eq!(go(13, 0, Motion::WordEnd, None), at(15, 0));
+        assert_eq!(go(15, 0, Motion::WordEnd, None), at(5, 2));
+        assert_eq!(go(8, 0, Motion::WordBackward, None), at(7, 0));
+        assert_eq!(go(2, 2, Motion::WordBackward, None), at(0, 1));
+        assert_eq!(go(0, 1, Motion::WordBackward, None), at(13, 0));
+    }
+
+    #[test]
+    fn test_line_and_find_motions() {
+        let document = document(&["  if (a(b)) {", "    x", "}"]);
+        let go = |x, y, motion, count| {
+            destination(&document, &Position { x, y }, motion, count).map(|d| d.position)
+        };
+        let at = |x, y| Some(Position { x, y });
+        assert_eq!(go(5, 0, Motion::FirstNonBlank, None), at(2, 0));
+        assert_eq!(go(5, 0, Motion::LineEnd, None), at(12, 0));
+        assert_eq!(go(5, 0, Motion::LineEnd, Some(2)), at(4, 1));
+        assert_eq!(go(0, 0, Motion::FindForward('('), Some(2)), at(7, 0));
+        assert_eq!(go(0, 0, Motion::TillForward('('), None), at(4, 0));
+        assert_eq!(go(9, 0, Motion::FindBackward('('), None), at(7, 0));
+        assert_eq!(go(9, 0, Motion::TillBackward('('), None), at(8, 0));
+        assert_eq!(go(0, 0, Motion::FindForward('z'), None), None);
+        assert_eq!(go(0, 0, Motion::MatchingBracket, None), at(10, 0));
+        assert_eq!(go(10, 0, Motion::MatchingBracket, None), at(5, 0));
+        assert_eq!(go(12, 0, Motion::MatchingBracket, None), at(0, 2));
+        assert_eq!(go(0, 2, Motion::MatchingBracket, None), at(12, 0));
+        assert_eq!(go(3, 1, Motion::FirstLine, None), at(2, 0));
+        assert_eq!(go(3, 0, Motion::LastLine, None), at(0, 2));
+        assert_eq!(go(3, 0, Motion::LastLine, Some(2)), at(4, 1));
+    }
+
+    #[test]
+    fn test_spans() {
+        let document = document(&["one two three", "four five", "six"]);
+        let span = |x, y, operator, target, count| {
+            let span = super::span(&document, &Position { x, y }, operator, target, count).unwrap();
+            text(&document, &span)
+        };
+        let word = Target::Motion(Motion::WordForward);
+        assert_eq!(span(0, 0, Operator::Delete, word, None), "one ");
+        assert_eq!(span(0, 0, Operator::Delete, word, Some(2)), "one two ");
+        // `cw` leaves the blank after the word, and `dw` the line break.
+        assert_eq!(span(0, 0, Operator::Change, word, None), "one");
+        assert_eq!(span(8, 0, Operator::Delete, word, None), "three");
+        assert_eq!(
+            span(
+                4,
+                0,
+                Operator::Delete,
+                Target::Motion(Motion::WordBackward),
+                None
+            ),
+            "one "
+        );
+        assert_eq!(
+            span(
+                4,
+                0,
+                Operator::Delete,
+                Target::Motion(Motion::LineEnd),
+                None
+            ),
+            "two three"
+        );
+        assert_eq!(
+            span(
+                4,
+                0,
+                Operator::Yank,
+                Target::Motion(Motion::FindForward('e')),
+                None
+            ),
+            "two thre"
+        );
+        assert_eq!(
+            span(1, 1, Operator::Delete, Target::Lines, Some(2)),
+            "four five\nsix"
+        );
+        assert_eq!(
+            span(1, 1, Operator::Delete, Target::Lines, Some(9)),
+            "four five\nsix"
+        );
+        assert_eq!(
+            span(1, 1, Operator::Delete, Target::Motion(Motion::Up), None),
+            "one two three\nfour five"
+        );
+        assert_eq!(
+            span(
+                0,
+                2,
+                Operator::Delete,
+                Target::Motion(Motion::Right),
+                Some(5)
+            ),
+            "six"
+        );
+    }
+
+    #[test]
+    fn test_word_spans_across_rows() {
+        let document = document(&["a foo", "  bar", "", "   ", "baz"]);
+        let word = Target::Motion(Motion::WordForward);
+        let span = |x, y, operator, target, count| {
+            let span = super::span(&document, &Position { x, y }, operator, target, count)?;
+            Some(text(&document, &span))
+        };
+        let text = |text: &str| Some(text.to_string());
+        // With an indented next row, `dw` on the last word still keeps the
+        // line break and the indentation.
+        assert_eq!(span(2, 0, Operator::Delete, word, None), text("foo"));
+        assert_eq!(span(2, 0, Operator::Yank, word, None), text("foo"));
+        assert_eq!(span(2, 1, Operator::Delete, word, None), text("bar"));
+        // An empty row is a word; a row of blanks is not.
+        assert_eq!(span(2, 1, Operator::Delete, word, Some(2)), text("bar\n"));
+        assert_eq!(span(0, 2, Operator::Delete, word, None), None);
+        // Spans without text are nothing to operate on, except for `c`.
+        let line_start = Target::Motion(Motion::LineStart);
+        assert_eq!(span(0, 0, Operator::Delete, line_start, None), None);
+        let right = Target::Motion(Motion::Right);
+        assert_eq!(span(0, 2, Operator::Delete, right, None), None);
+        assert_eq!(span(0, 2, Operator::Change, right, None), text(""));
+    }
+
+    #[test]
+    fn test_text_objects() {
+        let document = document(&[
+            "  say(\"hi there\", (a, b)) now. Next one!",
+            "Still next.",
+            "",
+            "",
+            "fn f() {",
+            "    body",
+            "}",
+        ]);
+        let object = |x, y, target, count| {
+            let span = super::span(
+                &document,
+                &Position { x, y },
+                Operator::Delete,
+                target,
+                count,
+            )?;
+            Some(text(&document, &span))
+        };
+        let text = |x, y, target| object(x, y, target, None).unwrap();
+        assert_eq!(text(2, 0, Target::Inner(Object::Word)), "say");
+        // Without blanks after it, the blanks before the word are taken.
+        assert_eq!(text(2, 0, Target::Around(Object::Word)), "  say");
+        assert_eq!(text(12, 0, Target::Around(Object::Word)), " there");
+        assert_eq!(text(8, 0, Target::Around(Object::Word)), "hi ");
+        assert_eq!(text(0, 0, Target::Around(Object::Word)), "  say");
+        assert_eq!(
+            object(2, 0, Target::Inner(Object::Word), Some(3)).unwrap(),
+            "say(\"hi"
+        );
+        assert_eq!(text(9, 0, Target::Inner(Object::Quote('"'))), "hi there");
+        assert_eq!(
+            text(2, 0, Target::Around(Object::Quote('"'))),
+            "\"hi there\""
+        );
+        assert_eq!(
+            text(21, 0, Target::Inner(Object::Bracket('(', ')'))),
+            "a, b"
+        );
+        assert_eq!(
+            text(21, 0, Target::Around(Object::Bracket('(', ')'))),
+            "(a, b)"
+        );
+        assert_eq!(
+            object(21, 0, Target::Inner(Object::Bracket('(', ')')), Some(2)).unwrap(),
+            "\"hi there\", (a, b)"
+        );
+        assert_eq!(
+            text(5, 0, Target::Inner(Object::Bracket('(', ')'))),
+            "\"hi there\", (a, b)"
+        );
+        assert_eq!(
+            object(0, 1, Target::Inner(Object::Bracket('(', ')')), None),
+            None
+        );
+        assert_eq!(
+            text(2, 5, Target::Inner(Object::Bracket('{', '}'))),
+            "    body"
+        );
+        assert_eq!(
+            text(2, 5, Target::Around(Object::Bracket('{', '}'))),
+            "{\n    body\n}"
+        );
+        assert_eq!(
+            text(4, 0, Target::Inner(Object::Sentence)),
+            "say(\"hi there\", (a, b)) now."
+        );
+        assert_eq!(
+            text(4, 0, Target::Around(Object::Sentence)),
+            "say(\"hi there\", (a, b)) now. "
+        );
+        assert_eq!(text(36, 0, Target::Inner(Object::Sentence)), "Next one!");
+        assert_eq!(text(3, 1, Target::Inner(Object::Sentence)), "Still next.");
+        assert_eq!(
+            text(3, 1, Target::Inner(Object::Paragraph)),
+            document.lines()[0..2].join("\n")
+        );
+        assert_eq!(
+            text(3, 1, Target::Around(Object::Paragraph)),
+            document.lines()[0..4].join("\n")
+        );
+        assert_eq!(
+            text(0, 6, Target::Around(Object::Paragraph)),
+            document.lines()[2..7].join("\n")
+        );
+    }
+
+    #[test]
+    fn test_org_objects() {
+        let document = document(&[
+            "* One",
+            "Text of one.",
+            "** Two",
+            "| a | bcd |",
+            "- item one",
+            "  continued",
+            "  - nested",
+            "#+BEGIN_SRC sh",
+            "echo hi",
+            "#+END_SRC",
+            "* Three",
+        ]);
+        let text = |x, y, target, count| {
+            let span = super::span(
+                &document,
+                &Position { x, y },
+                Operator::Delete,
+                target,
+                count,
+            )?;
+            Some(text(&document, &span))
+        };
+        let lines = |range: Range<usize>| Some(document.lines()[range].join("\n"));
+        assert_eq!(
+            text(0, 1, Target::Around(Object::Subtree), None),
+            lines(0..10)
+        );
+        assert_eq!(
+            text(0, 8, Target::Around(Object::Subtree), None),
+            lines(2..10)
+        );
+        assert_eq!(
+            text(0, 8, Target::Around(Object::Subtree), Some(2)),
+            lines(0..10)
+        );
+        assert_eq!(
+            text(0, 8, Target::Inner(Object::Subtree), None),
+            lines(3..10)
+        );
+        assert_eq!(text(0, 10, Target::Inner(Object::Subtree), None), None);
+        assert_eq!(
+            text(0, 3, Target::Inner(Object::Element), None),
+            Some("a".to_string())
+        );
+        assert_eq!(
+            text(7, 3, Target::Inner(Object::Element), None),
+            Some("bcd".to_string())
+        );
+        assert_eq!(
+            text(10, 3, Target::Inner(Object::Element), None),
+            Some("bcd".to_string())
+        );
+        assert_eq!(
+            text(7, 3, Target::Around(Object::Element), None),
+            Some(" bcd ".to_string())
+        );
+        assert_eq!(
+            text(3, 5, Target::Inner(Object::Element), None),
+            Some("item one\n  continued".to_string())
+        );
+        assert_eq!(
+            text(3, 5, Target::Around(Object::Element), None),
+            lines(4..7)
+        );
+        assert_eq!(
+            text(4, 6, Target::Inner(Object::Element), None),
+            Some("nested".to_string())
+        );
+        assert_eq!(
+            text(0, 7, Target::Inner(Object::Element), None),
+            lines(8..9)
+        );
+        assert_eq!(
+            text(0, 8, Target::Around(Object::Element), None),
+            lines(7..10)
+        );
+        assert_eq!(
+            text(0, 1, Target::Inner(Object::Element), None),
+            lines(1..2)
+        );
+    }
+}